@@ -19,6 +19,7 @@
 const int MAX_REQUESTS_PER_MINUTE = 120;
 const int MAX_RESPONSE_SIZE = 50 * 1024 * 1024;
 const int REQUEST_TIMEOUT_MS = 60000;
+const int MAX_REQUEST_SIZE = 64 * 1024 * 1024;
 
 class Base64 {
 public:
@@ -88,27 +89,107 @@ public:
 
 class HTTPFetcher {
 public:
-    struct FetchResult { std::string content, content_type, error; long status_code; bool success; };
-    FetchResult fetch(const std::string& url) {
+    struct FetchResult { std::string content, content_type, final_url, raw_headers, error; long status_code; bool success; };
+    struct FetchRequest { std::string url, method = "GET", content_type, body; std::vector<std::pair<std::string, std::string>> headers; bool follow_redirects = true; };
+    FetchResult fetch(const std::string& url) { FetchRequest request; request.url = url; return fetch(request); }
+    FetchResult fetch(const FetchRequest& request) {
         FetchResult result; result.success = false; result.status_code = 0; result.content_type = "text/html";
+        const std::string& url = request.url;
         HINTERNET hInternet = InternetOpenA("Mozilla/5.0", INTERNET_OPEN_TYPE_DIRECT, NULL, NULL, 0);
         if (!hInternet) { result.error = "Failed to initialize"; return result; }
         DWORD timeout = REQUEST_TIMEOUT_MS;
         InternetSetOptionA(hInternet, INTERNET_OPTION_CONNECT_TIMEOUT, &timeout, sizeof(timeout));
-        HINTERNET hConnect = InternetOpenUrlA(hInternet, url.c_str(), NULL, 0, INTERNET_FLAG_RELOAD | INTERNET_FLAG_NO_CACHE_WRITE, 0);
-        if (!hConnect) { result.error = "Failed to open URL"; InternetCloseHandle(hInternet); return result; }
+        std::string extraHeaders;
+        for (const auto& header : request.headers) extraHeaders += header.first + ": " + header.second + "\r\n";
+        if (!request.content_type.empty()) extraHeaders += "Content-Type: " + request.content_type + "\r\n";
+        HINTERNET hSession = NULL;
+        HINTERNET hConnect = NULL;
+        // Cookies and credentials come only from the browser's headers; WinINet's
+        // own store would mix sites together and keep them outside the profile.
+        DWORD baseFlags = INTERNET_FLAG_RELOAD | INTERNET_FLAG_NO_CACHE_WRITE | INTERNET_FLAG_NO_COOKIES | INTERNET_FLAG_NO_AUTH |
+                          (request.follow_redirects ? 0 : INTERNET_FLAG_NO_AUTO_REDIRECT);
+        if (request.method == "GET" && request.body.empty()) {
+            hConnect = InternetOpenUrlA(hInternet, url.c_str(), extraHeaders.empty() ? NULL : extraHeaders.c_str(),
+                                        (DWORD)extraHeaders.length(), baseFlags, 0);
+        } else {
+            // Anything but a plain GET needs the connect/open/send sequence.
+            URL_COMPONENTSA parts{}; parts.dwStructSize = sizeof(parts);
+            char host[INTERNET_MAX_HOST_NAME_LENGTH] = {0}; char path[INTERNET_MAX_URL_LENGTH] = {0}; char extra[INTERNET_MAX_URL_LENGTH] = {0};
+            parts.lpszHostName = host; parts.dwHostNameLength = sizeof(host);
+            parts.lpszUrlPath = path; parts.dwUrlPathLength = sizeof(path);
+            parts.lpszExtraInfo = extra; parts.dwExtraInfoLength = sizeof(extra);
+            if (InternetCrackUrlA(url.c_str(), 0, 0, &parts)) {
+                hSession = InternetConnectA(hInternet, host, parts.nPort, NULL, NULL, INTERNET_SERVICE_HTTP, 0, 0);
+                std::string target = std::string(path) + extra;
+                // The fragment is for the page, not the server.
+                target = target.substr(0, target.find('#'));
+                DWORD flags = baseFlags | (parts.nScheme == INTERNET_SCHEME_HTTPS ? INTERNET_FLAG_SECURE : 0);
+                if (hSession) hConnect = HttpOpenRequestA(hSession, request.method.c_str(), target.empty() ? "/" : target.c_str(), NULL, NULL, NULL, flags, 0);
+                if (hConnect && !HttpSendRequestA(hConnect, extraHeaders.empty() ? NULL : extraHeaders.c_str(), (DWORD)extraHeaders.length(),
+                                                  (LPVOID)request.body.data(), (DWORD)request.body.length())) {
+                    InternetCloseHandle(hConnect); hConnect = NULL;
+                }
+            }
+        }
+        if (!hConnect) { result.error = "Failed to open URL"; if (hSession) InternetCloseHandle(hSession); InternetCloseHandle(hInternet); return result; }
         char contentType[256]; DWORD contentTypeSize = sizeof(contentType);
         if (HttpQueryInfoA(hConnect, HTTP_QUERY_CONTENT_TYPE, contentType, &contentTypeSize, NULL)) result.content_type = std::string(contentType);
+        DWORD statusCode = 0; DWORD statusSize = sizeof(statusCode);
+        result.status_code = HttpQueryInfoA(hConnect, HTTP_QUERY_STATUS_CODE | HTTP_QUERY_FLAG_NUMBER, &statusCode, &statusSize, NULL) ? statusCode : 200;
+        // Unless asked not to, WinINet follows redirects itself; the handle
+        // knows where it ended up.
+        char finalUrl[INTERNET_MAX_URL_LENGTH] = {0}; DWORD finalUrlSize = sizeof(finalUrl);
+        result.final_url = InternetQueryOptionA(hConnect, INTERNET_OPTION_URL, finalUrl, &finalUrlSize) ? std::string(finalUrl) : url;
+        DWORD headersSize = 0;
+        HttpQueryInfoA(hConnect, HTTP_QUERY_RAW_HEADERS_CRLF, NULL, &headersSize, NULL);
+        if (headersSize > 0) {
+            std::string headers(headersSize, '\0');
+            if (HttpQueryInfoA(hConnect, HTTP_QUERY_RAW_HEADERS_CRLF, &headers[0], &headersSize, NULL)) { headers.resize(headersSize); result.raw_headers = headers; }
+        }
         char buffer[8192]; DWORD bytesRead; std::string response; size_t total_size = 0;
         while (InternetReadFile(hConnect, buffer, sizeof(buffer), &bytesRead) && bytesRead > 0) {
             total_size += bytesRead;
-            if (total_size > MAX_RESPONSE_SIZE) { result.error = "Response too large"; InternetCloseHandle(hConnect); InternetCloseHandle(hInternet); return result; }
+            if (total_size > MAX_RESPONSE_SIZE) { result.error = "Response too large"; InternetCloseHandle(hConnect); if (hSession) InternetCloseHandle(hSession); InternetCloseHandle(hInternet); return result; }
             response.append(buffer, bytesRead);
         }
-        result.content = response; result.status_code = 200; result.success = true;
-        InternetCloseHandle(hConnect); InternetCloseHandle(hInternet);
+        result.content = response; result.success = true;
+        InternetCloseHandle(hConnect); if (hSession) InternetCloseHandle(hSession); InternetCloseHandle(hInternet);
         return result;
     }
+
+    // Sends the upstream response to `client` as it arrives: status, type,
+    // length and Content-Range, then the body byte for byte. `range` is passed
+    // on so a paused download can carry on where it stopped. Returns false,
+    // with nothing sent, if the upstream could not be reached.
+    bool stream(const std::string& url, const std::string& range, SOCKET client, std::string& error) {
+        HINTERNET hInternet = InternetOpenA("Mozilla/5.0", INTERNET_OPEN_TYPE_DIRECT, NULL, NULL, 0);
+        if (!hInternet) { error = "Failed to initialize"; return false; }
+        DWORD timeout = REQUEST_TIMEOUT_MS;
+        InternetSetOptionA(hInternet, INTERNET_OPTION_CONNECT_TIMEOUT, &timeout, sizeof(timeout));
+        std::string extraHeaders = range.empty() ? "" : "Range: " + range + "\r\n";
+        HINTERNET hConnect = InternetOpenUrlA(hInternet, url.c_str(), extraHeaders.empty() ? NULL : extraHeaders.c_str(), (DWORD)extraHeaders.length(),
+                                              INTERNET_FLAG_RELOAD | INTERNET_FLAG_NO_CACHE_WRITE | INTERNET_FLAG_NO_COOKIES | INTERNET_FLAG_NO_AUTH, 0);
+        if (!hConnect) { error = "Failed to open URL"; InternetCloseHandle(hInternet); return false; }
+        DWORD statusCode = 200; DWORD statusSize = sizeof(statusCode);
+        HttpQueryInfoA(hConnect, HTTP_QUERY_STATUS_CODE | HTTP_QUERY_FLAG_NUMBER, &statusCode, &statusSize, NULL);
+        std::stringstream head;
+        head << "HTTP/1.1 " << statusCode << (statusCode == 206 ? " Partial Content" : statusCode < 400 ? " OK" : " Error") << "\r\n";
+        for (DWORD query : { (DWORD)HTTP_QUERY_CONTENT_TYPE, (DWORD)HTTP_QUERY_CONTENT_LENGTH, (DWORD)HTTP_QUERY_CONTENT_RANGE }) {
+            char value[256]; DWORD valueSize = sizeof(value);
+            if (!HttpQueryInfoA(hConnect, query, value, &valueSize, NULL)) continue;
+            const char* name = query == HTTP_QUERY_CONTENT_TYPE ? "Content-Type" : query == HTTP_QUERY_CONTENT_LENGTH ? "Content-Length" : "Content-Range";
+            head << name << ": " << std::string(value, valueSize) << "\r\n";
+        }
+        head << "Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n";
+        std::string headers = head.str();
+        bool open = send(client, headers.c_str(), (int)headers.length(), 0) != SOCKET_ERROR;
+        char buffer[65536]; DWORD bytesRead;
+        while (open && InternetReadFile(hConnect, buffer, sizeof(buffer), &bytesRead) && bytesRead > 0) {
+            open = send(client, buffer, (int)bytesRead, 0) != SOCKET_ERROR;
+        }
+        InternetCloseHandle(hConnect); InternetCloseHandle(hInternet);
+        return true;
+    }
 };
 
 class URLRewriter {
@@ -193,17 +274,160 @@ private:
             else if (c == '\t') result += "\\t";
             else if (c == '\b') result += "\\b";
             else if (c == '\f') result += "\\f";
+            else if ((unsigned char)c < 0x20) { char buf[8]; snprintf(buf, sizeof(buf), "\\u%04x", c); result += buf; }
             else result += c;
         }
         return result;
     }
 
+    static std::string charsetOf(const std::string& contentType) {
+        std::string lower = contentType;
+        std::transform(lower.begin(), lower.end(), lower.begin(), ::tolower);
+        size_t pos = lower.find("charset=");
+        if (pos == std::string::npos) return "";
+        std::string charset = contentType.substr(pos + 8);
+        charset = charset.substr(0, charset.find(';'));
+        charset.erase(std::remove(charset.begin(), charset.end(), '"'), charset.end());
+        charset.erase(std::remove(charset.begin(), charset.end(), ' '), charset.end());
+        return charset;
+    }
+
+    // Raw "Name: value" lines as a JSON array of pairs; repeated headers such
+    // as Set-Cookie keep every value.
+    std::string headersJSON(const std::string& raw) {
+        std::stringstream json;
+        json << "[";
+        bool first = true;
+        size_t start = raw.find("\r\n");
+        while (start != std::string::npos) {
+            start += 2;
+            size_t end = raw.find("\r\n", start);
+            std::string line = raw.substr(start, end == std::string::npos ? std::string::npos : end - start);
+            size_t colon = line.find(':');
+            if (colon != std::string::npos) {
+                std::string value = line.substr(colon + 1);
+                value.erase(0, value.find_first_not_of(' '));
+                json << (first ? "" : ",") << "[\"" << escapeJSON(line.substr(0, colon)) << "\",\"" << escapeJSON(value) << "\"]";
+                first = false;
+            }
+            start = end;
+        }
+        json << "]";
+        return json.str();
+    }
+
+    // The value of a header in the raw request, or "" if it has none.
+    static std::string requestHeader(const std::string& request, const std::string& name) {
+        size_t header_end = request.find("\r\n\r\n");
+        std::string lower = request.substr(0, header_end);
+        std::transform(lower.begin(), lower.end(), lower.begin(), ::tolower);
+        size_t pos = lower.find("\r\n" + name + ":");
+        if (pos == std::string::npos) return "";
+        pos += name.length() + 3;
+        size_t end = lower.find("\r\n", pos);
+        std::string value = request.substr(pos, (end == std::string::npos ? lower.length() : end) - pos);
+        value.erase(0, value.find_first_not_of(' '));
+        value.erase(value.find_last_not_of(' ') + 1);
+        return value;
+    }
+
+    // Reads a JSON string starting at the opening quote at `pos`, leaving
+    // `pos` just past the closing one.
+    static bool readJSONString(const std::string& json, size_t& pos, std::string& out) {
+        if (pos >= json.length() || json[pos] != '"') return false;
+        out.clear();
+        for (pos++; pos < json.length(); pos++) {
+            char c = json[pos];
+            if (c == '"') { pos++; return true; }
+            if (c != '\\' || ++pos >= json.length()) { out += c; continue; }
+            switch (json[pos]) {
+                case 'n': out += '\n'; break;
+                case 'r': out += '\r'; break;
+                case 't': out += '\t'; break;
+                case 'b': out += '\b'; break;
+                case 'f': out += '\f'; break;
+                case 'u': {
+                    if (pos + 4 >= json.length()) return false;
+                    unsigned int code = std::stoul(json.substr(pos + 1, 4), nullptr, 16);
+                    pos += 4;
+                    if (code < 0x80) out += (char)code;
+                    else if (code < 0x800) { out += (char)(0xC0 | (code >> 6)); out += (char)(0x80 | (code & 0x3F)); }
+                    else { out += (char)(0xE0 | (code >> 12)); out += (char)(0x80 | ((code >> 6) & 0x3F)); out += (char)(0x80 | (code & 0x3F)); }
+                    break;
+                }
+                default: out += json[pos];
+            }
+        }
+        return false;
+    }
+
+    static size_t skipSpace(const std::string& json, size_t pos) {
+        while (pos < json.length() && isspace((unsigned char)json[pos])) pos++;
+        return pos;
+    }
+
+    // The value of a top-level string field, or `fallback` when it is missing.
+    static std::string jsonField(const std::string& json, const std::string& name, const std::string& fallback = "") {
+        size_t pos = json.find("\"" + name + "\"");
+        if (pos == std::string::npos) return fallback;
+        pos = skipSpace(json, pos + name.length() + 2);
+        if (pos >= json.length() || json[pos] != ':') return fallback;
+        pos = skipSpace(json, pos + 1);
+        std::string value;
+        return readJSONString(json, pos, value) ? value : fallback;
+    }
+
+    // `"headers": [["Name", "value"], ...]` as name/value pairs.
+    static std::vector<std::pair<std::string, std::string>> jsonHeaders(const std::string& json) {
+        std::vector<std::pair<std::string, std::string>> headers;
+        size_t pos = json.find("\"headers\"");
+        if (pos == std::string::npos) return headers;
+        pos = json.find('[', pos);
+        if (pos == std::string::npos) return headers;
+        for (pos++;;) {
+            pos = skipSpace(json, pos);
+            if (pos < json.length() && json[pos] == ',') pos = skipSpace(json, pos + 1);
+            if (pos >= json.length() || json[pos] != '[') break;
+            std::string name, value;
+            pos = skipSpace(json, pos + 1);
+            if (!readJSONString(json, pos, name)) break;
+            pos = skipSpace(json, pos);
+            if (pos >= json.length() || json[pos] != ',') break;
+            pos = skipSpace(json, pos + 1);
+            if (!readJSONString(json, pos, value)) break;
+            pos = skipSpace(json, pos);
+            if (pos >= json.length() || json[pos] != ']') break;
+            pos++;
+            // Header injection through a value would smuggle extra headers.
+            if (name.find_first_of("\r\n:") == std::string::npos && value.find_first_of("\r\n") == std::string::npos)
+                headers.push_back({name, value});
+        }
+        return headers;
+    }
+
     void handleClient(SOCKET client_socket) {
         char buffer[16384] = {0};
         int bytes_received = recv(client_socket, buffer, sizeof(buffer) - 1, 0);
         if (bytes_received <= 0) { closesocket(client_socket); return; }
 
         std::string request(buffer, bytes_received);
+        // Posted forms and uploads can be far larger than one read.
+        size_t header_end = request.find("\r\n\r\n");
+        if (header_end != std::string::npos) {
+            std::string lower = request.substr(0, header_end);
+            std::transform(lower.begin(), lower.end(), lower.begin(), ::tolower);
+            size_t length_pos = lower.find("content-length:");
+            size_t expected = length_pos == std::string::npos ? 0 : strtoul(lower.c_str() + length_pos + 15, nullptr, 10);
+            if (expected > MAX_REQUEST_SIZE) {
+                sendResponse(client_socket, 400, "application/json", "{\"error\":\"Request too large\"}");
+                closesocket(client_socket); return;
+            }
+            while (request.length() < header_end + 4 + expected) {
+                bytes_received = recv(client_socket, buffer, sizeof(buffer), 0);
+                if (bytes_received <= 0) break;
+                request.append(buffer, bytes_received);
+            }
+        }
         std::string method, path;
         size_t s1 = request.find(' '), s2 = request.find(' ', s1 + 1);
         if (s1 != std::string::npos && s2 != std::string::npos) {
@@ -229,27 +453,45 @@ private:
                 closesocket(client_socket); return;
             }
             std::string body = request.substr(body_start + 4);
-            std::cout << "Request body: " << body << std::endl;
-
-            size_t url_start = body.find("\"url\":\"");
-            if (url_start == std::string::npos) {
+            std::cout << "Request body: " << body.substr(0, 1024) << std::endl;
+
+            // {"url", "method", "headers": [[name, value]...], "contentType",
+            // "body": <base64 like the reply's>, "redirect": "manual"}; only
+            // url is required. A manual redirect is passed back as it is.
+            HTTPFetcher::FetchRequest fetch_request;
+            fetch_request.url = jsonField(body, "url");
+            if (fetch_request.url.empty()) {
                 std::cout << "ERROR: Missing url field" << std::endl;
                 sendResponse(client_socket, 400, "application/json", "{\"error\":\"Missing url field\"}");
                 closesocket(client_socket); return;
             }
-            url_start += 7;
-            size_t url_end = body.find("\"", url_start);
-            std::string url = body.substr(url_start, url_end - url_start);
+            fetch_request.method = jsonField(body, "method", "GET");
+            std::transform(fetch_request.method.begin(), fetch_request.method.end(), fetch_request.method.begin(), ::toupper);
+            if (fetch_request.method.find_first_not_of("ABCDEFGHIJKLMNOPQRSTUVWXYZ") != std::string::npos) {
+                sendResponse(client_socket, 400, "application/json", "{\"error\":\"Invalid method\"}");
+                closesocket(client_socket); return;
+            }
+            fetch_request.content_type = jsonField(body, "contentType");
+            fetch_request.headers = jsonHeaders(body);
+            fetch_request.body = Base64::decode(jsonField(body, "body"));
+            fetch_request.follow_redirects = jsonField(body, "redirect", "follow") != "manual";
+            std::string url = fetch_request.url;
 
-            std::cout << "Fetching: " << url << std::endl;
-            auto result = fetcher.fetch(url);
+            std::cout << "Fetching: " << fetch_request.method << " " << url << std::endl;
+            auto result = fetcher.fetch(fetch_request);
 
             if (result.success) {
                 std::cout << "SUCCESS: Got " << result.content.length() << " bytes" << std::endl;
-                std::string escaped_content = escapeJSON(result.content);
+                // The body is base64 (URL-safe alphabet, no padding) so binary
+                // and non-UTF-8 responses arrive byte for byte.
                 std::stringstream json;
-                json << "{\"success\":true,\"content\":\"" << escaped_content
-                     << "\",\"contentType\":\"" << result.content_type << "\"}";
+                json << "{\"success\":true"
+                     << ",\"status\":" << result.status_code
+                     << ",\"finalUrl\":\"" << escapeJSON(result.final_url) << "\""
+                     << ",\"contentType\":\"" << escapeJSON(result.content_type) << "\""
+                     << ",\"charset\":\"" << escapeJSON(charsetOf(result.content_type)) << "\""
+                     << ",\"headers\":" << headersJSON(result.raw_headers)
+                     << ",\"body\":\"" << Base64::encode(result.content) << "\"}";
                 sendResponse(client_socket, 200, "application/json", json.str());
             } else {
                 std::cout << "FAILED: " << result.error << std::endl;
@@ -299,6 +541,22 @@ private:
                 sendResponse(client_socket, 502, "text/plain", "Failed to fetch: " + result.error);
             }
         }
+        // Downloads: the body streamed through untouched, with no size limit
+        // and the Range header honoured, instead of buffered and rewritten.
+        else if (method == "GET" && path.find("/download/") == 0) {
+            std::string url = Base64::decode(path.substr(10));
+            if (url.find("http://") != 0 && url.find("https://") != 0) {
+                sendResponse(client_socket, 400, "text/plain", "Invalid URL - must start with http:// or https://");
+                closesocket(client_socket); return;
+            }
+            std::string range = requestHeader(request, "range");
+            std::cout << "Streaming: " << url << (range.empty() ? "" : " (" + range + ")") << std::endl;
+            std::string error;
+            if (!fetcher.stream(url, range, client_socket, error)) {
+                std::cout << "FAILED: " << error << std::endl;
+                sendResponse(client_socket, 502, "text/plain", "Failed to fetch: " + error);
+            }
+        }
         else if (path.find("/navigate?url=") == 0) {
             std::string url = path.substr(14);
             std::string decoded_url;
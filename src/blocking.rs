@@ -0,0 +1,687 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use scraper::Selector;
+use serde::{Deserialize, Serialize};
+use url::Url;
+use crate::cookies::site_of;
+use crate::profile::write_atomic;
+
+// Tokens so common in addresses that indexing filters under them would not
+// narrow anything down.
+const COMMON_TOKENS: [&str; 4] = ["com", "http", "https", "www"];
+
+// Selectors per CSS rule. One selector the webview does not understand
+// throws away the whole rule it is in, so the rules are kept small.
+const SELECTORS_PER_RULE: usize = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RequestKind {
+    Document,
+    Subdocument,
+    Script,
+    Stylesheet,
+    Image,
+    Font,
+    Media,
+    Xhr,
+    Other,
+}
+
+impl RequestKind {
+    // From the Sec-Fetch-Dest header when the webview sends one, otherwise
+    // guessed from the file extension. None means it could be anything.
+    pub fn detect(destination: Option<&str>, url: &str) -> Option<Self> {
+        if let Some(destination) = destination {
+            return Some(match destination {
+                "document" => Self::Document,
+                "iframe" | "frame" => Self::Subdocument,
+                "script" | "worker" | "sharedworker" | "serviceworker" => Self::Script,
+                "style" => Self::Stylesheet,
+                "image" => Self::Image,
+                "font" => Self::Font,
+                "audio" | "video" | "track" => Self::Media,
+                "empty" => Self::Xhr,
+                _ => Self::Other,
+            });
+        }
+        let path = Url::parse(url).ok()?.path().to_ascii_lowercase();
+        let extension = path.rsplit_once('.').map(|(_, extension)| extension)?;
+        match extension {
+            "js" | "mjs" => Some(Self::Script),
+            "css" => Some(Self::Stylesheet),
+            "png" | "jpg" | "jpeg" | "gif" | "webp" | "avif" | "svg" | "ico" | "bmp" => Some(Self::Image),
+            "woff" | "woff2" | "ttf" | "otf" | "eot" => Some(Self::Font),
+            "mp4" | "webm" | "mp3" | "ogg" | "wav" | "m4a" | "vtt" => Some(Self::Media),
+            _ => None,
+        }
+    }
+
+    fn from_option(name: &str) -> Option<Self> {
+        match name {
+            "document" | "doc" => Some(Self::Document),
+            "subdocument" | "frame" => Some(Self::Subdocument),
+            "script" => Some(Self::Script),
+            "stylesheet" | "css" => Some(Self::Stylesheet),
+            "image" => Some(Self::Image),
+            "font" => Some(Self::Font),
+            "media" => Some(Self::Media),
+            "xmlhttprequest" | "xhr" => Some(Self::Xhr),
+            "other" | "object" | "ping" | "websocket" => Some(Self::Other),
+            _ => None,
+        }
+    }
+
+    fn bit(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+// Filters apply to every kind of subresource unless they say otherwise, but
+// never to the page itself.
+const ALL_KINDS: u16 = (1 << 9) - 1;
+const DEFAULT_KINDS: u16 = ALL_KINDS & !(1 << RequestKind::Document as u16);
+
+// `domain=` in network filters and the part before `##` in cosmetic ones.
+#[derive(Default)]
+struct Domains {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl Domains {
+    // None when a domain uses a form this parser does not understand, such
+    // as `example.*`, so the filter is left out rather than misapplied.
+    fn parse(list: &str, separator: char) -> Option<Self> {
+        let mut domains = Self::default();
+        for domain in list.split(separator).map(str::trim).filter(|d| !d.is_empty()) {
+            let (target, domain) = match domain.strip_prefix('~') {
+                Some(domain) => (&mut domains.exclude, domain),
+                None => (&mut domains.include, domain),
+            };
+            if domain.contains(['*', '/']) {
+                return None;
+            }
+            target.push(domain.to_ascii_lowercase());
+        }
+        Some(domains)
+    }
+
+    fn applies(&self, host: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|d| within(host, d)))
+            && !self.exclude.iter().any(|d| within(host, d))
+    }
+}
+
+fn within(host: &str, domain: &str) -> bool {
+    host.strip_suffix(domain).is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+}
+
+struct NetworkFilter {
+    // `||`: matches from the start of the host or any of its labels.
+    host_anchor: bool,
+    start_anchor: bool,
+    end_anchor: bool,
+    // `*` stands for anything and `^` for a separator or the end.
+    pattern: String,
+    match_case: bool,
+    third_party: Option<bool>,
+    kinds: u16,
+    domains: Domains,
+    important: bool,
+    // Exceptions that switch blocking or hiding off for whole pages.
+    document: bool,
+    element_hide: bool,
+    generic_hide: bool,
+}
+
+// The address being checked, prepared once for all the filters tried on it.
+struct Request<'a> {
+    url: &'a str,
+    lower: String,
+    host_start: usize,
+    host_end: usize,
+    page_host: String,
+    third_party: bool,
+    kind: Option<RequestKind>,
+}
+
+impl<'a> Request<'a> {
+    fn new(url: &'a str, page: &str, kind: Option<RequestKind>) -> Option<Self> {
+        let parsed = Url::parse(url).ok()?;
+        let host = parsed.host_str()?.to_ascii_lowercase();
+        let page_host = Url::parse(page)
+            .ok()
+            .and_then(|page| page.host_str().map(str::to_ascii_lowercase))
+            .unwrap_or_default();
+        let lower = url.to_ascii_lowercase();
+        let host_start = lower.find("://").map(|i| i + 3).unwrap_or(0);
+        let host_start = host_start + lower[host_start..].find(&host)?;
+        Some(Self {
+            url,
+            host_start,
+            host_end: host_start + host.len(),
+            third_party: site_of(&host) != site_of(&page_host),
+            page_host,
+            lower,
+            kind,
+        })
+    }
+
+    fn tokens(&self) -> HashSet<&str> {
+        self.lower
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|token| token.len() > 1)
+            .collect()
+    }
+}
+
+impl NetworkFilter {
+    fn parse(line: &str) -> Option<(bool, Self)> {
+        let (exception, line) = match line.strip_prefix("@@") {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        // Regular expression filters are not supported.
+        if line.len() > 1 && line.starts_with('/') && line.ends_with('/') {
+            return None;
+        }
+        let (mut pattern, options) = match line.rfind('$') {
+            Some(at) => (&line[..at], Some(&line[at + 1..])),
+            None => (line, None),
+        };
+
+        let mut filter = Self {
+            host_anchor: false,
+            start_anchor: false,
+            end_anchor: false,
+            pattern: String::new(),
+            match_case: false,
+            third_party: None,
+            kinds: DEFAULT_KINDS,
+            domains: Domains::default(),
+            important: false,
+            document: false,
+            element_hide: false,
+            generic_hide: false,
+        };
+        let mut included = 0;
+        let mut excluded = 0;
+        for option in options.into_iter().flat_map(|options| options.split(',')) {
+            let option = option.trim().to_ascii_lowercase();
+            let (negated, name) = match option.strip_prefix('~') {
+                Some(name) => (true, name),
+                None => (false, option.as_str()),
+            };
+            match name {
+                "third-party" | "3p" => filter.third_party = Some(!negated),
+                "first-party" | "1p" => filter.third_party = Some(negated),
+                "match-case" => filter.match_case = true,
+                "important" => filter.important = true,
+                "elemhide" | "ehide" if exception => filter.element_hide = true,
+                "generichide" | "ghide" if exception => filter.generic_hide = true,
+                "all" => included |= ALL_KINDS,
+                _ if name.starts_with("domain=") || name.starts_with("from=") => {
+                    let (_, list) = name.split_once('=')?;
+                    filter.domains = Domains::parse(list, '|')?;
+                }
+                _ => {
+                    // Anything else this parser does not know, such as
+                    // redirects or CSP injection, would change what the
+                    // filter means, so the filter is dropped instead.
+                    let kind = RequestKind::from_option(name)?;
+                    if negated {
+                        excluded |= kind.bit();
+                    } else {
+                        included |= kind.bit();
+                        if kind == RequestKind::Document && exception {
+                            filter.document = true;
+                        }
+                    }
+                }
+            }
+        }
+        if included != 0 {
+            filter.kinds = included;
+        }
+        filter.kinds &= !excluded;
+        // These are checked against the page's own address.
+        let page_wide = filter.document || filter.element_hide || filter.generic_hide;
+        if page_wide {
+            filter.kinds = ALL_KINDS;
+        }
+
+        if let Some(rest) = pattern.strip_prefix("||") {
+            filter.host_anchor = true;
+            pattern = rest;
+        } else if let Some(rest) = pattern.strip_prefix('|') {
+            filter.start_anchor = true;
+            pattern = rest;
+        }
+        if let Some(rest) = pattern.strip_suffix('|') {
+            filter.end_anchor = true;
+            pattern = rest;
+        }
+        if !filter.host_anchor && !filter.start_anchor {
+            pattern = pattern.trim_start_matches('*');
+        }
+        if !filter.end_anchor {
+            pattern = pattern.trim_end_matches('*');
+        }
+        // A filter that matches every address is only taken when something
+        // else narrows it down.
+        if pattern.is_empty() && filter.domains.include.is_empty() && !page_wide {
+            return None;
+        }
+        filter.pattern = if filter.match_case { pattern.to_string() } else { pattern.to_ascii_lowercase() };
+        Some((exception, filter))
+    }
+
+    // The longest run of letters and digits that must appear as a whole
+    // token in every address the filter matches.
+    fn token(&self) -> Option<String> {
+        let pattern = self.pattern.as_bytes();
+        let mut best: Option<&[u8]> = None;
+        let mut start = 0;
+        while start < pattern.len() {
+            if !pattern[start].is_ascii_alphanumeric() {
+                start += 1;
+                continue;
+            }
+            let end = (start..pattern.len())
+                .find(|&i| !pattern[i].is_ascii_alphanumeric())
+                .unwrap_or(pattern.len());
+            let whole_before = if start == 0 { self.host_anchor || self.start_anchor } else { pattern[start - 1] != b'*' };
+            let whole_after = if end == pattern.len() { self.end_anchor } else { pattern[end] != b'*' };
+            let token = &pattern[start..end];
+            if whole_before
+                && whole_after
+                && token.len() > 1
+                && !COMMON_TOKENS.iter().any(|common| common.as_bytes().eq_ignore_ascii_case(token))
+                && best.is_none_or(|best| token.len() > best.len())
+            {
+                best = Some(token);
+            }
+            start = end;
+        }
+        best.map(|token| String::from_utf8_lossy(token).to_ascii_lowercase())
+    }
+
+    fn matches(&self, request: &Request) -> bool {
+        let kind_ok = match request.kind {
+            Some(kind) => self.kinds & kind.bit() != 0,
+            None => self.kinds & DEFAULT_KINDS != 0,
+        };
+        if !kind_ok
+            || self.third_party.is_some_and(|third_party| third_party != request.third_party)
+            || !self.domains.applies(&request.page_host)
+        {
+            return false;
+        }
+        let url = if self.match_case { request.url.as_bytes() } else { request.lower.as_bytes() };
+        let pattern = self.pattern.as_bytes();
+        if self.host_anchor {
+            (request.host_start..request.host_end)
+                .filter(|&i| i == request.host_start || url[i - 1] == b'.')
+                .any(|i| glob(pattern, &url[i..], self.end_anchor))
+        } else if self.start_anchor {
+            glob(pattern, url, self.end_anchor)
+        } else {
+            (0..=url.len()).any(|i| glob(pattern, &url[i..], self.end_anchor))
+        }
+    }
+}
+
+fn is_separator(byte: u8) -> bool {
+    !(byte.is_ascii_alphanumeric() || matches!(byte, b'_' | b'-' | b'.' | b'%'))
+}
+
+// Matches `pattern` against the start of `text`, or all of it when
+// `whole` is set.
+fn glob(pattern: &[u8], text: &[u8], whole: bool) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    loop {
+        if p == pattern.len() {
+            if !whole || t == text.len() {
+                return true;
+            }
+        } else if pattern[p] == b'*' {
+            star = Some((p + 1, t));
+            p += 1;
+            continue;
+        } else if t < text.len() && (pattern[p] == text[t] || (pattern[p] == b'^' && is_separator(text[t]))) {
+            p += 1;
+            t += 1;
+            continue;
+        } else if t == text.len() && pattern[p] == b'^' {
+            // `^` also matches the end of the address.
+            p += 1;
+            continue;
+        }
+        match star {
+            Some((after, from)) if from < text.len() => {
+                star = Some((after, from + 1));
+                p = after;
+                t = from + 1;
+            }
+            _ => return false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct NetworkFilters {
+    by_token: HashMap<String, Vec<NetworkFilter>>,
+    untokened: Vec<NetworkFilter>,
+}
+
+impl NetworkFilters {
+    fn insert(&mut self, filter: NetworkFilter) {
+        match filter.token() {
+            Some(token) => self.by_token.entry(token).or_default().push(filter),
+            None => self.untokened.push(filter),
+        }
+    }
+
+    fn find(&self, request: &Request, tokens: &HashSet<&str>) -> Option<&NetworkFilter> {
+        tokens
+            .iter()
+            .filter_map(|token| self.by_token.get(*token))
+            .flatten()
+            .chain(&self.untokened)
+            .find(|filter| filter.matches(request))
+    }
+
+    fn len(&self) -> usize {
+        self.by_token.values().map(Vec::len).sum::<usize>() + self.untokened.len()
+    }
+}
+
+struct CosmeticFilter {
+    domains: Domains,
+    selector: String,
+}
+
+// EasyList and uBlock Origin style filter lists. Regular expressions,
+// scriptlets and procedural cosmetic filters are skipped.
+#[derive(Default)]
+pub struct FilterSet {
+    blocks: NetworkFilters,
+    exceptions: NetworkFilters,
+    page_exceptions: Vec<NetworkFilter>,
+    generic_hiding: Vec<String>,
+    hiding: Vec<CosmeticFilter>,
+    generic_unhiding: HashSet<String>,
+    unhiding: Vec<CosmeticFilter>,
+}
+
+impl FilterSet {
+    pub fn parse(text: &str) -> Self {
+        let mut filters = Self::default();
+        filters.add_list(text);
+        filters
+    }
+
+    // Every `.txt` file in the directory, in name order. A missing directory
+    // just means no lists.
+    pub fn load_dir(dir: &Path) -> Self {
+        let mut filters = Self::default();
+        let Ok(entries) = fs::read_dir(dir) else {
+            return filters;
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "txt"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            match fs::read(&path) {
+                Ok(data) => filters.add_list(&String::from_utf8_lossy(&data)),
+                Err(e) => eprintln!("Failed to read filter list {}: {}", path.display(), e),
+            }
+        }
+        filters
+    }
+
+    pub fn add_list(&mut self, text: &str) {
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+                continue;
+            }
+            if let Some((domains, selector)) = line.split_once("#@#") {
+                self.add_cosmetic(domains, selector, true);
+            } else if let Some((domains, selector)) = line.split_once("##") {
+                self.add_cosmetic(domains, selector, false);
+            } else if line.contains("#?#") || line.contains("#$#") || line.contains("#%#") {
+                continue;
+            } else if let Some((exception, filter)) = NetworkFilter::parse(line) {
+                if filter.document || filter.element_hide || filter.generic_hide {
+                    self.page_exceptions.push(filter);
+                } else if exception {
+                    self.exceptions.insert(filter);
+                } else {
+                    self.blocks.insert(filter);
+                }
+            }
+        }
+    }
+
+    fn add_cosmetic(&mut self, domains: &str, selector: &str, exception: bool) {
+        let selector = selector.trim();
+        // The selectors end up inside a <style> element, so anything that
+        // could close it or start another rule is refused along with what
+        // is not plain CSS, such as `+js(...)` or `:has-text(...)`.
+        if selector.is_empty() || selector.contains(['{', '}', '<', '\\']) || Selector::parse(selector).is_err() {
+            return;
+        }
+        let Some(domains) = Domains::parse(domains, ',') else {
+            return;
+        };
+        let generic = domains.include.is_empty() && domains.exclude.is_empty();
+        match (exception, generic) {
+            (true, true) => {
+                self.generic_unhiding.insert(selector.to_string());
+            }
+            (true, false) => self.unhiding.push(CosmeticFilter { domains, selector: selector.to_string() }),
+            (false, true) => self.generic_hiding.push(selector.to_string()),
+            (false, false) => self.hiding.push(CosmeticFilter { domains, selector: selector.to_string() }),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+            + self.exceptions.len()
+            + self.page_exceptions.len()
+            + self.generic_hiding.len()
+            + self.hiding.len()
+            + self.generic_unhiding.len()
+            + self.unhiding.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Whether `url`, requested by the page at `page`, should be refused.
+    pub fn blocks(&self, url: &str, page: &str, kind: Option<RequestKind>) -> bool {
+        let Some(request) = Request::new(url, page, kind) else {
+            return false;
+        };
+        if self.page_exception(page, |filter| filter.document) {
+            return false;
+        }
+        let tokens = request.tokens();
+        match self.blocks.find(&request, &tokens) {
+            Some(filter) if filter.important => true,
+            Some(_) => self.exceptions.find(&request, &tokens).is_none(),
+            None => false,
+        }
+    }
+
+    fn page_exception(&self, page: &str, applies: impl Fn(&NetworkFilter) -> bool) -> bool {
+        let Some(request) = Request::new(page, page, None) else {
+            return false;
+        };
+        self.page_exceptions
+            .iter()
+            .any(|filter| applies(filter) && filter.matches(&request))
+    }
+
+    // The style sheet that hides what the lists say to hide on `page`.
+    pub fn hiding_css(&self, page: &str) -> String {
+        let Some(host) = Url::parse(page).ok().and_then(|url| url.host_str().map(str::to_ascii_lowercase)) else {
+            return String::new();
+        };
+        if self.page_exception(page, |filter| filter.document || filter.element_hide) {
+            return String::new();
+        }
+        let unhidden: HashSet<&str> = self
+            .unhiding
+            .iter()
+            .filter(|filter| filter.domains.applies(&host))
+            .map(|filter| filter.selector.as_str())
+            .chain(self.generic_unhiding.iter().map(String::as_str))
+            .collect();
+        let generic: &[String] = if self.page_exception(page, |filter| filter.generic_hide) {
+            &[]
+        } else {
+            &self.generic_hiding
+        };
+        let mut seen = HashSet::new();
+        let selectors: Vec<&str> = generic
+            .iter()
+            .map(String::as_str)
+            .chain(
+                self.hiding
+                    .iter()
+                    .filter(|filter| filter.domains.applies(&host))
+                    .map(|filter| filter.selector.as_str()),
+            )
+            .filter(|selector| !unhidden.contains(selector) && seen.insert(*selector))
+            .collect();
+        selectors
+            .chunks(SELECTORS_PER_RULE)
+            .map(|chunk| format!("{} {{ display: none !important; }}\n", chunk.join(", ")))
+            .collect()
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Allowlist {
+    allowed_sites: HashSet<String>,
+}
+
+// The filter lists together with the sites the user has switched blocking
+// off for, and how much has been blocked on each page since it loaded.
+#[derive(Default)]
+pub struct ContentBlocker {
+    // Shared with the blocker of the private windows.
+    filters: Arc<FilterSet>,
+    allowlist: Allowlist,
+    blocked: HashMap<String, usize>,
+    allowlist_path: Option<PathBuf>,
+}
+
+impl ContentBlocker {
+    pub fn load(filters_dir: &Path, allowlist_path: PathBuf) -> Self {
+        let allowlist = fs::read_to_string(&allowlist_path)
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default();
+        Self {
+            filters: Arc::new(FilterSet::load_dir(filters_dir)),
+            allowlist,
+            blocked: HashMap::new(),
+            allowlist_path: Some(allowlist_path),
+        }
+    }
+
+    pub fn with_filters(filters: FilterSet) -> Self {
+        Self { filters: Arc::new(filters), ..Self::default() }
+    }
+
+    // For private windows: the same filters and allowed sites to begin with,
+    // but sites allowed or blocked there are not saved.
+    pub fn private_copy(&self) -> Self {
+        Self {
+            filters: self.filters.clone(),
+            allowlist: self.allowlist.clone(),
+            blocked: HashMap::new(),
+            allowlist_path: None,
+        }
+    }
+
+    pub fn filters(&self) -> &FilterSet {
+        &self.filters
+    }
+
+    pub fn set_filters(&mut self, filters: Arc<FilterSet>) {
+        self.filters = filters;
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.allowlist_path else { return };
+        let result = serde_json::to_string_pretty(&self.allowlist)
+            .map_err(|e| format!("Failed to serialize blocking allowlist: {}", e))
+            .and_then(|data| write_atomic(path, data.as_bytes()));
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+    }
+
+    pub fn is_allowed(&self, page: &str) -> bool {
+        page_site(page).is_some_and(|site| self.allowlist.allowed_sites.contains(&site))
+    }
+
+    pub fn set_allowed(&mut self, page: &str, allowed: bool) {
+        let Some(site) = page_site(page) else { return };
+        if allowed {
+            self.allowlist.allowed_sites.insert(site);
+        } else {
+            self.allowlist.allowed_sites.remove(&site);
+        }
+        self.save();
+    }
+
+    // Returns how many requests have now been blocked on the page if this
+    // one is blocked too.
+    pub fn check(&mut self, url: &str, page: &str, kind: Option<RequestKind>) -> Option<usize> {
+        if self.is_allowed(page) || !self.filters.blocks(url, page, kind) {
+            return None;
+        }
+        let count = self.blocked.entry(page_key(page)).or_insert(0);
+        *count += 1;
+        Some(*count)
+    }
+
+    pub fn hiding_css(&self, page: &str) -> String {
+        if self.is_allowed(page) {
+            String::new()
+        } else {
+            self.filters.hiding_css(page)
+        }
+    }
+
+    pub fn blocked_on(&self, page: &str) -> usize {
+        self.blocked.get(&page_key(page)).copied().unwrap_or(0)
+    }
+
+    // Called when a new page loads, which starts its count again.
+    pub fn reset(&mut self, page: &str) {
+        self.blocked.remove(&page_key(page));
+    }
+}
+
+// Subresources often only say which site asked for them, not which page, so
+// counts are kept per origin.
+pub fn page_key(page: &str) -> String {
+    Url::parse(page)
+        .map(|url| url.origin().ascii_serialization())
+        .unwrap_or_else(|_| page.to_string())
+}
+
+fn page_site(page: &str) -> Option<String> {
+    Url::parse(page).ok()?.host_str().map(site_of)
+}
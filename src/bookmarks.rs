@@ -0,0 +1,204 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::profile::write_atomic;
+
+pub const QUICK_LINKS_FOLDER: &str = "Quick Links";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub id: u64,
+    pub url: String,
+    pub title: String,
+    pub folder: String,
+    pub tags: Vec<String>,
+    pub favicon: Option<String>,
+    pub added: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BookmarkStore {
+    next_id: u64,
+    folders: Vec<String>,
+    bookmarks: Vec<Bookmark>,
+    // None when changes are not saved.
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+pub struct BookmarkList<'a> {
+    pub folders: &'a [String],
+    pub bookmarks: &'a [Bookmark],
+}
+
+impl BookmarkStore {
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        Self::open(&path, Some(path.clone()))
+    }
+
+    // For `--private`: the saved bookmarks, or the starting ones when there
+    // are none, with every change kept in memory.
+    pub fn load_private(path: &Path) -> Result<Self, String> {
+        Self::open(path, None)
+    }
+
+    fn open(path: &Path, save_to: Option<PathBuf>) -> Result<Self, String> {
+        if !path.exists() {
+            let mut store = Self {
+                next_id: 1,
+                folders: vec![QUICK_LINKS_FOLDER.to_string()],
+                bookmarks: Vec::new(),
+                path: save_to,
+            };
+            for (url, title) in [
+                ("https://github.com", "GitHub"),
+                ("https://youtube.com", "YouTube"),
+                ("https://reddit.com", "Reddit"),
+                ("https://twitter.com", "Twitter"),
+            ] {
+                store.add(url, title, QUICK_LINKS_FOLDER, Vec::new(), None)?;
+            }
+            return Ok(store);
+        }
+
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read bookmarks: {}", e))?;
+        let mut store: Self = serde_json::from_str(&data)
+            .map_err(|e| format!("Invalid bookmarks file: {}", e))?;
+        store.path = save_to;
+        Ok(store)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let data = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize bookmarks: {}", e))?;
+        write_atomic(path, data.as_bytes())
+    }
+
+    pub fn list(&self) -> BookmarkList<'_> {
+        BookmarkList {
+            folders: &self.folders,
+            bookmarks: &self.bookmarks,
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        url: &str,
+        title: &str,
+        folder: &str,
+        tags: Vec<String>,
+        favicon: Option<String>,
+    ) -> Result<u64, String> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(format!("Cannot bookmark {}", url));
+        }
+
+        let folder = normalize_folder(folder);
+        self.ensure_folder(&folder);
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.bookmarks.push(Bookmark {
+            id,
+            url: url.to_string(),
+            title: if title.trim().is_empty() { url.to_string() } else { title.trim().to_string() },
+            folder,
+            tags: normalize_tags(tags),
+            favicon: resolve_favicon(url, favicon.as_deref()),
+            added: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        });
+        self.save()?;
+        Ok(id)
+    }
+
+    pub fn update(&mut self, id: u64, title: Option<String>, tags: Option<Vec<String>>) -> Result<(), String> {
+        let bookmark = self.get_mut(id)?;
+        if let Some(title) = title.filter(|t| !t.trim().is_empty()) {
+            bookmark.title = title.trim().to_string();
+        }
+        if let Some(tags) = tags {
+            bookmark.tags = normalize_tags(tags);
+        }
+        self.save()
+    }
+
+    pub fn remove(&mut self, id: u64) -> Result<(), String> {
+        let before = self.bookmarks.len();
+        self.bookmarks.retain(|b| b.id != id);
+        if self.bookmarks.len() == before {
+            return Err(format!("No bookmark with id {}", id));
+        }
+        self.save()
+    }
+
+    pub fn move_to(&mut self, id: u64, folder: &str) -> Result<(), String> {
+        let folder = normalize_folder(folder);
+        self.ensure_folder(&folder);
+        self.get_mut(id)?.folder = folder;
+        self.save()
+    }
+
+    pub fn create_folder(&mut self, folder: &str) -> Result<(), String> {
+        self.ensure_folder(&normalize_folder(folder));
+        self.save()
+    }
+
+    pub fn remove_folder(&mut self, folder: &str) -> Result<(), String> {
+        let folder = normalize_folder(folder);
+        let prefix = format!("{}/", folder);
+        self.folders.retain(|f| *f != folder && !f.starts_with(&prefix));
+        self.bookmarks.retain(|b| b.folder != folder && !b.folder.starts_with(&prefix));
+        self.save()
+    }
+
+    fn get_mut(&mut self, id: u64) -> Result<&mut Bookmark, String> {
+        self.bookmarks
+            .iter_mut()
+            .find(|b| b.id == id)
+            .ok_or_else(|| format!("No bookmark with id {}", id))
+    }
+
+    fn ensure_folder(&mut self, folder: &str) {
+        if !folder.is_empty() && !self.folders.iter().any(|f| f == folder) {
+            self.folders.push(folder.to_string());
+            self.folders.sort();
+        }
+    }
+}
+
+fn normalize_folder(folder: &str) -> String {
+    folder
+        .split('/')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut tags: Vec<String> = tags
+        .iter()
+        .flat_map(|t| t.split(','))
+        .map(|t| t.trim().to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+fn resolve_favicon(page_url: &str, href: Option<&str>) -> Option<String> {
+    url::Url::parse(page_url)
+        .ok()?
+        .join(href.unwrap_or("/favicon.ico"))
+        .ok()
+        .filter(|u| u.scheme() == "http" || u.scheme() == "https")
+        .map(String::from)
+}
@@ -0,0 +1,1034 @@
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::blocking::{page_key, ContentBlocker, FilterSet, RequestKind};
+use crate::bookmarks::{self, BookmarkStore};
+use crate::cookies::{open_with_cookies, CookieJar};
+use crate::downloads::{receive, transfer, DownloadManager};
+use crate::fetch::{fetch_page, fetch_search_results, search_history, Fetched, PageRequest};
+use crate::forms::FormSubmission;
+use crate::history::{History, HistoryTarget, SearchProvider};
+use crate::import::{import_into, read_documents, ImportFormat};
+use crate::indexer::{extract_title, IndexPolicy, SharedIndex};
+use crate::profile::Profile;
+use crate::protocol::{self, prepare_page, view_url, PageCache, Resource};
+use crate::snippet::escape_html;
+use crate::session::{Session, WindowBounds};
+use crate::settings::{Settings, SettingsStore};
+use crate::tabs::TabManager;
+use crate::urlclean::UrlCleaner;
+use crate::windows::WindowManager;
+
+// A response body being saved as it arrives, and its length if known.
+type DownloadStream = (Box<dyn Read + Send>, Option<u64>);
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct NavigateMessage {
+    pub action: String,
+    pub url: Option<String>,
+    pub path: Option<String>,
+    pub format: Option<String>,
+    pub fetch: Option<bool>,
+    pub id: Option<u64>,
+    pub title: Option<String>,
+    pub folder: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub favicon: Option<String>,
+    pub page: Option<u32>,
+    pub scroll: Option<f64>,
+    pub tab: Option<u64>,
+    pub index: Option<usize>,
+    pub window: Option<u64>,
+    pub settings: Option<Value>,
+    pub form: Option<FormSubmission>,
+    pub document: Option<String>,
+}
+
+// A call into the chrome's JavaScript. The arguments stay JSON until the
+// webview needs them so the headless driver can inspect them directly.
+#[derive(Clone, Debug)]
+pub struct ScriptCall {
+    pub function: String,
+    pub args: Vec<Value>,
+}
+
+impl ScriptCall {
+    fn new(function: &str, args: Value) -> Self {
+        let args = match args {
+            Value::Array(args) => args,
+            Value::Null => Vec::new(),
+            arg => vec![arg],
+        };
+        Self { function: function.to_string(), args }
+    }
+
+    pub fn to_script(&self) -> String {
+        let args: Vec<String> = self.args.iter().map(Value::to_string).collect();
+        format!("{}({});", self.function, args.join(", "))
+    }
+}
+
+pub enum BrowserEvent {
+    EvaluateScript(u64, ScriptCall),
+    OpenWindow(TabManager, Option<WindowBounds>, bool),
+    CloseWindow(u64),
+    ApplySettings(Settings),
+}
+
+// Whatever shows the browser: the tao event loop, or a channel in tests.
+pub type EventSink = Arc<dyn Fn(BrowserEvent) + Send + Sync>;
+
+// Everything a window remembers about where it has been, and what the user
+// has decided about sites there. Private windows share one set that is only
+// ever held in memory.
+#[derive(Clone)]
+struct BrowsingData {
+    history: Arc<Mutex<History>>,
+    downloads: Arc<Mutex<DownloadManager>>,
+    pages: Arc<Mutex<PageCache>>,
+    cookies: Arc<Mutex<CookieJar>>,
+    policy: Arc<Mutex<IndexPolicy>>,
+    // None when the policy is not saved.
+    policy_path: Option<PathBuf>,
+    blocker: Arc<Mutex<ContentBlocker>>,
+}
+
+impl BrowsingData {
+    fn load(profile: &Profile) -> Result<Self, String> {
+        let policy_path = profile.index_policy_path();
+        Ok(Self {
+            history: Arc::new(Mutex::new(History::load(profile.history_path())?)),
+            downloads: Arc::new(Mutex::new(DownloadManager::load(profile.downloads_path()))),
+            pages: Arc::new(Mutex::new(PageCache::default())),
+            cookies: Arc::new(Mutex::new(CookieJar::load(profile.cookies_path()))),
+            policy: Arc::new(Mutex::new(IndexPolicy::load(&policy_path))),
+            policy_path: Some(policy_path),
+            blocker: Arc::new(Mutex::new(ContentBlocker::load(
+                &profile.filters_dir(),
+                profile.blocking_allowlist_path(),
+            ))),
+        })
+    }
+
+    // Starts from the sites the user has excluded from the index or allowed
+    // ads on, but keeps any change to them in memory.
+    fn in_memory(policy: IndexPolicy, blocker: &ContentBlocker) -> Self {
+        Self {
+            history: Arc::new(Mutex::new(History::in_memory())),
+            downloads: Arc::new(Mutex::new(DownloadManager::in_memory())),
+            pages: Arc::new(Mutex::new(PageCache::default())),
+            cookies: Arc::new(Mutex::new(CookieJar::in_memory())),
+            policy: Arc::new(Mutex::new(policy)),
+            policy_path: None,
+            blocker: Arc::new(Mutex::new(blocker.private_copy())),
+        }
+    }
+
+    fn private_copy(&self) -> Self {
+        let policy = self.policy.lock().unwrap().clone();
+        Self::in_memory(policy, &self.blocker.lock().unwrap())
+    }
+}
+
+#[derive(Clone)]
+pub struct BrowserContext {
+    sink: EventSink,
+    index: SharedIndex,
+    bookmarks: Arc<Mutex<BookmarkStore>>,
+    cleaner: Arc<Mutex<UrlCleaner>>,
+    profile_data: BrowsingData,
+    // Created for the first private window and dropped with the last one.
+    private_data: Arc<Mutex<Option<BrowsingData>>>,
+    data: BrowsingData,
+    profile: Arc<Profile>,
+    settings: Arc<Mutex<SettingsStore>>,
+    windows: Arc<Mutex<WindowManager>>,
+    recovered_session: Arc<Mutex<Option<Session>>>,
+    window: u64,
+    tabs: Arc<Mutex<TabManager>>,
+    private: bool,
+    // Set by --private, which makes every window private.
+    always_private: bool,
+}
+
+impl BrowserContext {
+    pub fn new(
+        profile: Profile,
+        settings: SettingsStore,
+        mut windows: WindowManager,
+        recovered_session: Option<Session>,
+        private: bool,
+        sink: EventSink,
+    ) -> Result<Self, String> {
+        if windows.is_empty() {
+            windows.open(TabManager::default(), None, private);
+        }
+        let (window, tabs) = windows.entries()[0].clone();
+        let (profile_data, bookmarks) = if private {
+            let data = BrowsingData::in_memory(
+                IndexPolicy::load(&profile.index_policy_path()),
+                &ContentBlocker::load(&profile.filters_dir(), profile.blocking_allowlist_path()),
+            );
+            (data, BookmarkStore::load_private(&profile.bookmarks_path())?)
+        } else {
+            (BrowsingData::load(&profile)?, BookmarkStore::load(profile.bookmarks_path())?)
+        };
+
+        let context = Self {
+            sink,
+            index: SharedIndex::open(profile.index_path())?,
+            bookmarks: Arc::new(Mutex::new(bookmarks)),
+            cleaner: Arc::new(Mutex::new(UrlCleaner::default())),
+            data: profile_data.clone(),
+            profile_data,
+            private_data: Arc::new(Mutex::new(None)),
+            profile: Arc::new(profile),
+            settings: Arc::new(Mutex::new(settings)),
+            windows: Arc::new(Mutex::new(windows)),
+            recovered_session: Arc::new(Mutex::new(recovered_session)),
+            window,
+            tabs: tabs.clone(),
+            private,
+            always_private: private,
+        };
+        Ok(context.for_window(window, tabs))
+    }
+
+    pub fn for_window(&self, window: u64, tabs: Arc<Mutex<TabManager>>) -> Self {
+        let private = self.windows.lock().unwrap().is_private(window);
+        let data = if private {
+            self.private_data.lock().unwrap().get_or_insert_with(|| self.profile_data.private_copy()).clone()
+        } else {
+            self.profile_data.clone()
+        };
+        Self {
+            window,
+            tabs,
+            data,
+            private,
+            ..self.clone()
+        }
+    }
+
+    // Returns false for the last window, which stays open. Closing the last
+    // private window throws away everything the private windows collected.
+    pub fn close_window(&self, window: u64) -> bool {
+        let mut windows = self.windows.lock().unwrap();
+        if !windows.close(window) {
+            return false;
+        }
+        if !windows.ids().iter().any(|id| windows.is_private(*id)) {
+            self.private_data.lock().unwrap().take();
+        }
+        true
+    }
+
+    pub fn window(&self) -> u64 {
+        self.window
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.private
+    }
+
+    pub fn tabs(&self) -> &Arc<Mutex<TabManager>> {
+        &self.tabs
+    }
+
+    pub fn windows(&self) -> &Arc<Mutex<WindowManager>> {
+        &self.windows
+    }
+
+    pub fn history(&self) -> &Arc<Mutex<History>> {
+        &self.data.history
+    }
+
+    pub fn index(&self) -> &SharedIndex {
+        &self.index
+    }
+
+    // Writes out what is kept in memory between saves. Called on a timer and
+    // before the browser exits.
+    pub fn flush(&self) {
+        self.profile_data.history.lock().unwrap().flush();
+        if let Err(e) = self.index.flush() {
+            eprintln!("{}", e);
+        }
+    }
+
+    pub fn downloads(&self) -> &Arc<Mutex<DownloadManager>> {
+        &self.data.downloads
+    }
+
+    pub fn blocker(&self) -> &Arc<Mutex<ContentBlocker>> {
+        &self.data.blocker
+    }
+
+    pub fn cleaner(&self) -> &Arc<Mutex<UrlCleaner>> {
+        &self.cleaner
+    }
+
+    // Called by the webview for every `cypher-proxy://` request the content
+    // frame makes. `request.url` is the proxied address. What a page asks
+    // for is checked against the filter lists; the page itself never is.
+    pub fn serve_proxy_request(&self, request: PageRequest) -> Resource {
+        let settings = self.settings();
+        let page = protocol::requested_by(&request).filter(|_| settings.blocking.enabled);
+        if let (Some(page), Some(url)) = (page, protocol::from_proxy_url(&request.url)) {
+            let destination = request
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("sec-fetch-dest"))
+                .map(|(_, value)| value.as_str());
+            let kind = RequestKind::detect(destination, &url);
+            let blocked = self.data.blocker.lock().unwrap().check(&url, &page, kind);
+            if let Some(count) = blocked {
+                self.call("setBlockedCount", json!([page_key(&page), count]));
+                return Resource::blocked();
+            }
+        }
+        protocol::serve(&settings, &self.data.pages, &self.data.cookies, &self.data.blocker, request)
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.settings.lock().unwrap().get().clone()
+    }
+
+    fn send(&self, event: BrowserEvent) {
+        (self.sink)(event);
+    }
+
+    fn call(&self, function: &str, args: Value) {
+        self.send(BrowserEvent::EvaluateScript(self.window, ScriptCall::new(function, args)));
+    }
+
+    fn broadcast(&self, function: &str, args: Value) {
+        let call = ScriptCall::new(function, args);
+        for window in self.windows.lock().unwrap().ids() {
+            self.send(BrowserEvent::EvaluateScript(window, call.clone()));
+        }
+    }
+
+    fn is_current(&self, tab: u64, navigation: u64) -> bool {
+        self.tabs.lock().unwrap().is_current(tab, navigation)
+    }
+
+    fn finish_navigation(&self, tab: u64, navigation: u64) {
+        self.tabs.lock().unwrap().finish_navigation(tab, navigation);
+        self.send_tabs();
+    }
+
+    // Targets named on the command line go into the first window before it
+    // is shown, so they load like any restored tab.
+    pub fn open_startup(&self, targets: Vec<HistoryTarget>) {
+        for target in targets {
+            self.tabs.lock().unwrap().open_with(target.clone());
+            self.data.history.lock().unwrap().record(target);
+        }
+    }
+
+    fn import(&self, path: PathBuf, format: Option<String>, fetch: bool) -> Result<usize, String> {
+        let format = format
+            .as_deref()
+            .and_then(ImportFormat::parse)
+            .or_else(|| ImportFormat::detect(&path))
+            .ok_or_else(|| format!("Unrecognized import file: {}", path.display()))?;
+        let docs = read_documents(&path, format, self.profile.cache_dir())?;
+        let policy = self.data.policy.lock().unwrap().clone();
+        let settings = self.settings();
+
+        import_into(
+            &self.index,
+            &policy,
+            docs,
+            fetch.then_some(|url: &str| fetch_page(&settings, url, |_, _| {}).map(|page| page.text())),
+            |done, total| self.call("importProgress", json!([done, total])),
+        )
+    }
+
+    fn notify(&self, message: &str) {
+        self.call("notify", json!([message]));
+    }
+
+    fn send_bookmarks(&self) {
+        let bookmarks = self.bookmarks.lock().unwrap();
+        self.broadcast("setBookmarks", json!([bookmarks.list()]));
+    }
+
+    fn send_tabs(&self) {
+        let tabs = self.tabs.lock().unwrap();
+        self.call("syncTabs", json!([tabs.infos(), tabs.active()]));
+    }
+
+    pub fn send_windows(&self) {
+        let (ids, private): (Vec<u64>, Vec<u64>) = {
+            let windows = self.windows.lock().unwrap();
+            let ids = windows.ids();
+            let private = ids.iter().copied().filter(|id| windows.is_private(*id)).collect();
+            (ids, private)
+        };
+        for window in &ids {
+            let call = ScriptCall::new("syncWindows", json!([ids, window, private]));
+            self.send(BrowserEvent::EvaluateScript(*window, call));
+        }
+    }
+
+    fn open_window(&self, url: Option<String>, private: bool) {
+        let mut tabs = TabManager::default();
+        if let Some(url) = url {
+            tabs.record(tabs.active(), HistoryTarget::Url { url });
+        }
+        self.send(BrowserEvent::OpenWindow(tabs, None, private || self.always_private));
+    }
+
+    fn restore_session(&self) {
+        let Some(session) = self.recovered_session.lock().unwrap().take() else {
+            return;
+        };
+        self.windows.lock().unwrap().release_recovered();
+        for window in session.windows {
+            self.send(BrowserEvent::OpenWindow(window.tabs, window.bounds, false));
+        }
+        let untouched = {
+            let tabs = self.tabs.lock().unwrap();
+            tabs.infos().iter().all(|t| t.target == HistoryTarget::Home && !t.can_go_back)
+        };
+        if untouched {
+            self.send(BrowserEvent::CloseWindow(self.window));
+        }
+        self.broadcast("sessionRestoreHandled", Value::Null);
+    }
+
+    fn move_tab(&self, tab: u64, destination: Option<u64>) {
+        let destination = match destination {
+            Some(window) if window == self.window => return,
+            Some(window) => {
+                let tabs = self.windows.lock().unwrap().get(window);
+                let private = self.windows.lock().unwrap().is_private(window);
+                // A tab never crosses between private and ordinary windows.
+                match tabs {
+                    Some(tabs) if private == self.private => Some(self.for_window(window, tabs)),
+                    _ => return,
+                }
+            }
+            None => None,
+        };
+        let (moved, emptied) = {
+            let mut tabs = self.tabs.lock().unwrap();
+            if destination.is_none() && tabs.len() < 2 {
+                return;
+            }
+            let Some(moved) = tabs.take(tab) else { return };
+            (moved, tabs.is_empty())
+        };
+
+        // The page itself lives in the source window's webview, so the moved
+        // tab is loaded again wherever it ends up.
+        match destination {
+            Some(destination) => {
+                let target = moved.current().unwrap_or(HistoryTarget::Home);
+                let id = destination.tabs.lock().unwrap().insert(moved, None, true);
+                destination.open_target(id, target);
+            }
+            None => self.send(BrowserEvent::OpenWindow(TabManager::with_tab(moved), None, self.private)),
+        }
+        if emptied {
+            self.send(BrowserEvent::CloseWindow(self.window));
+        } else {
+            self.send_tabs();
+        }
+    }
+
+    fn navigate(&self, tab: u64, target: HistoryTarget) {
+        // Cleaned before anything else sees the address, so the history and
+        // the tab only ever hold the clean one.
+        let target = match target {
+            HistoryTarget::Url { url } => {
+                let url = self.cleaner.lock().unwrap().clean(&self.settings().url_cleaning, &url);
+                HistoryTarget::Url { url }
+            }
+            target => target,
+        };
+        self.tabs.lock().unwrap().record(tab, target.clone());
+        self.data.history.lock().unwrap().record(target.clone());
+        self.open_target(tab, target);
+    }
+
+    fn open_target(&self, tab: u64, target: HistoryTarget) {
+        self.open_target_with(tab, target, None);
+    }
+
+    // `form` is sent instead of a plain GET for the address when the page is
+    // the result of submitting a form.
+    fn open_target_with(&self, tab: u64, target: HistoryTarget, form: Option<PageRequest>) {
+        // Only the most recent navigation of a tab may replace its content, so a
+        // slow response cannot overwrite a page the user has already moved on from.
+        let (navigation, from) = {
+            let mut tabs = self.tabs.lock().unwrap();
+            let Some(navigation) = tabs.begin_navigation(tab) else {
+                return;
+            };
+            (navigation, tabs.get(tab).and_then(|t| t.page()).map(str::to_string))
+        };
+        self.send_tabs();
+        self.call("beginNavigation", json!([tab, target]));
+
+        match &target {
+            HistoryTarget::Home => self.show_page(tab, navigation, None),
+            HistoryTarget::Search { provider, query, page } => {
+                let settings = self.settings();
+                let results = match provider {
+                    SearchProvider::DuckDuckGo => fetch_search_results(&settings, query, *page).map(|mut results| {
+                        let mut cleaner = self.cleaner.lock().unwrap();
+                        for result in &mut results {
+                            result.url = cleaner.clean(&settings.url_cleaning, &result.url);
+                        }
+                        results
+                    }),
+                    SearchProvider::Local => {
+                        let policy = self.data.policy.lock().unwrap();
+                        Ok(search_history(&self.index, &policy, query, *page, settings.search.results_per_page))
+                    }
+                };
+                if self.is_current(tab, navigation) {
+                    match results {
+                        Ok(results) => self.call("displayResults", json!([tab, results, target])),
+                        Err(e) => self.call("showError", json!([tab, e])),
+                    }
+                }
+                self.show_page(tab, navigation, None);
+            }
+            HistoryTarget::Url { url } => {
+                if let Some(page) = url.strip_prefix("cypher://") {
+                    self.open_internal_page(tab, page, navigation);
+                    self.show_page(tab, navigation, None);
+                } else {
+                    let request = form.unwrap_or_else(|| PageRequest::get(url));
+                    self.load_page(tab, &target, &request, from.as_deref(), navigation);
+                }
+            }
+        }
+
+        let scroll = self.tabs.lock().unwrap().get(tab).map(|t| t.scroll()).unwrap_or(0.0);
+        if scroll > 0.0 && self.is_current(tab, navigation) {
+            self.call("restoreScroll", json!([tab, scroll]));
+        }
+        self.finish_navigation(tab, navigation);
+    }
+
+    // `from` is the page the tab showed before, whose site decides whether
+    // the request counts as same-site for cookies.
+    fn load_page(&self, tab: u64, target: &HistoryTarget, request: &PageRequest, from: Option<&str>, navigation: u64) {
+        let fetched = open_with_cookies(&self.settings(), &self.data.cookies, request, from, |received, total| {
+            if self.is_current(tab, navigation) {
+                self.call("showPageProgress", json!([tab, received, total]));
+            }
+        });
+        let page = match fetched {
+            Ok(Fetched::Page(page)) => page,
+            Ok(Fetched::File { page, body, total }) => {
+                if self.is_current(tab, navigation) {
+                    self.start_download(&page.url, &page.content_type, Some(tab), Some((body, total)));
+                }
+                return;
+            }
+            Err(e) => {
+                if self.is_current(tab, navigation) {
+                    self.call("showError", json!([tab, e]));
+                }
+                self.show_page(tab, navigation, None);
+                return;
+            }
+        };
+
+        // Redirects are followed, so links and the index refer to where the
+        // page actually came from.
+        let url = page.url.as_str();
+        let html = if page.mime() == "text/plain" {
+            format!("<pre>{}</pre>", escape_html(&page.text()))
+        } else {
+            page.text()
+        };
+        let indexed = self.data.policy.lock().unwrap().should_index(url, self.private);
+        let (hiding, blocking) = if self.settings().blocking.enabled {
+            let mut blocker = self.data.blocker.lock().unwrap();
+            blocker.reset(url);
+            (blocker.hiding_css(url), !blocker.is_allowed(url))
+        } else {
+            (String::new(), false)
+        };
+        let Some(view) = view_url(url) else {
+            if self.is_current(tab, navigation) {
+                self.call("showError", json!([tab, format!("{} cannot be shown through the proxy", url)]));
+            }
+            self.show_page(tab, navigation, None);
+            return;
+        };
+        if self.is_current(tab, navigation) {
+            let mut pages = self.data.pages.lock().unwrap();
+            let document = pages.register(url);
+            pages.insert(
+                url,
+                Resource {
+                    status: page.status,
+                    content_type: "text/html; charset=utf-8".to_string(),
+                    body: prepare_page(&html, url, &hiding, &document),
+                },
+            );
+            drop(pages);
+            self.call("loadProxiedPage", json!([tab, view, url, indexed, blocking]));
+        }
+        self.show_page(tab, navigation, Some(url.to_string()));
+        let title = extract_title(&html);
+        self.tabs.lock().unwrap().set_title(tab, target, &title);
+        self.data.history.lock().unwrap().set_title(target, &title);
+        if indexed {
+            if let Err(e) = self.index.index_page(url, &html) {
+                eprintln!("{}", e);
+            }
+        }
+    }
+
+    // Remembers what a navigation left the tab showing: the address of a web
+    // page, or None for anything the browser draws itself.
+    fn show_page(&self, tab: u64, navigation: u64, page: Option<String>) {
+        let mut tabs = self.tabs.lock().unwrap();
+        if tabs.is_current(tab, navigation) {
+            tabs.set_page(tab, page);
+        }
+    }
+
+    fn download(&self, url: &str, content_type: &str, tab: Option<u64>) {
+        self.start_download(url, content_type, tab, None);
+    }
+
+    // `stream` is the body of a response that is already being read, with
+    // its length if known, which is saved instead of requesting `url` again.
+    fn start_download(&self, url: &str, content_type: &str, tab: Option<u64>, stream: Option<DownloadStream>) {
+        let directory = self.settings().downloads.directory;
+        let started = self.data.downloads.lock().unwrap().start(url, &directory, content_type);
+        match started {
+            Ok((id, stop)) => {
+                if let Some(tab) = tab {
+                    let download = self.data.downloads.lock().unwrap().get(id).cloned();
+                    self.call("showDownloadStarted", json!([tab, download]));
+                }
+                self.spawn_download(id, stop, stream);
+            }
+            Err(e) => self.notify(&e),
+        }
+    }
+
+    // Transfers run on their own thread and only take the downloads lock to
+    // record progress, so pausing never waits on the network.
+    fn spawn_download(&self, id: u64, stop: Arc<AtomicBool>, stream: Option<DownloadStream>) {
+        self.send_downloads();
+        let context = self.clone();
+        thread::spawn(move || {
+            let Some(download) = context.data.downloads.lock().unwrap().get(id).cloned() else {
+                return;
+            };
+            let progress = |received, total| {
+                context.data.downloads.lock().unwrap().progress(id, received, total);
+                context.send_downloads();
+            };
+            let result = match stream {
+                Some((stream, total)) => receive(&download, stream, total, &stop, progress),
+                None => transfer(&context.settings(), &download, &stop, progress),
+            };
+            let message = match &result {
+                Ok(Some(_)) => Some(format!("Downloaded {}", download.path.display())),
+                Ok(None) => None,
+                Err(e) => Some(format!("Download failed: {}", e)),
+            };
+            context.data.downloads.lock().unwrap().finish(id, result);
+            context.send_downloads();
+            if let Some(message) = message {
+                context.notify(&message);
+            }
+        });
+    }
+
+    fn send_downloads(&self) {
+        let downloads = self.data.downloads.lock().unwrap();
+        self.broadcast("syncDownloads", json!([downloads.list()]));
+    }
+
+    fn handle_download(&self, msg: &NavigateMessage) {
+        let Some(id) = msg.id else { return };
+        match msg.action.as_str() {
+            "pause_download" => {
+                self.data.downloads.lock().unwrap().pause(id);
+            }
+            "resume_download" => {
+                let resumed = self.data.downloads.lock().unwrap().resume(id);
+                if let Some(stop) = resumed {
+                    self.spawn_download(id, stop, None);
+                }
+            }
+            "remove_download" => {
+                self.data.downloads.lock().unwrap().remove(id);
+                self.send_downloads();
+            }
+            _ => {}
+        }
+    }
+
+    fn send_cookies(&self) {
+        let cookies = self.data.cookies.lock().unwrap();
+        self.broadcast("syncCookies", json!([cookies.sites()]));
+    }
+
+    fn send_history_page(&self, tab: u64, query: &str) {
+        let history = self.data.history.lock().unwrap();
+        self.call("showHistoryPage", json!([tab, history.search(query, 500), query]));
+    }
+
+    fn open_internal_page(&self, tab: u64, page: &str, navigation: u64) {
+        if !self.is_current(tab, navigation) {
+            return;
+        }
+        match page {
+            "history" => self.send_history_page(tab, ""),
+            "bookmarks" => {
+                let bookmarks = self.bookmarks.lock().unwrap();
+                self.call("showBookmarksPage", json!([tab, bookmarks.list()]));
+            }
+            "settings" => {
+                let settings = self.settings();
+                let stripped = self.cleaner.lock().unwrap().stripped();
+                self.call("showSettingsPage", json!([tab, settings, stripped]));
+            }
+            "downloads" => {
+                let downloads = self.data.downloads.lock().unwrap();
+                self.call("showDownloadsPage", json!([tab, downloads.list()]));
+            }
+            "cookies" => {
+                let cookies = self.data.cookies.lock().unwrap();
+                self.call("showCookiesPage", json!([tab, cookies.sites()]));
+            }
+            "profile" => {
+                let storage: Vec<_> = self
+                    .profile
+                    .storage()
+                    .into_iter()
+                    .map(|(label, path)| json!({ "label": label, "path": path }))
+                    .collect();
+                let page = json!({
+                    "name": self.profile.name(),
+                    "private": self.private,
+                    "storage": storage,
+                });
+                self.call("showProfilePage", json!([tab, page]));
+            }
+            _ => {
+                let error = format!("Unknown page cypher://{}", page);
+                self.call("showError", json!([tab, error]));
+            }
+        }
+    }
+
+    fn handle_bookmark(&self, msg: NavigateMessage) -> Result<(), String> {
+        let mut bookmarks = self.bookmarks.lock().unwrap();
+        match msg.action.as_str() {
+            "add_bookmark" => {
+                let url = msg.url.ok_or("Missing bookmark url")?;
+                bookmarks.add(
+                    &url,
+                    msg.title.as_deref().unwrap_or(""),
+                    msg.folder.as_deref().unwrap_or(bookmarks::QUICK_LINKS_FOLDER),
+                    msg.tags.unwrap_or_default(),
+                    msg.favicon,
+                )?;
+            }
+            "update_bookmark" => {
+                let id = msg.id.ok_or("Missing bookmark id")?;
+                bookmarks.update(id, msg.title, msg.tags)?;
+                if let Some(folder) = msg.folder {
+                    bookmarks.move_to(id, &folder)?;
+                }
+            }
+            "remove_bookmark" => bookmarks.remove(msg.id.ok_or("Missing bookmark id")?)?,
+            "move_bookmark" => {
+                let id = msg.id.ok_or("Missing bookmark id")?;
+                bookmarks.move_to(id, msg.folder.as_deref().unwrap_or(""))?;
+            }
+            "create_bookmark_folder" => bookmarks.create_folder(msg.folder.as_deref().unwrap_or(""))?,
+            "remove_bookmark_folder" => bookmarks.remove_folder(msg.folder.as_deref().unwrap_or(""))?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // GET forms are ordinary navigations. A posted form's result is recorded
+    // under the action's address, so going back to it or reloading fetches
+    // that address again rather than sending the form a second time.
+    fn submit_form(&self, tab: u64, form: FormSubmission) {
+        let request = match form.to_request() {
+            Ok(request) => request,
+            Err(e) => {
+                self.notify(&e);
+                return;
+            }
+        };
+        let target = HistoryTarget::Url { url: request.url.clone() };
+        if !form.is_post() {
+            self.navigate(tab, target);
+            return;
+        }
+        self.tabs.lock().unwrap().record(tab, target.clone());
+        self.data.history.lock().unwrap().record(target.clone());
+        self.open_target_with(tab, target, Some(request));
+    }
+
+    pub fn open_tab(&self, url: Option<String>, after: Option<u64>, activate: bool) {
+        let tab = {
+            let mut tabs = self.tabs.lock().unwrap();
+            // A tab opened from another starts from the page that one shows,
+            // as when a link is opened in a new tab.
+            let opener = after.and_then(|after| tabs.get(after)).and_then(|t| t.page()).map(str::to_string);
+            let tab = tabs.open(after, activate);
+            tabs.set_page(tab, opener);
+            tab
+        };
+        self.send_tabs();
+        let target = match url {
+            Some(url) => HistoryTarget::Url { url },
+            None => HistoryTarget::Home,
+        };
+        self.navigate(tab, target);
+    }
+
+    fn restore_tabs(&self) {
+        self.send_tabs();
+        let tabs: Vec<_> = {
+            let tabs = self.tabs.lock().unwrap();
+            tabs.infos().into_iter().map(|info| (info.id, info.target)).collect()
+        };
+        for (tab, target) in tabs {
+            let context = self.clone();
+            thread::spawn(move || context.open_target(tab, target));
+        }
+    }
+
+    fn send_settings(&self) {
+        let settings = self.settings();
+        self.broadcast("applySettings", json!([settings]));
+    }
+
+    fn update_settings(&self, value: Option<Value>) -> Result<(), String> {
+        let settings = match value {
+            Some(value) => serde_json::from_value::<Settings>(value)
+                .map_err(|e| format!("Invalid settings: {}", e))?,
+            None => Settings::default(),
+        };
+        self.settings.lock().unwrap().replace(settings.clone())?;
+        self.send(BrowserEvent::ApplySettings(settings));
+        Ok(())
+    }
+
+    pub fn handle(&self, msg: NavigateMessage) {
+        let tab = msg.tab.unwrap_or_else(|| self.tabs.lock().unwrap().active());
+        match msg.action.as_str() {
+            "list_tabs" => self.restore_tabs(),
+            "new_tab" => self.open_tab(msg.url, msg.tab, true),
+            "open_in_background" => self.open_tab(msg.url, msg.tab, false),
+            "close_tab" => {
+                let last = self.tabs.lock().unwrap().len() < 2;
+                if last && self.windows.lock().unwrap().len() > 1 {
+                    self.send(BrowserEvent::CloseWindow(self.window));
+                    return;
+                }
+                self.tabs.lock().unwrap().close(tab);
+                self.send_tabs();
+            }
+            "new_window" => self.open_window(msg.url, false),
+            "new_private_window" => self.open_window(msg.url, true),
+            "close_window" => self.send(BrowserEvent::CloseWindow(self.window)),
+            "list_windows" => self.send_windows(),
+            "get_settings" => self.send_settings(),
+            "save_settings" | "reset_settings" => {
+                let value = if msg.action == "save_settings" { msg.settings } else { None };
+                match self.update_settings(value) {
+                    Ok(()) => {
+                        self.notify("Settings saved");
+                        self.send_settings();
+                    }
+                    Err(e) => self.notify(&e),
+                }
+            }
+            // A page's frame says it has loaded, naming the document it was
+            // served as; the address shown is where that document came from.
+            "frame_loaded" => {
+                let pages = self.data.pages.lock().unwrap();
+                let url = msg.document.and_then(|id| pages.document_url(&id).map(str::to_string));
+                drop(pages);
+                if let Some(url) = url {
+                    self.call("showFrameAddress", json!([tab, url]));
+                }
+            }
+            "scroll" => {
+                if let Some(scroll) = msg.scroll {
+                    self.tabs.lock().unwrap().set_scroll(tab, scroll);
+                }
+            }
+            "session_status" => {
+                let recovered = self.recovered_session.lock().unwrap().as_ref().map(|s| s.windows.len());
+                if let Some(count) = recovered {
+                    self.call("offerSessionRestore", json!([count]));
+                }
+            }
+            "restore_session" => self.restore_session(),
+            "discard_session" => {
+                if self.recovered_session.lock().unwrap().take().is_some() {
+                    self.windows.lock().unwrap().release_recovered();
+                }
+                self.broadcast("sessionRestoreHandled", Value::Null);
+            }
+            "move_tab" => self.move_tab(tab, msg.window),
+            "switch_tab" => {
+                self.tabs.lock().unwrap().activate(tab);
+                self.send_tabs();
+            }
+            "reorder_tab" => {
+                if let Some(index) = msg.index {
+                    self.tabs.lock().unwrap().reorder(tab, index);
+                }
+                self.send_tabs();
+            }
+            "list_bookmarks" => self.send_bookmarks(),
+            "add_bookmark" | "update_bookmark" | "remove_bookmark" | "move_bookmark"
+            | "create_bookmark_folder" | "remove_bookmark_folder" => {
+                if let Err(e) = self.handle_bookmark(msg) {
+                    self.notify(&e);
+                }
+                self.send_bookmarks();
+            }
+            "search" | "search_history" => {
+                if let Some(query) = msg.url {
+                    let provider = if msg.action == "search" {
+                        SearchProvider::DuckDuckGo
+                    } else {
+                        SearchProvider::Local
+                    };
+                    let page = msg.page.unwrap_or(1).max(1);
+                    self.navigate(tab, HistoryTarget::Search { provider, query, page });
+                }
+            }
+            "load_url" => {
+                if let Some(url) = msg.url {
+                    self.navigate(tab, HistoryTarget::Url { url });
+                }
+            }
+            "submit_form" => {
+                if let Some(form) = msg.form {
+                    self.submit_form(tab, form);
+                }
+            }
+            "go_home" => self.navigate(tab, HistoryTarget::Home),
+            "go_back" | "go_forward" | "reload" => {
+                let target = {
+                    let mut tabs = self.tabs.lock().unwrap();
+                    match msg.action.as_str() {
+                        "go_back" => tabs.back(tab),
+                        "go_forward" => tabs.forward(tab),
+                        _ => tabs.get(tab).and_then(|t| t.current()),
+                    }
+                };
+                if let Some(target) = target {
+                    self.open_target(tab, target);
+                }
+            }
+            "download" => {
+                if let Some(url) = &msg.url {
+                    self.download(url, "", None);
+                }
+            }
+            "list_downloads" => self.send_downloads(),
+            "pause_download" | "resume_download" | "remove_download" => self.handle_download(&msg),
+            "clear_cookies" => {
+                {
+                    let mut cookies = self.data.cookies.lock().unwrap();
+                    match &msg.url {
+                        Some(site) => cookies.clear_site(site),
+                        None => cookies.clear(),
+                    }
+                }
+                self.send_cookies();
+            }
+            "search_visits" => self.send_history_page(tab, msg.url.as_deref().unwrap_or("")),
+            "remove_visit" | "clear_history" => {
+                {
+                    let mut history = self.data.history.lock().unwrap();
+                    if msg.action == "clear_history" {
+                        history.clear();
+                        self.tabs.lock().unwrap().clear_history();
+                    } else if let Some(id) = msg.id {
+                        history.remove_visit(id);
+                    }
+                }
+                self.send_history_page(tab, msg.url.as_deref().unwrap_or(""));
+                self.send_tabs();
+            }
+            "exclude_site_from_index" | "include_site_in_index" => {
+                if let Some(url) = msg.url {
+                    let excluded = msg.action == "exclude_site_from_index";
+                    let mut policy = self.data.policy.lock().unwrap();
+                    policy.set_site_excluded(&url, excluded);
+                    // A private window's choice lasts as long as the private
+                    // windows do; its searches leave the site out instead.
+                    if let Some(path) = &self.data.policy_path {
+                        if let Err(e) = policy.save(path) {
+                            eprintln!("{}", e);
+                        }
+                        if excluded {
+                            if let Err(e) = self.index.remove_where(|doc| policy.is_site_excluded(&doc.url)) {
+                                eprintln!("{}", e);
+                            }
+                        }
+                    }
+                    self.call("setSiteIndexing", json!([tab, !excluded && !self.private]));
+                }
+            }
+            "block_on_site" | "allow_on_site" => {
+                if let Some(url) = msg.url {
+                    let allowed = msg.action == "allow_on_site";
+                    self.data.blocker.lock().unwrap().set_allowed(&url, allowed);
+                    // The page was filtered as it loaded, so it is loaded
+                    // again to show the difference.
+                    let current = self.tabs.lock().unwrap().get(tab).and_then(|t| t.current());
+                    if let Some(target) = current {
+                        self.open_target(tab, target);
+                    }
+                }
+            }
+            "reload_filters" => {
+                let filters = Arc::new(FilterSet::load_dir(&self.profile.filters_dir()));
+                let count = filters.len();
+                self.profile_data.blocker.lock().unwrap().set_filters(filters.clone());
+                if let Some(private) = self.private_data.lock().unwrap().as_ref() {
+                    private.blocker.lock().unwrap().set_filters(filters);
+                }
+                self.notify(&format!("Loaded {} filters", count));
+            }
+            "import" => {
+                if let Some(path) = msg.path {
+                    let result = if self.private {
+                        Err("Importing is disabled in private windows".to_string())
+                    } else {
+                        self.import(PathBuf::from(path), msg.format, msg.fetch.unwrap_or(false))
+                    };
+                    match result {
+                        Ok(count) => self.call("importFinished", json!([count, null])),
+                        Err(e) => self.call("importFinished", json!([0, e])),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+
+// How far into the document a `<meta charset>` is looked for, as in browsers.
+const PRESCAN_LIMIT: usize = 1024;
+
+// Picks the encoding a browser would: a byte order mark wins, then the HTTP
+// charset, then a `<meta>` declaration near the top, and failing all of those
+// a statistical guess from the bytes themselves.
+pub fn detect(body: &[u8], charset: Option<&str>, url: &str) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return encoding;
+    }
+    if let Some(encoding) = charset.and_then(|label| Encoding::for_label(label.trim().as_bytes())) {
+        return encoding;
+    }
+    if let Some(encoding) = prescan(&body[..body.len().min(PRESCAN_LIMIT)]) {
+        return encoding;
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(body, true);
+    detector.guess(tld(url).as_deref().map(str::as_bytes), true)
+}
+
+// Returns the page as UTF-8 along with the encoding it was read as.
+pub fn decode(body: &[u8], charset: Option<&str>, url: &str) -> (String, &'static Encoding) {
+    let (text, encoding, _) = detect(body, charset, url).decode(body);
+    (text.into_owned(), encoding)
+}
+
+fn prescan(head: &[u8]) -> Option<&'static Encoding> {
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+    let mut rest = head.as_str();
+    while let Some(start) = rest.find("<meta") {
+        let tag = &rest[start + 5..];
+        let end = tag.find('>').unwrap_or(tag.len());
+        if let Some(encoding) = meta_charset(&tag[..end]) {
+            // A page cannot really be UTF-16 if its ASCII markup was readable.
+            return Some(match encoding.name() {
+                "UTF-16LE" | "UTF-16BE" => UTF_8,
+                "x-user-defined" => WINDOWS_1252,
+                _ => encoding,
+            });
+        }
+        rest = &tag[end..];
+    }
+    None
+}
+
+// Covers both `<meta charset="...">` and the older
+// `<meta http-equiv="Content-Type" content="text/html; charset=...">`.
+fn meta_charset(attributes: &str) -> Option<&'static Encoding> {
+    let position = attributes.find("charset")?;
+    let value = attributes[position + 7..].trim_start().strip_prefix('=')?.trim_start();
+    let value = value.trim_start_matches(['"', '\'']);
+    let end = value
+        .find(|c: char| c == '"' || c == '\'' || c == ';' || c == '/' || c.is_whitespace())
+        .unwrap_or(value.len());
+    Encoding::for_label(&value.as_bytes()[..end])
+}
+
+// The detector leans towards the usual legacy encoding of a country domain.
+fn tld(url: &str) -> Option<String> {
+    let host = url::Url::parse(url).ok()?.host_str()?.to_ascii_lowercase();
+    let tld = host.rsplit('.').next()?;
+    (!tld.is_empty() && tld.chars().all(|c| c.is_ascii_alphabetic())).then(|| tld.to_string())
+}
@@ -0,0 +1,88 @@
+use std::path::PathBuf;
+use crate::settings::Fetcher;
+
+pub const USAGE: &str = "\
+Usage: cypher [OPTIONS] [URL]...
+
+Options:
+  --search <QUERY>       Search for QUERY at startup
+  --page <N>             Results page to show for --search (default 1)
+  --profile <NAME>       Use the named profile
+  --profile-dir <PATH>   Keep all profile data in PATH
+  --private              Open every window as a private window
+  --proxy <URL>          Proxy server to fetch pages through
+  --fetcher <KIND>       Fetch pages 'direct'ly or through the 'proxy'
+  --headless             Run without a window and print what was fetched
+  --dump-results         Print the --search results as JSON and exit
+  -h, --help             Show this help
+";
+
+#[derive(Default)]
+pub struct CliOptions {
+    pub urls: Vec<String>,
+    pub search: Option<String>,
+    pub page: u32,
+    pub profile: Option<String>,
+    pub profile_dir: Option<PathBuf>,
+    pub private: bool,
+    pub proxy: Option<String>,
+    pub fetcher: Option<Fetcher>,
+    pub headless: bool,
+    pub dump_results: bool,
+    pub help: bool,
+}
+
+impl CliOptions {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut options = Self { page: 1, ..Self::default() };
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+            match arg.as_str() {
+                "-h" | "--help" => options.help = true,
+                "--search" => options.search = Some(value("--search")?),
+                "--page" => {
+                    options.page = value("--page")?
+                        .parse()
+                        .ok()
+                        .filter(|page| *page > 0)
+                        .ok_or("--page needs a positive number")?;
+                }
+                "--profile" => options.profile = Some(value("--profile")?),
+                "--profile-dir" => options.profile_dir = Some(PathBuf::from(value("--profile-dir")?)),
+                "--private" => options.private = true,
+                "--proxy" => options.proxy = Some(value("--proxy")?),
+                "--fetcher" => {
+                    let kind = value("--fetcher")?;
+                    options.fetcher = Some(Fetcher::parse(&kind)
+                        .ok_or_else(|| format!("Unknown fetcher '{}', expected direct or proxy", kind))?);
+                }
+                "--headless" => options.headless = true,
+                "--dump-results" => options.dump_results = true,
+                _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+                _ => options.urls.push(normalize_url(&arg)),
+            }
+        }
+
+        if options.profile.is_some() && options.profile_dir.is_some() {
+            return Err("--profile and --profile-dir cannot be used together".to_string());
+        }
+        if options.dump_results && (options.search.is_none() || !options.urls.is_empty()) {
+            return Err("--dump-results needs --search and no URLs".to_string());
+        }
+        if options.headless && options.search.is_none() && options.urls.is_empty() {
+            return Err("--headless needs a URL or --search".to_string());
+        }
+        Ok(options)
+    }
+}
+
+// Lets `cypher example.com` work the same way as typing it in the address bar.
+fn normalize_url(arg: &str) -> String {
+    if arg.contains("://") {
+        arg.to_string()
+    } else {
+        format!("https://{}", arg)
+    }
+}
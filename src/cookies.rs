@@ -0,0 +1,477 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use crate::fetch::{open_hop, send_hop, Fetched, FetchedPage, PageRequest};
+use crate::profile::write_atomic;
+use crate::settings::Settings;
+
+const MAX_COOKIE_SIZE: usize = 4096;
+const MAX_COOKIES_PER_SITE: usize = 1000;
+// As many as reqwest would follow on its own.
+const MAX_REDIRECTS: usize = 10;
+
+// Two-letter country domains commonly sold one level further down, as in
+// example.co.uk.
+const SECOND_LEVEL_LABELS: [&str; 7] = ["ac", "co", "com", "edu", "gov", "net", "org"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+    // Without a Domain attribute a cookie goes back to its own host only.
+    pub host_only: bool,
+    pub path: String,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: SameSite,
+    // Seconds since the epoch. Session cookies have none and are never saved.
+    pub expires: Option<u64>,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    fn same_key(&self, other: &Cookie) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or("").to_ascii_lowercase();
+        let domain_ok = if self.host_only { host == self.domain } else { domain_matches(&host, &self.domain) };
+        domain_ok && path_matches(url.path(), &self.path) && (!self.secure || url.scheme() == "https")
+    }
+}
+
+#[derive(Serialize)]
+pub struct CookieSite<'a> {
+    pub site: &'a str,
+    pub cookies: &'a [Cookie],
+}
+
+// Cookies are kept apart by the site of the page the user was on, so a
+// tracker embedded in two sites sees two unrelated jars.
+#[derive(Default, Serialize, Deserialize)]
+pub struct CookieJar {
+    sites: BTreeMap<String, Vec<Cookie>>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl CookieJar {
+    pub fn load(path: PathBuf) -> Self {
+        let mut jar = if path.exists() {
+            fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read cookies: {}", e))
+                .and_then(|data| serde_json::from_str::<Self>(&data).map_err(|e| format!("Invalid cookies file: {}", e)))
+                .unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    Self::default()
+                })
+        } else {
+            Self::default()
+        };
+        jar.remove_expired(now());
+        jar.path = Some(path);
+        jar
+    }
+
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else { return };
+        let persistent: BTreeMap<&String, Vec<&Cookie>> = self
+            .sites
+            .iter()
+            .map(|(site, cookies)| (site, cookies.iter().filter(|c| c.expires.is_some()).collect::<Vec<_>>()))
+            .filter(|(_, cookies)| !cookies.is_empty())
+            .collect();
+        let result = serde_json::to_string_pretty(&serde_json::json!({ "sites": persistent }))
+            .map_err(|e| format!("Failed to serialize cookies: {}", e))
+            .and_then(|data| write_atomic(path, data.as_bytes()));
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+    }
+
+    fn remove_expired(&mut self, now: u64) {
+        for cookies in self.sites.values_mut() {
+            cookies.retain(|c| !c.is_expired(now));
+        }
+        self.sites.retain(|_, cookies| !cookies.is_empty());
+    }
+
+    // Applies every Set-Cookie header of a response from `url`, fetched while
+    // the user was on `top_level`.
+    pub fn store_response(&mut self, top_level: &str, url: &str, headers: &[(String, String)]) {
+        let (Ok(url), Some(site)) = (Url::parse(url), site_of_url(top_level)) else {
+            return;
+        };
+        let now = now();
+        let mut changed = false;
+        for (_, value) in headers.iter().filter(|(name, _)| name.eq_ignore_ascii_case("set-cookie")) {
+            if let Some(cookie) = parse_set_cookie(value, &url, now) {
+                changed |= self.insert(&site, cookie, now);
+            }
+        }
+        if changed {
+            self.save();
+        }
+    }
+
+    fn insert(&mut self, site: &str, cookie: Cookie, now: u64) -> bool {
+        let cookies = self.sites.entry(site.to_string()).or_default();
+        let existing = cookies.iter().position(|c| c.same_key(&cookie));
+        let changed = match (existing, cookie.is_expired(now)) {
+            (Some(index), true) => {
+                cookies.remove(index);
+                true
+            }
+            (None, true) => false,
+            (Some(index), false) => {
+                cookies[index] = cookie;
+                true
+            }
+            (None, false) => {
+                cookies.push(cookie);
+                if cookies.len() > MAX_COOKIES_PER_SITE {
+                    cookies.remove(0);
+                }
+                true
+            }
+        };
+        if cookies.is_empty() {
+            self.sites.remove(site);
+        }
+        changed
+    }
+
+    // The Cookie header for a request to `url`. `top_level` is the page the
+    // request is made from, or None when the request is that page, in which
+    // case `from` is the page the navigation started on, if any.
+    pub fn header_for(&mut self, top_level: Option<&str>, from: Option<&str>, url: &str, method: &str) -> Option<String> {
+        let url = Url::parse(url).ok()?;
+        let request_site = site_of(url.host_str()?);
+        let site = match top_level {
+            Some(top_level) => site_of_url(top_level)?,
+            None => request_site.clone(),
+        };
+        self.remove_expired(now());
+        let cookies = self.sites.get(&site)?;
+
+        // A navigation is same-site unless it started on a page of another
+        // site, as when a link is followed off it. Lax cookies still come
+        // along on GETs then; Strict ones do not.
+        let navigation = top_level.is_none();
+        let initiator = if navigation { from.and_then(site_of_url) } else { Some(site.clone()) };
+        let same_site = initiator.is_none_or(|initiator| initiator == request_site);
+        let safe = matches!(method, "GET" | "HEAD");
+        let mut matching: Vec<&Cookie> = cookies
+            .iter()
+            .filter(|c| c.matches(&url))
+            .filter(|c| match c.same_site {
+                SameSite::None => true,
+                SameSite::Lax => same_site || (navigation && safe),
+                SameSite::Strict => same_site,
+            })
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+        // Longer paths first; otherwise oldest first, which is the order
+        // they are kept in.
+        matching.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+        let pairs: Vec<String> = matching
+            .iter()
+            .map(|c| if c.name.is_empty() { c.value.clone() } else { format!("{}={}", c.name, c.value) })
+            .collect();
+        Some(pairs.join("; "))
+    }
+
+    pub fn sites(&self) -> Vec<CookieSite<'_>> {
+        let now = now();
+        self.sites
+            .iter()
+            .map(|(site, cookies)| CookieSite { site, cookies })
+            .filter(|site| site.cookies.iter().any(|c| !c.is_expired(now)))
+            .collect()
+    }
+
+    pub fn clear_site(&mut self, site: &str) {
+        if self.sites.remove(site).is_some() {
+            self.save();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sites.clear();
+        self.save();
+    }
+}
+
+// Sends `request` with the cookies the jar holds for it and keeps whatever it
+// sets. Redirects are followed here, one hop at a time, so a cookie set on
+// the way, such as the session a login form's redirect hands out, is kept and
+// sent on to where it leads.
+pub fn send_with_cookies(
+    settings: &Settings,
+    jar: &Mutex<CookieJar>,
+    request: &PageRequest,
+    top_level: Option<&str>,
+    mut progress: impl FnMut(u64, Option<u64>),
+) -> Result<FetchedPage, String> {
+    follow_redirects(jar, request, top_level, None, |request| send_hop(settings, request, &mut progress), |page| page)
+}
+
+// The same for navigating to a page, which may turn out to be a download,
+// from the page at `from`.
+pub fn open_with_cookies(
+    settings: &Settings,
+    jar: &Mutex<CookieJar>,
+    request: &PageRequest,
+    from: Option<&str>,
+    mut progress: impl FnMut(u64, Option<u64>),
+) -> Result<Fetched, String> {
+    follow_redirects(jar, request, None, from, |request| open_hop(settings, request, &mut progress), Fetched::page)
+}
+
+fn follow_redirects<T>(
+    jar: &Mutex<CookieJar>,
+    request: &PageRequest,
+    top_level: Option<&str>,
+    from: Option<&str>,
+    mut send: impl FnMut(&PageRequest) -> Result<T, String>,
+    page: impl Fn(&T) -> &FetchedPage,
+) -> Result<T, String> {
+    let mut request = request.clone();
+    let mut from = from.map(str::to_string);
+    for _ in 0..=MAX_REDIRECTS {
+        let response = send(&with_cookies(jar, &request, top_level, from.as_deref()))?;
+        store_cookies(jar, top_level, page(&response));
+        match request.redirected(page(&response)) {
+            Some(next) => {
+                // Where a redirect leads counts as reached from wherever the
+                // chain started, so a typed address cannot bounce a
+                // navigation into another site with its Strict cookies.
+                from.get_or_insert_with(|| request.url.clone());
+                request = next;
+            }
+            None => return Ok(response),
+        }
+    }
+    Err(format!("Too many redirects from {}", request.url))
+}
+
+fn with_cookies(jar: &Mutex<CookieJar>, request: &PageRequest, top_level: Option<&str>, from: Option<&str>) -> PageRequest {
+    let mut request = request.clone();
+    request.headers.retain(|(name, _)| !name.eq_ignore_ascii_case("cookie"));
+    if let Some(header) = jar.lock().unwrap().header_for(top_level, from, &request.url, &request.method) {
+        request.headers.push(("Cookie".to_string(), header));
+    }
+    request
+}
+
+// `page.url` is the address that answered, which for a proxy that follows
+// redirects itself is where they ended.
+fn store_cookies(jar: &Mutex<CookieJar>, top_level: Option<&str>, page: &FetchedPage) {
+    jar.lock().unwrap().store_response(top_level.unwrap_or(&page.url), &page.url, &page.headers);
+}
+
+// Without the public suffix list, a site is taken to be the last two labels
+// of the host, or three under suffixes such as co.uk. An IP address is a
+// site of its own.
+pub fn site_of(host: &str) -> String {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    if host.parse::<IpAddr>().is_ok() || host.starts_with('[') {
+        return host;
+    }
+    let labels: Vec<&str> = host.split('.').collect();
+    let keep = match labels.as_slice() {
+        [.., second, tld] if tld.len() == 2 && SECOND_LEVEL_LABELS.contains(second) => 3,
+        _ => 2,
+    };
+    labels[labels.len().saturating_sub(keep)..].join(".")
+}
+
+pub fn site_of_url(url: &str) -> Option<String> {
+    Url::parse(url).ok()?.host_str().map(site_of)
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host.as_bytes().get(host.len() - domain.len() - 1) == Some(&b'.')
+            && host.parse::<IpAddr>().is_err())
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path.as_bytes().get(cookie_path.len()) == Some(&b'/')))
+}
+
+// The directory of the request path, which is where a cookie without a Path
+// attribute applies.
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(end) => path[..end].to_string(),
+    }
+}
+
+pub fn parse_set_cookie(header: &str, url: &Url, now: u64) -> Option<Cookie> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    let mut parts = header.split(';');
+    let pair = parts.next()?;
+    let (name, value) = match pair.split_once('=') {
+        Some((name, value)) => (name.trim(), value.trim()),
+        None => ("", pair.trim()),
+    };
+    if (name.is_empty() && value.is_empty()) || name.len() + value.len() > MAX_COOKIE_SIZE {
+        return None;
+    }
+
+    let mut cookie = Cookie {
+        name: name.to_string(),
+        value: value.to_string(),
+        domain: host.clone(),
+        host_only: true,
+        path: default_path(url),
+        secure: false,
+        http_only: false,
+        same_site: SameSite::Lax,
+        expires: None,
+    };
+    let mut max_age = None;
+    let mut expires = None;
+    let mut domain = None;
+    for attribute in parts {
+        let (key, value) = match attribute.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (attribute.trim(), ""),
+        };
+        match key.to_ascii_lowercase().as_str() {
+            "expires" => expires = parse_cookie_date(value).or(expires),
+            "max-age" => {
+                if let Ok(seconds) = value.parse::<i64>() {
+                    max_age = Some(if seconds <= 0 { 0 } else { now.saturating_add(seconds as u64) });
+                }
+            }
+            "domain" => domain = Some(value.trim_start_matches('.').to_ascii_lowercase()).filter(|d| !d.is_empty()),
+            "path" => cookie.path = if value.starts_with('/') { value.to_string() } else { default_path(url) },
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            "samesite" => {
+                cookie.same_site = match value.to_ascii_lowercase().as_str() {
+                    "strict" => SameSite::Strict,
+                    "none" => SameSite::None,
+                    _ => SameSite::Lax,
+                }
+            }
+            _ => {}
+        }
+    }
+    cookie.expires = max_age.or(expires);
+
+    if let Some(domain) = domain {
+        // A cookie may be shared with the rest of its own site, but not with
+        // a different site or a whole suffix like co.uk.
+        if !domain_matches(&host, &domain) || !domain.ends_with(&site_of(&host)) || host.parse::<IpAddr>().is_ok() {
+            return None;
+        }
+        cookie.domain = domain;
+        cookie.host_only = false;
+    }
+    let https = url.scheme() == "https";
+    if cookie.secure && !https {
+        return None;
+    }
+    if cookie.same_site == SameSite::None && !cookie.secure {
+        return None;
+    }
+    if cookie.name.starts_with("__Secure-") && !cookie.secure {
+        return None;
+    }
+    if cookie.name.starts_with("__Host-") && !(cookie.secure && cookie.host_only && cookie.path == "/") {
+        return None;
+    }
+    Some(cookie)
+}
+
+// The lenient date format of RFC 6265, which takes the time, day, month and
+// year from wherever they appear.
+fn parse_cookie_date(value: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+    let (mut time, mut day, mut month, mut year) = (None, None, None, None);
+    let tokens = value
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == ':'))
+        .filter(|token| !token.is_empty());
+    for token in tokens {
+        let digits = token.bytes().all(|b| b.is_ascii_digit());
+        if time.is_none() && token.contains(':') {
+            let parts: Vec<u64> = token.split(':').filter_map(|part| part.parse().ok()).collect();
+            if let [hours, minutes, seconds] = parts[..] {
+                time = Some((hours, minutes, seconds));
+                continue;
+            }
+        }
+        if day.is_none() && digits && token.len() <= 2 {
+            day = token.parse::<u64>().ok();
+        } else if month.is_none() && token.len() >= 3 {
+            if let Some(index) = MONTHS.iter().position(|m| token[..3].eq_ignore_ascii_case(m)) {
+                month = Some(index as u64 + 1);
+            } else if year.is_none() && digits && token.len() == 4 {
+                year = token.parse::<u64>().ok();
+            }
+        } else if year.is_none() && digits && (2..=4).contains(&token.len()) {
+            year = token.parse::<u64>().ok();
+        }
+    }
+
+    let (hours, minutes, seconds) = time?;
+    let mut year = year?;
+    if year < 70 {
+        year += 2000;
+    } else if year < 100 {
+        year += 1900;
+    }
+    let (day, month) = (day?, month?);
+    if !(1..=31).contains(&day) || year < 1601 || hours > 23 || minutes > 59 || seconds > 59 {
+        return None;
+    }
+    let days = days_from_civil(year as i64, month as i64, day as i64);
+    let timestamp = days * 86400 + (hours * 3600 + minutes * 60 + seconds) as i64;
+    Some(timestamp.max(0) as u64)
+}
+
+// Days since 1970-01-01 in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
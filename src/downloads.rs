@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::profile::write_atomic;
+use crate::settings::{Fetcher, Settings};
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadState {
+    Downloading,
+    Paused,
+    Completed,
+    Failed,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Download {
+    pub id: u64,
+    pub url: String,
+    pub path: PathBuf,
+    pub content_type: String,
+    pub received: u64,
+    pub total: Option<u64>,
+    pub state: DownloadState,
+    pub error: Option<String>,
+    pub sha256: Option<String>,
+}
+
+impl Download {
+    // Bytes land in a side file until the transfer completes, so a half
+    // finished download is never mistaken for the real file.
+    pub fn part_path(&self) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(".part");
+        self.path.with_file_name(name)
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct DownloadManager {
+    downloads: Vec<Download>,
+    next_id: u64,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    #[serde(skip)]
+    running: HashMap<u64, Arc<AtomicBool>>,
+}
+
+impl DownloadManager {
+    // Downloads that were still running when the browser last exited are
+    // picked up as paused, from however much reached the disk.
+    pub fn load(path: PathBuf) -> Self {
+        let mut manager = if path.exists() {
+            fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read downloads: {}", e))
+                .and_then(|data| serde_json::from_str::<Self>(&data).map_err(|e| format!("Invalid downloads file: {}", e)))
+                .unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    Self::default()
+                })
+        } else {
+            Self::default()
+        };
+        for download in manager.downloads.iter_mut() {
+            if download.state == DownloadState::Downloading {
+                download.state = DownloadState::Paused;
+                download.received = fs::metadata(download.part_path()).map(|m| m.len()).unwrap_or(0);
+            }
+        }
+        manager.next_id = manager.next_id.max(manager.downloads.iter().map(|d| d.id + 1).max().unwrap_or(1));
+        manager.path = Some(path);
+        manager
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            next_id: 1,
+            ..Self::default()
+        }
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else { return };
+        let result = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize downloads: {}", e))
+            .and_then(|data| write_atomic(path, data.as_bytes()));
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+    }
+
+    pub fn list(&self) -> &[Download] {
+        &self.downloads
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Download> {
+        self.downloads.iter().find(|d| d.id == id)
+    }
+
+    fn get_mut(&mut self, id: u64) -> Option<&mut Download> {
+        self.downloads.iter_mut().find(|d| d.id == id)
+    }
+
+    pub fn start(&mut self, url: &str, dir: &Path, content_type: &str) -> Result<(u64, Arc<AtomicBool>), String> {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let path = self.unused_path(dir, &file_name(url, content_type));
+        let id = self.next_id;
+        self.next_id += 1;
+        self.downloads.push(Download {
+            id,
+            url: url.to_string(),
+            path,
+            content_type: content_type.to_string(),
+            received: 0,
+            total: None,
+            state: DownloadState::Downloading,
+            error: None,
+            sha256: None,
+        });
+        self.save();
+        Ok((id, self.run(id)))
+    }
+
+    fn unused_path(&self, dir: &Path, name: &str) -> PathBuf {
+        let name = Path::new(name);
+        let stem = name.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let extension = name.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+        (0..)
+            .map(|n| match n {
+                0 => dir.join(format!("{}{}", stem, extension)),
+                n => dir.join(format!("{} ({}){}", stem, n, extension)),
+            })
+            .find(|path| !path.exists() && !self.downloads.iter().any(|d| &d.path == path))
+            .unwrap()
+    }
+
+    fn run(&mut self, id: u64) -> Arc<AtomicBool> {
+        let stop = Arc::new(AtomicBool::new(false));
+        self.running.insert(id, stop.clone());
+        stop
+    }
+
+    pub fn resume(&mut self, id: u64) -> Option<Arc<AtomicBool>> {
+        if self.running.contains_key(&id) {
+            return None;
+        }
+        let download = self.get_mut(id)?;
+        if !matches!(download.state, DownloadState::Paused | DownloadState::Failed) {
+            return None;
+        }
+        download.state = DownloadState::Downloading;
+        download.error = None;
+        self.save();
+        Some(self.run(id))
+    }
+
+    pub fn pause(&mut self, id: u64) -> bool {
+        match self.running.get(&id) {
+            Some(stop) => {
+                stop.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Unfinished downloads take their partial file with them; finished ones
+    // only leave the list.
+    pub fn remove(&mut self, id: u64) -> bool {
+        self.pause(id);
+        let Some(position) = self.downloads.iter().position(|d| d.id == id) else {
+            return false;
+        };
+        let download = self.downloads.remove(position);
+        if download.state != DownloadState::Completed {
+            let _ = fs::remove_file(download.part_path());
+        }
+        self.save();
+        true
+    }
+
+    pub fn progress(&mut self, id: u64, received: u64, total: Option<u64>) {
+        if let Some(download) = self.get_mut(id) {
+            download.received = received;
+            download.total = total;
+        }
+    }
+
+    pub fn finish(&mut self, id: u64, result: Result<Option<String>, String>) {
+        self.running.remove(&id);
+        if let Some(download) = self.get_mut(id) {
+            match result {
+                Ok(Some(sha256)) => {
+                    download.state = DownloadState::Completed;
+                    download.sha256 = Some(sha256);
+                }
+                Ok(None) => download.state = DownloadState::Paused,
+                Err(e) => {
+                    download.state = DownloadState::Failed;
+                    download.error = Some(e);
+                }
+            }
+        }
+        self.save();
+    }
+}
+
+// Runs one download from wherever it left off until it completes, fails or
+// `stop` is set. Returns the SHA-256 of the finished file, or None if paused.
+pub fn transfer(
+    settings: &Settings,
+    download: &Download,
+    stop: &AtomicBool,
+    progress: impl FnMut(u64, Option<u64>),
+) -> Result<Option<String>, String> {
+    let part = download.part_path();
+    let offset = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+    let response = request(settings, &download.url, offset)?;
+
+    // A server that ignores the Range header sends the whole file again.
+    let resumed = offset > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+    let received = if resumed { offset } else { 0 };
+    let total = response.content_length().map(|length| length + received);
+    save_stream(download, response, received, total, stop, progress)
+}
+
+// Saves a response that was already under way when it turned out to be a
+// download, so it is not requested a second time. Resuming it later falls
+// back to `transfer`.
+pub fn receive(
+    download: &Download,
+    stream: impl Read,
+    total: Option<u64>,
+    stop: &AtomicBool,
+    progress: impl FnMut(u64, Option<u64>),
+) -> Result<Option<String>, String> {
+    save_stream(download, stream, 0, total, stop, progress)
+}
+
+// Writes `stream` to the part file, after the `received` bytes already in
+// it, and moves the file into place once it is complete.
+fn save_stream(
+    download: &Download,
+    mut stream: impl Read,
+    mut received: u64,
+    total: Option<u64>,
+    stop: &AtomicBool,
+    mut progress: impl FnMut(u64, Option<u64>),
+) -> Result<Option<String>, String> {
+    let part = download.part_path();
+    let mut file = if received > 0 {
+        OpenOptions::new().append(true).open(&part)
+    } else {
+        File::create(&part)
+    }
+    .map_err(|e| format!("Failed to open {}: {}", part.display(), e))?;
+    progress(received, total);
+
+    let mut buffer = vec![0; 64 * 1024];
+    let mut reported = Instant::now();
+    loop {
+        if stop.load(Ordering::SeqCst) {
+            progress(received, total);
+            return Ok(None);
+        }
+        let read = stream
+            .read(&mut buffer)
+            .map_err(|e| format!("Download interrupted: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buffer[..read])
+            .map_err(|e| format!("Failed to write {}: {}", part.display(), e))?;
+        received += read as u64;
+        if reported.elapsed() >= PROGRESS_INTERVAL {
+            progress(received, total);
+            reported = Instant::now();
+        }
+    }
+    progress(received, total);
+    if total.is_some_and(|total| received < total) {
+        return Err(format!("Connection closed after {} of {} bytes", received, total.unwrap_or(0)));
+    }
+
+    drop(file);
+    fs::rename(&part, &download.path)
+        .map_err(|e| format!("Failed to move download to {}: {}", download.path.display(), e))?;
+    sha256_file(&download.path).map(Some)
+}
+
+fn request(settings: &Settings, url: &str, offset: u64) -> Result<Response, String> {
+    let client = Client::builder()
+        .user_agent(settings.network.user_agent.as_str())
+        .connect_timeout(Duration::from_secs(30))
+        .build()
+        .map_err(|e| format!("Failed to create client: {}", e))?;
+
+    // The proxy's /download/ endpoint streams the body through untouched and
+    // passes Range on, unlike /fetch, which buffers the whole thing into one
+    // JSON reply within a size limit.
+    let request = match settings.network.fetcher {
+        Fetcher::Direct => client.get(url),
+        Fetcher::Proxy => client.get(settings.proxy_endpoint(&format!("/download/{}", URL_SAFE_NO_PAD.encode(url)))),
+    };
+    let request = if offset > 0 {
+        request.header(RANGE, format!("bytes={}-", offset))
+    } else {
+        request
+    };
+    request
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("Download failed: {}", e))
+}
+
+pub fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// The last path segment of the URL, with an extension from the content type
+// when the URL has none.
+fn file_name(url: &str, content_type: &str) -> String {
+    let segment = url::Url::parse(url)
+        .ok()
+        .and_then(|url| url.path_segments().and_then(|mut s| s.next_back().map(percent_decode)))
+        .unwrap_or_default();
+    let name: String = segment
+        .chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+        .collect();
+    let name = name.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    let name = if name.is_empty() { "download" } else { name };
+    if Path::new(name).extension().is_some() {
+        return name.to_string();
+    }
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    let extension = match mime.as_str() {
+        "application/pdf" => "pdf",
+        "application/zip" => "zip",
+        "application/gzip" => "gz",
+        "application/json" => "json",
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/svg+xml" => "svg",
+        "audio/mpeg" => "mp3",
+        "video/mp4" => "mp4",
+        _ => return name.to_string(),
+    };
+    format!("{}.{}", name, extension)
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                out.push(byte);
+                i += 3;
+            }
+            _ => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,584 @@
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use std::io::{Cursor, Read};
+use std::time::{Duration, Instant};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{redirect, Method};
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use crate::charset::decode;
+use crate::indexer::{tokenize, IndexPolicy, SharedIndex};
+use crate::settings::{Fetcher, Settings};
+use crate::snippet::{highlight, make_snippet};
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+// The largest response the proxy's `/fetch` passes on.
+const PROXY_MAX_RESPONSE_BYTES: u64 = 50 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchResult {
+    pub title: String,
+    pub url: String,
+    pub snippet: String,
+}
+
+// One request for a page: a plain GET for links and the address bar, or
+// whatever a submitted form asks for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageRequest {
+    pub method: String,
+    pub url: String,
+    pub content_type: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl PageRequest {
+    pub fn get(url: &str) -> Self {
+        Self {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            content_type: None,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    // The request a redirect response asks for next, if it is one. As in
+    // browsers, a 303, or a 301 or 302 after a POST, turns into a GET
+    // without the body; 307 and 308 repeat the request as it was.
+    pub fn redirected(&self, page: &FetchedPage) -> Option<PageRequest> {
+        if !matches!(page.status, 301 | 302 | 303 | 307 | 308) {
+            return None;
+        }
+        let url = url::Url::parse(&page.url).ok()?.join(page.header("location")?).ok()?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return None;
+        }
+        let mut next = self.clone();
+        next.url = url.to_string();
+        if (page.status == 303 && self.method != "HEAD") || (matches!(page.status, 301 | 302) && self.method == "POST") {
+            next.method = "GET".to_string();
+            next.content_type = None;
+            next.body.clear();
+        }
+        Some(next)
+    }
+}
+
+// What is posted to the proxy's `/fetch`. Proxies that only know `url`
+// ignore the rest and send a GET.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProxyRequest<'a> {
+    url: &'a str,
+    method: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: &'a Vec<(String, String)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    // "manual" asks for a redirect to be passed back instead of followed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redirect: Option<&'a str>,
+}
+
+// Rate limiting and malformed requests come back as a bare `{"error": ...}`
+// with no success flag. The body is base64 so binary and non-UTF-8 pages
+// survive the trip; proxies predating that send the page as a JSON string in
+// `content` instead.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProxyResponse {
+    #[serde(default)]
+    success: bool,
+    status: Option<u16>,
+    final_url: Option<String>,
+    content_type: Option<String>,
+    charset: Option<String>,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    body: Option<String>,
+    content: Option<String>,
+    error: Option<String>,
+}
+
+// Bodies go both ways in the URL-safe alphabet without padding, though
+// padded input is accepted too.
+const PROXY_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+#[derive(Debug)]
+pub struct FetchedPage {
+    pub status: u16,
+    // Where the request ended up after redirects.
+    pub url: String,
+    pub content_type: String,
+    pub charset: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl FetchedPage {
+    // Anything the content pane cannot show is handed to the download
+    // manager instead. A missing type is assumed to be a web page.
+    pub fn is_displayable(&self) -> bool {
+        let mime = self.mime();
+        mime.is_empty() || mime == "text/html" || mime == "application/xhtml+xml" || mime == "text/plain"
+    }
+
+    pub fn mime(&self) -> String {
+        self.content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // The body transcoded to UTF-8 from whatever encoding the page is in.
+    pub fn text(&self) -> String {
+        decode(&self.body, self.charset.as_deref(), &self.url).0
+    }
+}
+
+// A response once its headers have arrived. Pages are read in full, within
+// the size limit; anything the content pane cannot show is left unread for
+// the download manager, however large it is.
+pub enum Fetched {
+    Page(FetchedPage),
+    // `page.body` is empty and the content is still to be read from `body`.
+    File {
+        page: FetchedPage,
+        body: Box<dyn Read + Send>,
+        total: Option<u64>,
+    },
+}
+
+impl Fetched {
+    pub fn page(&self) -> &FetchedPage {
+        match self {
+            Self::Page(page) | Self::File { page, .. } => page,
+        }
+    }
+}
+
+// The `charset` parameter of a Content-Type header, if any.
+pub fn charset_of(content_type: &str) -> Option<String> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|charset| !charset.is_empty())
+}
+
+// `progress` is told how many bytes have arrived, and of how many if known,
+// a few times a second while the page comes in.
+pub fn fetch_page(
+    settings: &Settings,
+    url: &str,
+    progress: impl FnMut(u64, Option<u64>),
+) -> Result<FetchedPage, String> {
+    send_request(settings, &PageRequest::get(url), progress)
+}
+
+pub fn send_request(
+    settings: &Settings,
+    request: &PageRequest,
+    progress: impl FnMut(u64, Option<u64>),
+) -> Result<FetchedPage, String> {
+    match settings.network.fetcher {
+        Fetcher::Direct => fetch_direct(settings, request, progress),
+        Fetcher::Proxy => fetch_through_proxy(settings, request, progress),
+    }
+}
+
+// Like `send_request`, but a redirect comes back as it is rather than being
+// followed, so whoever sent the request can see what it set.
+pub fn send_hop(
+    settings: &Settings,
+    request: &PageRequest,
+    progress: impl FnMut(u64, Option<u64>),
+) -> Result<FetchedPage, String> {
+    match settings.network.fetcher {
+        Fetcher::Direct => read_direct(settings, request, false, progress),
+        Fetcher::Proxy => read_through_proxy(settings, request, false, progress),
+    }
+}
+
+// Decides between a page and a file from the headers before any of the body
+// is read. Redirects come back as they are, as from `send_hop`.
+pub fn open_hop(
+    settings: &Settings,
+    request: &PageRequest,
+    progress: impl FnMut(u64, Option<u64>),
+) -> Result<Fetched, String> {
+    match settings.network.fetcher {
+        Fetcher::Direct => open_direct(settings, request, progress),
+        Fetcher::Proxy => open_through_proxy(settings, request, progress),
+    }
+}
+
+pub fn fetch_direct(
+    settings: &Settings,
+    request: &PageRequest,
+    progress: impl FnMut(u64, Option<u64>),
+) -> Result<FetchedPage, String> {
+    read_direct(settings, request, true, progress)
+}
+
+fn read_direct(
+    settings: &Settings,
+    request: &PageRequest,
+    follow: bool,
+    mut progress: impl FnMut(u64, Option<u64>),
+) -> Result<FetchedPage, String> {
+    let (response, mut page) = send_direct(settings, request, follow)?;
+    if request.redirected(&page).is_some() {
+        return Ok(page);
+    }
+    let total = response.content_length();
+    page.body = read_body(response, settings.network.max_page_bytes(), total, &mut progress)
+        .map_err(|e| e.unwrap_or_else(|| too_large(settings)))?;
+    Ok(page)
+}
+
+fn open_direct(
+    settings: &Settings,
+    request: &PageRequest,
+    mut progress: impl FnMut(u64, Option<u64>),
+) -> Result<Fetched, String> {
+    let (response, mut page) = send_direct(settings, request, false)?;
+    if request.redirected(&page).is_some() {
+        return Ok(Fetched::Page(page));
+    }
+    let total = response.content_length();
+    if !page.is_displayable() {
+        return Ok(Fetched::File { page, body: Box::new(response), total });
+    }
+    page.body = read_body(response, settings.network.max_page_bytes(), total, &mut progress)
+        .map_err(|e| e.unwrap_or_else(|| too_large(settings)))?;
+    Ok(Fetched::Page(page))
+}
+
+// The response with its body still unread, and the page it describes
+// without a body.
+fn send_direct(settings: &Settings, request: &PageRequest, follow: bool) -> Result<(Response, FetchedPage), String> {
+    let client = Client::builder()
+        .user_agent(settings.network.user_agent.as_str())
+        .timeout(Duration::from_secs(30))
+        .redirect(if follow { redirect::Policy::default() } else { redirect::Policy::none() })
+        .build()
+        .map_err(|e| format!("Failed to create client: {}", e))?;
+
+    let method = Method::from_bytes(request.method.as_bytes())
+        .map_err(|_| format!("Invalid request method: {}", request.method))?;
+    let mut builder = client.request(method, &request.url);
+    for (name, value) in &request.headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    if let Some(content_type) = &request.content_type {
+        builder = builder.header(CONTENT_TYPE, content_type.as_str());
+    }
+    if !request.body.is_empty() {
+        builder = builder.body(request.body.clone());
+    }
+    let response = builder
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("Request failed: {}", e))?;
+    let status = response.status().as_u16();
+    let final_url = response.url().to_string();
+    let headers: Vec<(String, String)> = response
+        .headers()
+        .iter()
+        .map(|(name, value)| (name.as_str().to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned()))
+        .collect();
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    let page = FetchedPage {
+        status,
+        url: final_url,
+        charset: charset_of(&content_type),
+        content_type,
+        headers,
+        body: Vec::new(),
+    };
+    Ok((response, page))
+}
+
+pub fn fetch_through_proxy(
+    settings: &Settings,
+    request: &PageRequest,
+    progress: impl FnMut(u64, Option<u64>),
+) -> Result<FetchedPage, String> {
+    read_through_proxy(settings, request, true, progress)
+}
+
+fn read_through_proxy(
+    settings: &Settings,
+    request: &PageRequest,
+    follow: bool,
+    progress: impl FnMut(u64, Option<u64>),
+) -> Result<FetchedPage, String> {
+    let page = proxy_fetch(settings, request, follow, settings.network.max_page_bytes(), progress)?;
+    if page.body.len() as u64 > settings.network.max_page_bytes() {
+        return Err(too_large(settings));
+    }
+    Ok(page)
+}
+
+// The proxy sends the headers and the body in one reply, so a file is only
+// recognised once all of it has arrived. Files are held to the proxy's own
+// ceiling rather than the page size limit.
+fn open_through_proxy(
+    settings: &Settings,
+    request: &PageRequest,
+    progress: impl FnMut(u64, Option<u64>),
+) -> Result<Fetched, String> {
+    let limit = settings.network.max_page_bytes().max(PROXY_MAX_RESPONSE_BYTES);
+    let mut page = proxy_fetch(settings, request, false, limit, progress)?;
+    if page.is_displayable() || request.redirected(&page).is_some() {
+        if page.body.len() as u64 > settings.network.max_page_bytes() {
+            return Err(too_large(settings));
+        }
+        return Ok(Fetched::Page(page));
+    }
+    let body = std::mem::take(&mut page.body);
+    let total = Some(body.len() as u64);
+    Ok(Fetched::File { page, body: Box::new(Cursor::new(body)), total })
+}
+
+// `limit` is the largest body that is accepted. Proxies that predate
+// `redirect` follow redirects whatever `follow` says.
+fn proxy_fetch(
+    settings: &Settings,
+    request: &PageRequest,
+    follow: bool,
+    limit: u64,
+    mut progress: impl FnMut(u64, Option<u64>),
+) -> Result<FetchedPage, String> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .map_err(|e| format!("Failed to create client: {}", e))?;
+
+    let url = request.url.as_str();
+    let json_body = ProxyRequest {
+        url,
+        method: &request.method,
+        headers: &request.headers,
+        content_type: request.content_type.as_deref(),
+        body: (!request.body.is_empty()).then(|| PROXY_BASE64.encode(&request.body)),
+        redirect: (!follow).then_some("manual"),
+    };
+
+    match client
+        .post(settings.proxy_endpoint("/fetch"))
+        .json(&json_body)
+        .send(){
+        Ok(response) => {
+            let status = response.status();
+            // Base64 makes the body a third larger, plus a little for the
+            // headers and the rest of the JSON.
+            let limit = limit / 3 * 4 + 64 * 1024;
+            let total = response.content_length();
+            let response_bytes = read_body(response, limit, total, &mut progress)
+                .map_err(|e| e.unwrap_or_else(|| too_large(settings)))?;
+            let response_text = String::from_utf8_lossy(&response_bytes);
+
+            let json: ProxyResponse = serde_json::from_str(&response_text)
+                .map_err(|e| format!("Invalid JSON response ({}): {}", status, e))?;
+
+            if json.success {
+                return proxied_page(url, json);
+            }
+            let error = json.error.unwrap_or_else(|| "Unknown error".to_string());
+            Err(match status.as_u16() {
+                400 => format!("The proxy rejected the request: {}", error),
+                429 => format!("The proxy is rate limiting requests: {}", error),
+                _ => error,
+            })
+        }
+        Err(e) => Err(format!(
+            "Request failed: {}. Make sure your C++ server is running at {}",
+            e, settings.network.proxy_url
+        ))
+    }
+}
+
+// Reads the body in pieces so an oversized page is given up on as soon as it
+// passes `limit` rather than after it has all been buffered. Err(None) means
+// the limit was hit.
+fn read_body(
+    mut reader: impl Read,
+    limit: u64,
+    total: Option<u64>,
+    progress: &mut impl FnMut(u64, Option<u64>),
+) -> Result<Vec<u8>, Option<String>> {
+    if total.is_some_and(|total| total > limit) {
+        return Err(None);
+    }
+    let mut body = Vec::with_capacity(total.unwrap_or(0) as usize);
+    let mut buffer = vec![0; 64 * 1024];
+    let mut reported = Instant::now();
+    progress(0, total);
+    loop {
+        let read = reader
+            .read(&mut buffer)
+            .map_err(|e| Some(format!("Failed to read response: {}", e)))?;
+        if read == 0 {
+            break;
+        }
+        if (body.len() + read) as u64 > limit {
+            return Err(None);
+        }
+        body.extend_from_slice(&buffer[..read]);
+        if reported.elapsed() >= PROGRESS_INTERVAL {
+            progress(body.len() as u64, total);
+            reported = Instant::now();
+        }
+    }
+    progress(body.len() as u64, total);
+    Ok(body)
+}
+
+fn too_large(settings: &Settings) -> String {
+    format!(
+        "This page is larger than the {} MB limit. It can be raised in cypher://settings",
+        settings.network.max_page_mb
+    )
+}
+
+fn proxied_page(url: &str, json: ProxyResponse) -> Result<FetchedPage, String> {
+    let body = match (json.body, json.content) {
+        (Some(body), _) => PROXY_BASE64
+            .decode(body)
+            .map_err(|e| format!("Invalid body from the proxy: {}", e))?,
+        (None, content) => content.unwrap_or_default().into_bytes(),
+    };
+    let status = json.status.unwrap_or(200);
+    let final_url = json.final_url.filter(|u| !u.is_empty()).unwrap_or_else(|| url.to_string());
+    // Same outcome as `error_for_status` on the direct path.
+    if status >= 400 {
+        return Err(format!("Request failed: HTTP status {} for url ({})", status, final_url));
+    }
+    let content_type = json.content_type.unwrap_or_default();
+    Ok(FetchedPage {
+        status,
+        url: final_url,
+        charset: json.charset.filter(|c| !c.is_empty()).or_else(|| charset_of(&content_type)),
+        content_type,
+        headers: json.headers,
+        body,
+    })
+}
+
+pub fn fetch_search_results(settings: &Settings, query: &str, page: u32) -> Result<Vec<SearchResult>, String> {
+    let per_page = settings.search.results_per_page;
+    let offset = page.saturating_sub(1) as usize * per_page;
+    let mut search_url = url::Url::parse(&settings.search.results_url)
+        .map_err(|e| format!("Invalid search results URL: {}", e))?;
+    search_url
+        .query_pairs_mut()
+        .append_pair("q", query)
+        .append_pair("s", &offset.to_string())
+        .append_pair("dc", &(offset + 1).to_string());
+
+    let client = Client::builder()
+        .user_agent(settings.network.user_agent.as_str())
+        .build()
+        .map_err(|e| format!("Failed to create client: {}", e))?;
+
+    match client.get(search_url).send() {
+        Ok(response) => {
+            let charset = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(charset_of);
+            if let Ok(body) = response.bytes() {
+                let (html, _) = decode(&body, charset.as_deref(), &settings.search.results_url);
+                Ok(parse_results(&html, query, per_page))
+            } else {
+                Ok(Vec::new())
+            }
+        }
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+pub fn parse_results(html: &str, query: &str, limit: usize) -> Vec<SearchResult> {
+    let terms = tokenize(query).into_iter().collect();
+    let document = Html::parse_document(html);
+    let result_selector = Selector::parse(".result").unwrap();
+    let title_selector = Selector::parse(".result__a").unwrap();
+    let snippet_selector = Selector::parse(".result__snippet").unwrap();
+
+    let mut results = Vec::new();
+
+    for element in document.select(&result_selector).take(limit) {
+        if let Some(title_elem) = element.select(&title_selector).next() {
+            let title = title_elem.text().collect::<String>();
+            let url = title_elem.value().attr("href").unwrap_or("").to_string();
+
+            let snippet = element
+                .select(&snippet_selector)
+                .next()
+                .map(|e| e.text().collect::<String>())
+                .unwrap_or_default();
+
+            if !title.is_empty() && !url.is_empty() {
+                results.push(SearchResult {
+                    title: title.trim().to_string(),
+                    url: if url.starts_with("//") {
+                        format!("https:{}", url)
+                    } else {
+                        url
+                    },
+                    snippet: highlight(snippet.trim(), &terms),
+                });
+            }
+        }
+    }
+
+    results
+}
+
+// Sites excluded from a private window stay in the index until the profile
+// excludes them too, so they are left out here.
+pub fn search_history(
+    index: &SharedIndex,
+    policy: &IndexPolicy,
+    query: &str,
+    page: u32,
+    per_page: usize,
+) -> Vec<SearchResult> {
+    index.search(query)
+        .into_iter()
+        .filter(|doc| !policy.is_site_excluded(&doc.url))
+        .skip(page.saturating_sub(1) as usize * per_page)
+        .take(per_page)
+        .map(|doc| SearchResult {
+            snippet: make_snippet(&doc.content, query),
+            title: if doc.title.is_empty() { doc.url.clone() } else { doc.title },
+            url: doc.url,
+        })
+        .collect()
+}
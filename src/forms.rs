@@ -0,0 +1,189 @@
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use encoding_rs::{Encoding, UTF_8};
+use serde::{Deserialize, Serialize};
+use url::form_urlencoded;
+use url::Url;
+use crate::fetch::PageRequest;
+
+// A form the frame script caught on its way out of a proxied page, with the
+// action already turned back into the site's real address.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FormSubmission {
+    pub action: String,
+    #[serde(default)]
+    pub method: String,
+    #[serde(default)]
+    pub enctype: String,
+    #[serde(default)]
+    pub accept_charset: String,
+    #[serde(default)]
+    pub fields: Vec<FormField>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FormField {
+    pub name: String,
+    #[serde(default)]
+    pub value: String,
+    pub file: Option<FormFile>,
+}
+
+// A chosen file, read by the frame script and sent over base64-encoded.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FormFile {
+    pub name: String,
+    #[serde(default)]
+    pub content_type: String,
+    #[serde(default)]
+    pub data: String,
+}
+
+impl FormSubmission {
+    pub fn is_post(&self) -> bool {
+        self.method.eq_ignore_ascii_case("post")
+    }
+
+    // Encodes the fields the way a browser would for this method and enctype.
+    pub fn to_request(&self) -> Result<PageRequest, String> {
+        let mut url = Url::parse(&self.action).map_err(|e| format!("Invalid form action {}: {}", self.action, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("Forms cannot be sent to {}", self.action));
+        }
+        let encoding = self.encoding();
+        if !self.is_post() {
+            // A GET form replaces the action's query string with its fields.
+            url.set_query(Some(&urlencode(&self.fields, encoding)));
+            return Ok(PageRequest::get(url.as_str()));
+        }
+
+        url.set_fragment(None);
+        let (content_type, body) = match self.enctype.to_ascii_lowercase().as_str() {
+            "multipart/form-data" => {
+                let (boundary, body) = multipart(&self.fields, encoding)?;
+                (format!("multipart/form-data; boundary={}", boundary), body)
+            }
+            "text/plain" => ("text/plain".to_string(), plain(&self.fields, encoding)),
+            _ => (
+                "application/x-www-form-urlencoded".to_string(),
+                urlencode(&self.fields, encoding).into_bytes(),
+            ),
+        };
+        Ok(PageRequest {
+            method: "POST".to_string(),
+            url: url.to_string(),
+            content_type: Some(content_type),
+            headers: Vec::new(),
+            body,
+        })
+    }
+
+    // The first label in accept-charset that names a known encoding. Pages
+    // reach the frame as UTF-8, so that is the fallback.
+    fn encoding(&self) -> &'static Encoding {
+        self.accept_charset
+            .split([' ', ','])
+            .find_map(|label| Encoding::for_label(label.as_bytes()))
+            .map(|encoding| encoding.output_encoding())
+            .unwrap_or(UTF_8)
+    }
+}
+
+fn encode<'a>(text: &'a str, encoding: &'static Encoding) -> Cow<'a, [u8]> {
+    encoding.encode(text).0
+}
+
+// File fields are sent as just their file name, as browsers do when a form
+// with a file input is not multipart.
+fn field_value(field: &FormField) -> &str {
+    match &field.file {
+        Some(file) => &file.name,
+        None => &field.value,
+    }
+}
+
+fn urlencode(fields: &[FormField], encoding: &'static Encoding) -> String {
+    let to_bytes: &dyn Fn(&str) -> Cow<'_, [u8]> = &|text| encode(text, encoding);
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    serializer.encoding_override(Some(to_bytes));
+    for field in fields {
+        serializer.append_pair(&field.name, &normalize_newlines(field_value(field)));
+    }
+    serializer.finish()
+}
+
+fn plain(fields: &[FormField], encoding: &'static Encoding) -> Vec<u8> {
+    let mut body = Vec::new();
+    for field in fields {
+        body.extend_from_slice(&encode(&field.name, encoding));
+        body.push(b'=');
+        body.extend_from_slice(&encode(&normalize_newlines(field_value(field)), encoding));
+        body.extend_from_slice(b"\r\n");
+    }
+    body
+}
+
+fn multipart(fields: &[FormField], encoding: &'static Encoding) -> Result<(String, Vec<u8>), String> {
+    let mut parts = Vec::new();
+    for field in fields {
+        let name = encode(&escape_disposition(&field.name), encoding).into_owned();
+        let part = match &field.file {
+            Some(file) => {
+                let data = STANDARD
+                    .decode(&file.data)
+                    .map_err(|e| format!("Invalid contents for {}: {}", file.name, e))?;
+                let content_type = if file.content_type.is_empty() {
+                    "application/octet-stream"
+                } else {
+                    file.content_type.as_str()
+                };
+                let mut head = b"Content-Disposition: form-data; name=\"".to_vec();
+                head.extend_from_slice(&name);
+                head.extend_from_slice(b"\"; filename=\"");
+                head.extend_from_slice(&encode(&escape_disposition(&file.name), encoding));
+                head.extend_from_slice(format!("\"\r\nContent-Type: {}\r\n\r\n", content_type).as_bytes());
+                (head, data)
+            }
+            None => {
+                let mut head = b"Content-Disposition: form-data; name=\"".to_vec();
+                head.extend_from_slice(&name);
+                head.extend_from_slice(b"\"\r\n\r\n");
+                (head, encode(&normalize_newlines(&field.value), encoding).into_owned())
+            }
+        };
+        parts.push(part);
+    }
+
+    // Uploaded files can contain anything, so the boundary is checked
+    // against them rather than assumed to be unique.
+    let boundary = loop {
+        let boundary = format!("----CypherFormBoundary{:016x}", RandomState::new().build_hasher().finish());
+        let clashes = parts.iter().any(|(_, data)| {
+            data.windows(boundary.len()).any(|window| window == boundary.as_bytes())
+        });
+        if !clashes {
+            break boundary;
+        }
+    };
+    let mut body = Vec::new();
+    for (head, data) in parts {
+        body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        body.extend_from_slice(&head);
+        body.extend_from_slice(&data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    Ok((boundary, body))
+}
+
+// Quotes and line breaks cannot appear inside a Content-Disposition value.
+fn escape_disposition(value: &str) -> String {
+    value.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+fn normalize_newlines(value: &str) -> String {
+    value.replace("\r\n", "\n").replace('\r', "\n").replace('\n', "\r\n")
+}
@@ -0,0 +1,94 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::browser::{BrowserContext, BrowserEvent, NavigateMessage, ScriptCall};
+use crate::profile::Profile;
+use crate::settings::SettingsStore;
+use crate::windows::WindowManager;
+
+// Drives a BrowserContext without a window or webview. Messages go through
+// the same `handle` as the IPC handler and the script calls that would have
+// reached the chrome are collected instead.
+pub struct HeadlessBrowser {
+    context: BrowserContext,
+    events: Receiver<BrowserEvent>,
+}
+
+impl HeadlessBrowser {
+    pub fn open(profile: Profile, settings: SettingsStore, private: bool) -> Result<Self, String> {
+        let (sender, events) = mpsc::channel();
+        let sink = Arc::new(move |event| {
+            let _ = sender.send(event);
+        });
+        let context = BrowserContext::new(profile, settings, WindowManager::in_memory(), None, private, sink)?;
+        Ok(Self { context, events })
+    }
+
+    pub fn context(&self) -> &BrowserContext {
+        &self.context
+    }
+
+    pub fn send(&self, msg: NavigateMessage) {
+        self.context.handle(msg);
+    }
+
+    pub fn send_to(&self, window: u64, msg: NavigateMessage) -> Result<(), String> {
+        let tabs = self
+            .context
+            .windows()
+            .lock()
+            .unwrap()
+            .get(window)
+            .ok_or_else(|| format!("No window {}", window))?;
+        self.context.for_window(window, tabs).handle(msg);
+        Ok(())
+    }
+
+    pub fn send_json(&self, json: &str) -> Result<(), String> {
+        let msg = serde_json::from_str(json).map_err(|e| format!("Invalid message: {}", e))?;
+        self.send(msg);
+        Ok(())
+    }
+
+    // Returns the script calls made so far for any window. Window events are
+    // applied to the window manager the way the GUI event loop would.
+    pub fn calls(&self) -> Vec<(u64, ScriptCall)> {
+        let mut calls = Vec::new();
+        while let Ok(event) = self.events.try_recv() {
+            if let Some(call) = self.apply(event) {
+                calls.push(call);
+            }
+        }
+        calls
+    }
+
+    // Some actions finish on worker threads, so tests wait for the call they
+    // expect rather than reading whatever has arrived.
+    pub fn wait_for(&self, function: &str, timeout: Duration) -> Option<(u64, ScriptCall)> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.events.recv_timeout(remaining) {
+                Ok(event) => match self.apply(event) {
+                    Some((window, call)) if call.function == function => return Some((window, call)),
+                    _ => {}
+                },
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    fn apply(&self, event: BrowserEvent) -> Option<(u64, ScriptCall)> {
+        match event {
+            BrowserEvent::EvaluateScript(window, call) => return Some((window, call)),
+            BrowserEvent::OpenWindow(tabs, bounds, private) => {
+                self.context.windows().lock().unwrap().open(tabs, bounds, private);
+            }
+            BrowserEvent::CloseWindow(window) => {
+                self.context.close_window(window);
+            }
+            BrowserEvent::ApplySettings(_) => {}
+        }
+        None
+    }
+}
@@ -0,0 +1,234 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::profile::write_atomic;
+
+const MAX_VISITS: usize = 10_000;
+const MAX_STACK: usize = 100;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SearchProvider {
+    #[serde(rename = "duckduckgo")]
+    DuckDuckGo,
+    #[serde(rename = "local")]
+    Local,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HistoryTarget {
+    Home,
+    Search {
+        provider: SearchProvider,
+        query: String,
+        page: u32,
+    },
+    Url {
+        url: String,
+    },
+}
+
+impl HistoryTarget {
+    pub fn default_title(&self) -> String {
+        match self {
+            HistoryTarget::Home => "Home".to_string(),
+            HistoryTarget::Search { provider: SearchProvider::DuckDuckGo, query, .. } => format!("Search: {}", query),
+            HistoryTarget::Search { provider: SearchProvider::Local, query, .. } => format!("History: {}", query),
+            HistoryTarget::Url { url } => url.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub target: HistoryTarget,
+    pub title: String,
+    pub visited: u64,
+}
+
+impl HistoryEntry {
+    pub fn new(target: HistoryTarget) -> Self {
+        Self {
+            title: target.default_title(),
+            target,
+            visited: now(),
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct NavigationStack {
+    entries: Vec<HistoryEntry>,
+    position: usize,
+}
+
+impl NavigationStack {
+    pub fn push(&mut self, entry: HistoryEntry) {
+        if self.current().is_some_and(|current| current.target == entry.target) {
+            self.entries[self.position] = entry;
+            return;
+        }
+        if !self.entries.is_empty() {
+            self.entries.truncate(self.position + 1);
+        }
+        self.entries.push(entry);
+        if self.entries.len() > MAX_STACK {
+            self.entries.remove(0);
+        }
+        self.position = self.entries.len() - 1;
+    }
+
+    pub fn current(&self) -> Option<&HistoryEntry> {
+        self.entries.get(self.position)
+    }
+
+    pub fn back(&mut self) -> Option<&HistoryEntry> {
+        if !self.can_go_back() {
+            return None;
+        }
+        self.position -= 1;
+        self.current()
+    }
+
+    pub fn forward(&mut self) -> Option<&HistoryEntry> {
+        if !self.can_go_forward() {
+            return None;
+        }
+        self.position += 1;
+        self.current()
+    }
+
+    pub fn can_go_back(&self) -> bool {
+        self.position > 0
+    }
+
+    pub fn can_go_forward(&self) -> bool {
+        self.position + 1 < self.entries.len()
+    }
+
+    pub fn set_title(&mut self, target: &HistoryTarget, title: &str) {
+        for entry in self.entries.iter_mut().filter(|e| e.target == *target) {
+            entry.title = title.to_string();
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Visit {
+    pub id: u64,
+    #[serde(flatten)]
+    pub entry: HistoryEntry,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct History {
+    visits: Vec<Visit>,
+    next_id: u64,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    // Visits and titles are only written by `flush`, so browsing does not
+    // rewrite thousands of visits on every page.
+    #[serde(skip)]
+    unsaved: bool,
+}
+
+impl History {
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let mut history = if path.exists() {
+            let data = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read history: {}", e))?;
+            serde_json::from_str(&data).map_err(|e| format!("Invalid history file: {}", e))?
+        } else {
+            Self::default()
+        };
+        history.path = Some(path);
+        Ok(history)
+    }
+
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    pub fn flush(&mut self) {
+        if self.unsaved {
+            self.save();
+        }
+    }
+
+    fn save(&mut self) {
+        self.unsaved = false;
+        let Some(path) = &self.path else { return };
+        let result = serde_json::to_string(self)
+            .map_err(|e| format!("Failed to serialize history: {}", e))
+            .and_then(|data| write_atomic(path, data.as_bytes()));
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+    }
+
+    pub fn record(&mut self, target: HistoryTarget) {
+        if target == HistoryTarget::Home {
+            return;
+        }
+        self.visits.push(Visit { id: self.next_id, entry: HistoryEntry::new(target) });
+        self.next_id += 1;
+        if self.visits.len() > MAX_VISITS {
+            let excess = self.visits.len() - MAX_VISITS;
+            self.visits.drain(..excess);
+        }
+        self.unsaved = true;
+    }
+
+    pub fn set_title(&mut self, target: &HistoryTarget, title: &str) {
+        let title = title.trim();
+        if title.is_empty() {
+            return;
+        }
+        if let Some(visit) = self.visits.iter_mut().rev().find(|v| v.entry.target == *target) {
+            visit.entry.title = title.to_string();
+            self.unsaved = true;
+        }
+    }
+
+    pub fn search(&self, query: &str, limit: usize) -> Vec<&Visit> {
+        let terms: Vec<String> = query.split_whitespace().map(|t| t.to_lowercase()).collect();
+        self.visits
+            .iter()
+            .rev()
+            .filter(|visit| {
+                let haystack = match &visit.entry.target {
+                    HistoryTarget::Url { url } => format!("{} {}", visit.entry.title, url),
+                    HistoryTarget::Search { query, .. } => format!("{} {}", visit.entry.title, query),
+                    HistoryTarget::Home => visit.entry.title.clone(),
+                }
+                .to_lowercase();
+                terms.iter().all(|t| haystack.contains(t.as_str()))
+            })
+            .take(limit)
+            .collect()
+    }
+
+    pub fn remove_visit(&mut self, id: u64) {
+        self.visits.retain(|v| v.id != id);
+        self.save();
+    }
+
+    pub fn clear(&mut self) {
+        self.visits.clear();
+        self.save();
+    }
+}
+
+impl Drop for History {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,228 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use rusqlite::{Connection, OpenFlags};
+use scraper::{ElementRef, Html, Selector};
+use serde_json::Value;
+use crate::indexer::{document_from_html, Document, IndexPolicy, SharedIndex};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    NetscapeBookmarks,
+    FirefoxPlaces,
+    ChromiumHistory,
+    ChromiumBookmarks,
+}
+
+impl ImportFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "netscape" | "bookmarks_html" => Some(Self::NetscapeBookmarks),
+            "firefox" | "places" => Some(Self::FirefoxPlaces),
+            "chromium_history" => Some(Self::ChromiumHistory),
+            "chromium_bookmarks" => Some(Self::ChromiumBookmarks),
+            _ => None,
+        }
+    }
+
+    pub fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name == "places.sqlite" {
+            Some(Self::FirefoxPlaces)
+        } else if name == "history" {
+            Some(Self::ChromiumHistory)
+        } else if name == "bookmarks" || name.ends_with(".json") {
+            Some(Self::ChromiumBookmarks)
+        } else if name.ends_with(".html") || name.ends_with(".htm") {
+            Some(Self::NetscapeBookmarks)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn read_documents(path: &Path, format: ImportFormat, scratch: &Path) -> Result<Vec<Document>, String> {
+    match format {
+        ImportFormat::NetscapeBookmarks => {
+            let html = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            Ok(parse_netscape_bookmarks(&html))
+        }
+        ImportFormat::ChromiumBookmarks => {
+            let data = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            parse_chromium_bookmarks(&data)
+        }
+        ImportFormat::FirefoxPlaces => query_sqlite(
+            path,
+            scratch,
+            "SELECT p.url, COALESCE(p.title, (SELECT b.title FROM moz_bookmarks b \
+             WHERE b.fk = p.id AND b.title IS NOT NULL LIMIT 1), '') \
+             FROM moz_places p WHERE p.url LIKE 'http%' ORDER BY p.last_visit_date DESC",
+        ),
+        ImportFormat::ChromiumHistory => query_sqlite(
+            path,
+            scratch,
+            "SELECT url, COALESCE(title, '') FROM urls WHERE url LIKE 'http%' \
+             ORDER BY last_visit_time DESC",
+        ),
+    }
+}
+
+pub fn parse_netscape_bookmarks(html: &str) -> Vec<Document> {
+    let document = Html::parse_document(html);
+    let link_selector = Selector::parse("a[href]").unwrap();
+    let mut docs = Vec::new();
+
+    for link in document.select(&link_selector) {
+        let url = link.value().attr("href").unwrap_or("").trim().to_string();
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            continue;
+        }
+        let title = link.text().collect::<String>().trim().to_string();
+        let tags = link.value().attr("tags").unwrap_or("").replace(',', " ");
+        let folders = netscape_folders(link);
+
+        docs.push(metadata_document(url, title, &[folders.join(" "), tags]));
+    }
+    docs
+}
+
+// Bookmark exports nest each folder as <DT><H3>name</H3><DL>...</DL>, so the
+// folder path of a link is the H3 heading of every enclosing DT.
+fn netscape_folders(link: ElementRef) -> Vec<String> {
+    let mut folders: Vec<String> = link
+        .ancestors()
+        .filter_map(ElementRef::wrap)
+        .filter(|e| e.value().name() == "dt")
+        .filter_map(|dt| dt.child_elements().find(|c| c.value().name() == "h3"))
+        .map(|h3| h3.text().collect::<String>().trim().to_string())
+        .collect();
+    folders.reverse();
+    folders
+}
+
+pub fn parse_chromium_bookmarks(data: &str) -> Result<Vec<Document>, String> {
+    let json: Value = serde_json::from_str(data)
+        .map_err(|e| format!("Invalid bookmarks file: {}", e))?;
+    let roots = json
+        .get("roots")
+        .and_then(Value::as_object)
+        .ok_or_else(|| "Bookmarks file has no roots".to_string())?;
+
+    let mut docs = Vec::new();
+    for root in roots.values() {
+        collect_chromium_node(root, &mut Vec::new(), &mut docs);
+    }
+    Ok(docs)
+}
+
+fn collect_chromium_node(node: &Value, folders: &mut Vec<String>, docs: &mut Vec<Document>) {
+    let name = node.get("name").and_then(Value::as_str).unwrap_or("");
+    match node.get("type").and_then(Value::as_str) {
+        Some("url") => {
+            let url = node.get("url").and_then(Value::as_str).unwrap_or("");
+            if url.starts_with("http://") || url.starts_with("https://") {
+                docs.push(metadata_document(url.to_string(), name.to_string(), &[folders.join(" ")]));
+            }
+        }
+        Some("folder") => {
+            folders.push(name.to_string());
+            if let Some(children) = node.get("children").and_then(Value::as_array) {
+                for child in children {
+                    collect_chromium_node(child, folders, docs);
+                }
+            }
+            folders.pop();
+        }
+        _ => {}
+    }
+}
+
+// Browsers keep their databases locked while running, so read from a copy.
+fn query_sqlite(path: &Path, scratch: &Path, sql: &str) -> Result<Vec<Document>, String> {
+    let copy = scratch.join(format!("import-{}.sqlite", std::process::id()));
+    fs::copy(path, &copy).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let wal = PathBuf::from(format!("{}-wal", path.display()));
+    let wal_copy = PathBuf::from(format!("{}-wal", copy.display()));
+    if wal.exists() {
+        let _ = fs::copy(&wal, &wal_copy);
+    }
+
+    let result = (|| {
+        let conn = Connection::open_with_flags(&copy, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|e| format!("Failed to open database: {}", e))?;
+        let mut stmt = conn.prepare(sql)
+            .map_err(|e| format!("Unsupported database: {}", e))?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+            .map_err(|e| format!("Failed to query database: {}", e))?;
+
+        let mut docs = Vec::new();
+        for row in rows {
+            let (url, title) = row.map_err(|e| format!("Failed to read row: {}", e))?;
+            docs.push(metadata_document(url, title, &[]));
+        }
+        Ok(docs)
+    })();
+
+    let _ = fs::remove_file(&copy);
+    let _ = fs::remove_file(&wal_copy);
+    result
+}
+
+fn metadata_document(url: String, title: String, extra: &[String]) -> Document {
+    let mut content = title.clone();
+    for part in extra.iter().filter(|p| !p.trim().is_empty()) {
+        content.push(' ');
+        content.push_str(part.trim());
+    }
+    Document { id: 0, title, url, content }
+}
+
+pub fn import_into<F, P>(
+    index: &SharedIndex,
+    policy: &IndexPolicy,
+    docs: Vec<Document>,
+    fetch: Option<F>,
+    progress: P,
+) -> Result<usize, String>
+where
+    F: Fn(&str) -> Result<String, String>,
+    P: Fn(usize, usize),
+{
+    let mut docs: Vec<Document> = docs
+        .into_iter()
+        .filter(|d| policy.should_index(&d.url, false))
+        .collect();
+    docs.sort_by(|a, b| a.url.cmp(&b.url));
+    docs.dedup_by(|a, b| a.url == b.url);
+
+    let total = docs.len();
+    let mut imported = 0;
+    progress(0, total);
+
+    // The whole index is rewritten on every change, so everything goes in
+    // with one.
+    let mut pages = Vec::with_capacity(total);
+    for doc in docs {
+        let fetched = fetch.as_ref().and_then(|fetch| fetch(&doc.url).ok());
+        pages.push(match fetched {
+            Some(html) => {
+                let mut page = document_from_html(0, &doc.url, &html);
+                if page.title.is_empty() {
+                    page.title = doc.title;
+                }
+                page.content = format!("{} {}", doc.content, page.content);
+                page
+            }
+            None => doc,
+        });
+        imported += 1;
+        if fetch.is_some() {
+            progress(imported, total);
+        }
+    }
+    index.add_documents(pages)?;
+    progress(imported, total);
+    Ok(imported)
+}
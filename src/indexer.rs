@@ -1,59 +1,356 @@
-use std::collections::HashMap;
-use serde::{Deserialize, Serialize};
-
-#[derive(Clone, Serialize, Deserialize)]
-pub struct Document {
-    pub id: usize,
-    pub title: String,
-    pub url: String,
-    pub content: String,
-}
-
-pub struct SearchEngine {
-    documents: Vec<Document>,
-    index: HashMap<String, Vec<usize>>,
-}
-
-impl SearchEngine {
-    pub fn new() -> Self {
-        Self {
-            documents: Vec::new(),
-            index: HashMap::new(),
-        }
-    }
-
-    pub fn add_document(&mut self, doc: Document) {
-        let id = doc.id;
-        let words: Vec<String> = doc.content
-            .to_lowercase()
-            .split_whitespace()
-            .map(|s| s.to_string())
-            .collect();
-
-        for word in words {
-            self.index.entry(word).or_insert_with(Vec::new).push(id);
-        }
-
-        self.documents.push(doc);
-    }
-
-    pub fn search(&self, query: &str) -> Vec<Document> {
-        let query_lower = query.to_lowercase();
-        let terms: Vec<&str> = query_lower.split_whitespace().collect();
-        let mut doc_ids: Vec<usize> = Vec::new();
-
-        for term in terms {
-            if let Some(ids) = self.index.get(term) {
-                doc_ids.extend(ids);
-            }
-        }
-
-        doc_ids.sort();
-        doc_ids.dedup();
-
-        doc_ids.iter()
-            .filter_map(|&id| self.documents.iter().find(|d| d.id == id))
-            .cloned()
-            .collect()
-    }
-}
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use scraper::{ElementRef, Html, Node, Selector};
+use crate::profile::write_atomic;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Document {
+    pub id: usize,
+    pub title: String,
+    pub url: String,
+    pub content: String,
+}
+
+const SEGMENT_CAPACITY: usize = 256;
+
+#[derive(Clone, Default)]
+pub struct SearchEngine {
+    documents: Vec<Document>,
+    index: HashMap<String, Vec<usize>>,
+}
+
+impl SearchEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    pub fn add_document(&mut self, doc: Document) {
+        let id = doc.id;
+        let mut words: Vec<String> = tokenize(&doc.title);
+        words.extend(tokenize(&doc.content));
+        words.sort();
+        words.dedup();
+
+        for word in words {
+            self.index.entry(word).or_default().push(id);
+        }
+
+        self.documents.push(doc);
+    }
+
+    pub fn remove_where<F: Fn(&Document) -> bool>(&mut self, predicate: F) {
+        let removed: HashSet<usize> = self.documents
+            .iter()
+            .filter(|d| predicate(d))
+            .map(|d| d.id)
+            .collect();
+        if removed.is_empty() {
+            return;
+        }
+
+        self.documents.retain(|d| !removed.contains(&d.id));
+        for ids in self.index.values_mut() {
+            ids.retain(|id| !removed.contains(id));
+        }
+        self.index.retain(|_, ids| !ids.is_empty());
+    }
+
+    pub fn search(&self, query: &str) -> Vec<Document> {
+        let terms = tokenize(query);
+        let mut doc_ids: Vec<usize> = Vec::new();
+
+        for term in terms {
+            if let Some(ids) = self.index.get(&term) {
+                doc_ids.extend(ids);
+            }
+        }
+
+        doc_ids.sort();
+        doc_ids.dedup();
+
+        doc_ids.iter()
+            .filter_map(|&id| self.documents.iter().find(|d| d.id == id))
+            .cloned()
+            .collect()
+    }
+}
+
+// Readers grab the current snapshot and search it without holding any lock,
+// while a single writer at a time rebuilds only the segments it touches and
+// swaps in a new snapshot.
+#[derive(Clone)]
+pub struct SharedIndex {
+    snapshot: Arc<RwLock<Arc<Snapshot>>>,
+    writer: Arc<Mutex<IndexWriter>>,
+}
+
+// Rewriting the whole file for every page visited is too slow once the index
+// grows, so pages indexed while browsing are only written by `flush` or when
+// the last handle goes away.
+struct IndexWriter {
+    path: PathBuf,
+    unsaved: Option<Arc<Snapshot>>,
+}
+
+impl IndexWriter {
+    fn flush(&mut self) -> Result<(), String> {
+        match self.unsaved.take() {
+            Some(snapshot) => write_snapshot(&self.path, &snapshot),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for IndexWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Snapshot {
+    segments: Vec<Arc<SearchEngine>>,
+    next_id: usize,
+}
+
+impl Snapshot {
+    pub fn search(&self, query: &str) -> Vec<Document> {
+        self.segments
+            .iter()
+            .flat_map(|segment| segment.search(query))
+            .collect()
+    }
+
+    fn insert(&mut self, mut doc: Document) {
+        doc.id = self.next_id;
+        self.next_id += 1;
+
+        match self.segments.last_mut() {
+            Some(segment) if segment.len() < SEGMENT_CAPACITY => {
+                Arc::make_mut(segment).add_document(doc);
+            }
+            _ => {
+                let mut segment = SearchEngine::new();
+                segment.add_document(doc);
+                self.segments.push(Arc::new(segment));
+            }
+        }
+    }
+
+    fn remove_where<F: Fn(&Document) -> bool>(&mut self, predicate: F) {
+        for segment in self.segments.iter_mut() {
+            if segment.documents.iter().any(&predicate) {
+                Arc::make_mut(segment).remove_where(&predicate);
+            }
+        }
+        self.segments.retain(|segment| !segment.is_empty());
+    }
+}
+
+impl SharedIndex {
+    pub fn open(path: PathBuf) -> Result<Self, String> {
+        let mut snapshot = Snapshot::default();
+        if path.exists() {
+            for doc in read_documents(&path)? {
+                snapshot.insert(doc);
+            }
+        }
+
+        Ok(Self {
+            snapshot: Arc::new(RwLock::new(Arc::new(snapshot))),
+            writer: Arc::new(Mutex::new(IndexWriter { path, unsaved: None })),
+        })
+    }
+
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    pub fn search(&self, query: &str) -> Vec<Document> {
+        self.snapshot().search(query)
+    }
+
+    pub fn index_page(&self, url: &str, html: &str) -> Result<(), String> {
+        let doc = document_from_html(0, url, html);
+        self.update(false, |snapshot| {
+            snapshot.remove_where(|d| d.url == doc.url);
+            if !doc.content.is_empty() || !doc.title.is_empty() {
+                snapshot.insert(doc);
+            }
+        })
+    }
+
+    // Documents already in the index under the same address are replaced, all
+    // in one pass, and so are earlier ones in `docs`.
+    pub fn add_documents(&self, docs: Vec<Document>) -> Result<(), String> {
+        let mut latest: HashMap<String, usize> = HashMap::new();
+        for (position, doc) in docs.iter().enumerate() {
+            latest.insert(doc.url.clone(), position);
+        }
+        self.update(true, |snapshot| {
+            snapshot.remove_where(|d| latest.contains_key(&d.url));
+            for (position, doc) in docs.into_iter().enumerate() {
+                if latest.get(&doc.url) == Some(&position) {
+                    snapshot.insert(doc);
+                }
+            }
+        })
+    }
+
+    pub fn remove_where<F: Fn(&Document) -> bool>(&self, predicate: F) -> Result<(), String> {
+        self.update(true, |snapshot| snapshot.remove_where(predicate))
+    }
+
+    pub fn flush(&self) -> Result<(), String> {
+        self.writer.lock().unwrap().flush()
+    }
+
+    // Imports and removals are written at once: the user is waiting on the
+    // one, and the other may be taking a site out of the index for good.
+    fn update<F: FnOnce(&mut Snapshot)>(&self, save: bool, apply: F) -> Result<(), String> {
+        let mut writer = self.writer.lock().unwrap();
+        let mut next = (*self.snapshot()).clone();
+        apply(&mut next);
+        let next = Arc::new(next);
+        *self.snapshot.write().unwrap() = next.clone();
+
+        writer.unsaved = Some(next);
+        if save {
+            writer.flush()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn read_documents(path: &Path) -> Result<Vec<Document>, String> {
+    let data = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read index: {}", e))?;
+    serde_json::from_str(&data).map_err(|e| format!("Invalid index file: {}", e))
+}
+
+fn write_snapshot(path: &Path, snapshot: &Snapshot) -> Result<(), String> {
+    let documents: Vec<&Document> = snapshot.segments
+        .iter()
+        .flat_map(|segment| segment.documents.iter())
+        .collect();
+    let data = serde_json::to_string(&documents)
+        .map_err(|e| format!("Failed to serialize index: {}", e))?;
+    write_atomic(path, data.as_bytes())
+}
+
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+pub fn extract_title(html: &str) -> String {
+    title_of(&Html::parse_document(html))
+}
+
+fn title_of(document: &Html) -> String {
+    let title_selector = Selector::parse("title").unwrap();
+    document
+        .select(&title_selector)
+        .next()
+        .map(|e| e.text().collect::<Vec<_>>().join(" "))
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn document_from_html(id: usize, url: &str, html: &str) -> Document {
+    let document = Html::parse_document(html);
+    let mut text = String::new();
+    collect_visible_text(document.root_element(), &mut text);
+
+    Document {
+        id,
+        title: title_of(&document),
+        url: url.to_string(),
+        content: text.split_whitespace().collect::<Vec<_>>().join(" "),
+    }
+}
+
+fn collect_visible_text(element: ElementRef, out: &mut String) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(t) => {
+                out.push_str(t);
+                out.push(' ');
+            }
+            Node::Element(e) => {
+                if matches!(e.name(), "head" | "script" | "style" | "noscript" | "template" | "svg") {
+                    continue;
+                }
+                if let Some(child_element) = ElementRef::wrap(child) {
+                    collect_visible_text(child_element, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct IndexPolicy {
+    excluded_sites: HashSet<String>,
+}
+
+impl IndexPolicy {
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize index policy: {}", e))?;
+        write_atomic(path, data.as_bytes())
+    }
+
+    pub fn set_site_excluded(&mut self, url: &str, excluded: bool) {
+        if let Some(host) = site_of(url) {
+            if excluded {
+                self.excluded_sites.insert(host);
+            } else {
+                self.excluded_sites.remove(&host);
+            }
+        }
+    }
+
+    pub fn is_site_excluded(&self, url: &str) -> bool {
+        site_of(url).is_some_and(|host| self.excluded_sites.contains(&host))
+    }
+
+    pub fn should_index(&self, url: &str, private: bool) -> bool {
+        !private
+            && (url.starts_with("http://") || url.starts_with("https://"))
+            && !self.is_site_excluded(url)
+    }
+}
+
+fn site_of(url: &str) -> Option<String> {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.trim_start_matches("www.").to_lowercase()))
+}
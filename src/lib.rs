@@ -0,0 +1,22 @@
+pub mod blocking;
+pub mod bookmarks;
+pub mod browser;
+pub mod charset;
+pub mod cli;
+pub mod cookies;
+pub mod downloads;
+pub mod fetch;
+pub mod forms;
+pub mod headless;
+pub mod history;
+pub mod import;
+pub mod indexer;
+pub mod profile;
+pub mod protocol;
+pub mod rewrite;
+pub mod session;
+pub mod settings;
+pub mod snippet;
+pub mod tabs;
+pub mod urlclean;
+pub mod windows;
@@ -1,131 +1,225 @@
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 use tao::{
     event::{Event, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    event_loop::{ControlFlow, EventLoopBuilder, EventLoopWindowTarget},
+    window::{Window, WindowBuilder},
 };
-use wry::WebViewBuilder;
-use serde::{Deserialize, Serialize};
-use reqwest::blocking::Client;
-use scraper::{Html, Selector};
-
-#[derive(Serialize)]
-struct SearchResult {
-    title: String,
-    url: String,
-    snippet: String,
-}
-
-#[derive(Deserialize, Serialize)]
-struct NavigateMessage {
-    action: String,
-    url: Option<String>,
-}
-
-#[derive(Deserialize)]
-struct ProxyResponse {
-    success: bool,
-    content: Option<String>,
-    error: Option<String>,
-}
-
-fn fetch_through_proxy(url: &str) -> Result<String, String> {
-    let client = Client::builder()
-        .timeout(std::time::Duration::from_secs(30))
-        .build()
-        .map_err(|e| format!("Failed to create client: {}", e))?;
+use wry::http::header::CONTENT_TYPE;
+use wry::http::Response;
+use wry::{WebView, WebViewBuilder};
+use cypher::browser::{BrowserContext, BrowserEvent, EventSink, NavigateMessage};
+use cypher::cli::{CliOptions, USAGE};
+use cypher::downloads::{Download, DownloadState};
+use cypher::fetch::{PageRequest, SearchResult};
+use cypher::headless::HeadlessBrowser;
+use cypher::history::{HistoryTarget, SearchProvider};
+use cypher::profile::Profile;
+use cypher::protocol::{from_proxy_url, SCHEME};
+use cypher::session::WindowBounds;
+use cypher::settings::{Settings, SettingsOverrides, SettingsStore};
+use cypher::snippet::unhighlight;
+use cypher::windows::WindowManager;
 
-    let json_body = serde_json::json!({
-        "url": url
-    });
-
-    match client
-        .post("http://localhost:8080/fetch")
-        .json(&json_body)
-        .send(){
-        Ok(response) => {
-            let response_text = response.text()
-                .map_err(|e| format!("Failed to read response: {}", e))?;
-
-            let json: ProxyResponse = serde_json::from_str(&response_text)
-                .map_err(|e| format!("Invalid JSON response: {}", e))?;
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+// Progress is reported several times a second, so a minute without any
+// means the transfer has stalled.
+const DOWNLOAD_STALL_TIMEOUT: Duration = Duration::from_secs(60);
 
-            if json.success {
-                Ok(json.content.unwrap_or_default())
-            } else {
-                Err(json.error.unwrap_or_else(|| "Unknown error".to_string()))
+fn run_headless(options: &CliOptions, browser: &HeadlessBrowser) -> Result<(), String> {
+    if let Some(query) = &options.search {
+        browser.send(NavigateMessage {
+            action: "search".to_string(),
+            url: Some(query.clone()),
+            page: Some(options.page),
+            ..Default::default()
+        });
+        let calls = browser.calls();
+        let error = calls
+            .iter()
+            .find(|(_, call)| call.function == "showError")
+            .and_then(|(_, call)| call.args[1].as_str().map(str::to_string));
+        if let Some(e) = error {
+            return Err(e);
+        }
+        let results = calls
+            .into_iter()
+            .find(|(_, call)| call.function == "displayResults")
+            .map(|(_, call)| call.args[1].clone())
+            .unwrap_or_default();
+        let mut results: Vec<SearchResult> = serde_json::from_value(results).unwrap_or_default();
+        if options.dump_results {
+            // The chrome is sent snippets as HTML with the query marked.
+            for result in &mut results {
+                result.snippet = unhighlight(&result.snippet);
             }
+            let json = serde_json::to_string_pretty(&results)
+                .map_err(|e| format!("Failed to serialize results: {}", e))?;
+            println!("{}", json);
+            return Ok(());
+        }
+        for result in &results {
+            println!("{}\n  {}", result.title, result.url);
         }
-        Err(e) => Err(format!("Request failed: {}. Make sure your C++ server is running on port 8080", e))
     }
-}
 
-fn fetch_search_results(query: &str) -> Vec<SearchResult> {
-    let search_url = format!(
-        "https://html.duckduckgo.com/html/?q={}",
-        urlencoding::encode(query)
-    );
-
-    let client = Client::builder()
-        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36")
-        .build()
-        .unwrap();
-
-    match client.get(&search_url).send() {
-        Ok(response) => {
-            if let Ok(body) = response.text() {
-                parse_results(&body)
-            } else {
-                Vec::new()
+    let mut failed = 0;
+    for url in &options.urls {
+        browser.send(NavigateMessage {
+            action: "load_url".to_string(),
+            url: Some(url.clone()),
+            ..Default::default()
+        });
+        let calls = browser.calls();
+        let error = calls
+            .iter()
+            .find(|(_, call)| call.function == "showError")
+            .and_then(|(_, call)| call.args[1].as_str().map(str::to_string));
+        if let Some(e) = error {
+            eprintln!("{}: {}", url, e);
+            failed += 1;
+            continue;
+        }
+        let download = calls
+            .iter()
+            .find(|(_, call)| call.function == "showDownloadStarted")
+            .and_then(|(_, call)| call.args[1]["id"].as_u64());
+        if let Some(id) = download {
+            match wait_for_download(browser, id) {
+                Ok(download) => println!(
+                    "{}\t{}\t{}",
+                    url,
+                    download.path.display(),
+                    download.sha256.unwrap_or_default()
+                ),
+                Err(e) => {
+                    eprintln!("{}: {}", url, e);
+                    failed += 1;
+                }
             }
+            continue;
         }
-        Err(_) => Vec::new(),
+        let tabs = browser.context().tabs().lock().unwrap();
+        let title = tabs
+            .infos()
+            .into_iter()
+            .find(|info| info.id == tabs.active())
+            .map(|info| info.title)
+            .unwrap_or_default();
+        println!("{}\t{}", url, title);
     }
+    if failed > 0 {
+        return Err(format!("{} of {} pages could not be fetched", failed, options.urls.len()));
+    }
+    Ok(())
 }
 
-fn parse_results(html: &str) -> Vec<SearchResult> {
-    let document = Html::parse_document(html);
-    let result_selector = Selector::parse(".result").unwrap();
-    let title_selector = Selector::parse(".result__a").unwrap();
-    let snippet_selector = Selector::parse(".result__snippet").unwrap();
-
-    let mut results = Vec::new();
-
-    for element in document.select(&result_selector).take(15) {
-        if let Some(title_elem) = element.select(&title_selector).next() {
-            let title = title_elem.text().collect::<String>();
-            let url = title_elem.value().attr("href").unwrap_or("").to_string();
-
-            let snippet = element
-                .select(&snippet_selector)
-                .next()
-                .map(|e| e.text().collect::<String>())
-                .unwrap_or_default();
-
-            if !title.is_empty() && !url.is_empty() {
-                results.push(SearchResult {
-                    title: title.trim().to_string(),
-                    url: if url.starts_with("//") {
-                        format!("https:{}", url)
-                    } else {
-                        url
-                    },
-                    snippet: snippet.trim().to_string(),
-                });
+// Headless runs exit when they are done, so a download started along the way
+// has to finish first.
+fn wait_for_download(browser: &HeadlessBrowser, id: u64) -> Result<Download, String> {
+    let mut heard = true;
+    loop {
+        let download = browser.context().downloads().lock().unwrap().get(id).cloned();
+        match download {
+            Some(download) if download.state == DownloadState::Completed => return Ok(download),
+            Some(download) if download.state == DownloadState::Downloading => {
+                if !heard {
+                    return Err("Download stalled for a minute".to_string());
+                }
             }
+            Some(download) => return Err(download.error.unwrap_or_else(|| "Download stopped".to_string())),
+            None => return Err("Download was removed".to_string()),
         }
+        heard = browser.wait_for("syncDownloads", DOWNLOAD_STALL_TIMEOUT).is_some();
     }
-
-    results
 }
 
 fn main() {
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-        .with_title("Cypher Browser")
-        .with_inner_size(tao::dpi::LogicalSize::new(1920, 1080))
-        .build(&event_loop)
-        .unwrap();
+    let options = CliOptions::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        std::process::exit(2);
+    });
+    if options.help {
+        print!("{}", USAGE);
+        return;
+    }
+
+    let private = options.private;
+    let profile = match (&options.profile_dir, &options.profile) {
+        (Some(dir), _) => Profile::open_dir(dir.clone()),
+        (None, Some(name)) => Profile::open_named(name),
+        (None, None) => Profile::open_default(),
+    };
+    let profile = profile.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let overrides = SettingsOverrides {
+        proxy_url: options.proxy.clone(),
+        fetcher: options.fetcher,
+    };
+    let settings = if private {
+        SettingsStore::load_private(&profile.settings_path(), overrides)
+    } else {
+        SettingsStore::load(profile.settings_path(), overrides)
+    };
+    let settings = settings.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+
+    if options.headless || options.dump_results {
+        let result = HeadlessBrowser::open(profile, settings, private)
+            .and_then(|browser| {
+                let result = run_headless(&options, &browser);
+                browser.context().flush();
+                result
+            });
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let (windows, recovered_session) = if private {
+        (WindowManager::in_memory(), None)
+    } else {
+        WindowManager::load(profile.session_path())
+    };
+    let event_loop = EventLoopBuilder::<BrowserEvent>::with_user_event().build();
+    let proxy = Mutex::new(event_loop.create_proxy());
+    let sink: EventSink = Arc::new(move |event| {
+        let _ = proxy.lock().unwrap().send_event(event);
+    });
+    let context = BrowserContext::new(profile, settings, windows, recovered_session, private, sink)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+
+    let mut startup: Vec<HistoryTarget> = options
+        .urls
+        .iter()
+        .map(|url| HistoryTarget::Url { url: url.clone() })
+        .collect();
+    if let Some(query) = &options.search {
+        startup.push(HistoryTarget::Search {
+            provider: SearchProvider::DuckDuckGo,
+            query: query.clone(),
+            page: options.page,
+        });
+    }
+    context.open_startup(startup);
+
+    let autosave = context.clone();
+    thread::spawn(move || loop {
+        thread::sleep(SESSION_SAVE_INTERVAL);
+        autosave.windows().lock().unwrap().save(false);
+        autosave.flush();
+    });
 
     let home_html = r#"<!DOCTYPE html>
 <html>
@@ -163,6 +257,18 @@ fn main() {
             background-clip: text;
             margin-right: 10px;
         }
+        .private-badge {
+            background: #4c1d95;
+            border: 1px solid #7c3aed;
+            border-radius: 6px;
+            padding: 4px 10px;
+            font-size: 13px;
+            font-weight: 600;
+        }
+        body.private .top-bar {
+            background: linear-gradient(135deg, #2e1065 0%, #1e1b4b 100%);
+            border-bottom-color: #7c3aed;
+        }
         .nav-buttons { display: flex; gap: 8px; }
         .nav-btn {
             background: rgba(255,255,255,0.1);
@@ -215,6 +321,61 @@ fn main() {
             background: linear-gradient(to bottom, #0a0a0a 0%, #1a1a2e 100%);
             position: relative;
         }
+        .tab-strip {
+            display: flex;
+            align-items: flex-end;
+            gap: 4px;
+            padding: 6px 20px 0;
+            background: #10162b;
+            border-bottom: 1px solid #0f3460;
+            flex-shrink: 0;
+            overflow-x: auto;
+        }
+        .tab {
+            display: flex;
+            align-items: center;
+            gap: 8px;
+            min-width: 120px;
+            max-width: 220px;
+            padding: 8px 12px;
+            border-radius: 8px 8px 0 0;
+            background: rgba(255,255,255,0.05);
+            color: rgba(255,255,255,0.7);
+            cursor: pointer;
+            font-size: 13px;
+        }
+        .tab.active { background: rgba(102,126,234,0.25); color: #fff; }
+        .tab.loading .tab-title::before { content: '⟳ '; }
+        .tab-title { flex: 1; overflow: hidden; white-space: nowrap; text-overflow: ellipsis; }
+        .tab-close, .tab-new { background: none; border: none; color: rgba(255,255,255,0.5); cursor: pointer; font-size: 14px; }
+        .tab-new { font-size: 20px; padding: 0 10px 6px; }
+        .session-banner {
+            display: flex;
+            align-items: center;
+            gap: 15px;
+            padding: 10px 20px;
+            background: #16213e;
+            border-bottom: 1px solid #667eea;
+            flex-shrink: 0;
+        }
+        .session-banner[hidden] { display: none; }
+        .session-banner span { flex: 1; }
+        .tab-menu {
+            position: fixed;
+            background: #16213e;
+            border: 1px solid #0f3460;
+            border-radius: 8px;
+            padding: 6px 0;
+            display: flex;
+            flex-direction: column;
+            box-shadow: 0 10px 40px rgba(0,0,0,0.5);
+            z-index: 2500;
+        }
+        .tab-menu[hidden] { display: none; }
+        .tab-menu button { background: none; border: none; color: white; text-align: left; padding: 8px 16px; cursor: pointer; font-size: 13px; }
+        .tab-menu button:hover { background: rgba(102,126,234,0.25); }
+        .tab-pane { position: absolute; top: 0; left: 0; right: 0; bottom: 0; overflow-y: auto; }
+        .tab-pane[hidden] { display: none; }
         .home-screen {
             display: flex;
             flex-direction: column;
@@ -245,7 +406,7 @@ fn main() {
             margin-bottom: 60px;
             box-shadow: 0 10px 40px rgba(0,0,0,0.3);
         }
-        #searchInput {
+        .search-input {
             flex: 1;
             background: transparent;
             border: none;
@@ -253,7 +414,7 @@ fn main() {
             color: white;
             font-size: 18px;
             outline: none;}
-        #searchInput::placeholder { color: rgba(255,255,255,0.4); }
+        .search-input::placeholder { color: rgba(255,255,255,0.4); }
         .search-btn {
             background: linear-gradient(135deg, #667eea 0%, #764ba2 100%);
             border: none;
@@ -266,6 +427,21 @@ fn main() {
             transition: all 0.3s;
         }
         .search-btn:hover { transform: scale(1.05); box-shadow: 0 5px 30px rgba(102,126,234,0.5); }
+        .search-btn.secondary { background: rgba(255,255,255,0.1); margin-left: 10px; }
+        .nav-btn.off, .nav-btn:disabled { opacity: 0.4; }
+        .blocker-btn { position: relative; }
+        .blocked-count {
+            position: absolute;
+            top: -6px;
+            right: -6px;
+            min-width: 18px;
+            padding: 1px 4px;
+            border-radius: 9px;
+            background: #dc2626;
+            font-size: 11px;
+            font-weight: 700;
+        }
+        .pager { display: flex; justify-content: center; gap: 15px; margin-top: 10px; }
         .quick-links {
             display: grid;
             grid-template-columns: repeat(4, 1fr);
@@ -283,7 +459,58 @@ fn main() {
             transition: all 0.3s;
         }
         .quick-link:hover { background: rgba(255,255,255,0.08); border-color: #667eea; transform: translateY(-5px); }
-        .quick-link .icon { font-size: 48px; margin-bottom: 15px; }
+        .quick-link .icon { font-size: 48px; margin-bottom: 15px; height: 48px; display: flex; align-items: center; justify-content: center; }
+        .quick-link .icon img { width: 48px; height: 48px; border-radius: 8px; }
+        .manage-link { margin-top: 30px; color: rgba(255,255,255,0.5); cursor: pointer; text-decoration: underline; }
+        .bookmark-editor {
+            position: absolute;
+            top: 115px;
+            right: 20px;
+            width: 340px;
+            background: #16213e;
+            border: 1px solid #0f3460;
+            border-radius: 12px;
+            padding: 20px;
+            display: flex;
+            flex-direction: column;
+            gap: 10px;
+            box-shadow: 0 10px 40px rgba(0,0,0,0.5);
+            z-index: 2000;
+        }
+        .bookmark-editor[hidden], .toast[hidden] { display: none; }
+        .bookmark-editor input {
+            background: rgba(255,255,255,0.1);
+            border: 1px solid rgba(255,255,255,0.2);
+            padding: 8px 14px;
+            border-radius: 8px;
+            color: white;
+            outline: none;
+        }
+        .editor-actions { display: flex; gap: 8px; }
+        .go-btn.secondary { background: rgba(255,255,255,0.1); padding: 10px 16px; }
+        .toast {
+            position: fixed;
+            bottom: 30px;
+            left: 50%;
+            transform: translateX(-50%);
+            background: #16213e;
+            border: 1px solid #667eea;
+            padding: 12px 24px;
+            border-radius: 25px;
+            z-index: 3000;
+        }
+        .bookmark-folder { margin-bottom: 30px; }
+        .bookmark-folder h3 { color: rgba(255,255,255,0.9); margin-bottom: 12px; display: flex; justify-content: space-between; }
+        .bookmark-row { display: flex; align-items: center; gap: 12px; padding: 10px 15px; border-radius: 10px; background: rgba(255,255,255,0.05); margin-bottom: 8px; }
+        .bookmark-row .title { color: #667eea; cursor: pointer; flex: 1; }
+        .bookmark-row .tags { color: rgba(255,255,255,0.5); font-size: 13px; }
+        .bookmark-row select { background: rgba(255,255,255,0.1); color: white; border: 1px solid rgba(255,255,255,0.2); border-radius: 6px; }
+        .bookmark-row select option { color: black; }
+        .bookmark-row button, .bookmark-folder h3 button { background: none; border: none; color: rgba(255,255,255,0.5); cursor: pointer; font-size: 16px; }
+        .download-row .details { flex: 1; min-width: 0; display: flex; flex-direction: column; gap: 4px; }
+        .download-row .tags { overflow: hidden; text-overflow: ellipsis; white-space: nowrap; }
+        .download-row progress { width: 100%; height: 6px; }
+        .download-row .checksum { font-family: monospace; font-size: 12px; user-select: all; }
         .quick-link .name { color: white; font-weight: 600; font-size: 16px; }
         .loading {
             display: flex;
@@ -333,6 +560,29 @@ fn main() {
         .result-item h3 { color: #667eea; font-size: 22px; margin-bottom: 10px; font-weight: 600; }
         .result-item .url { color: #10b981; font-size: 14px; margin-bottom: 12px; word-break: break-all; }
         .result-item .snippet { color: rgba(255,255,255,0.7); line-height: 1.6; font-size: 15px; }
+        .result-item .snippet mark { background: rgba(102,126,234,0.35); color: #fff; border-radius: 3px; padding: 0 2px; }
+        .import-panel { max-width: 700px; margin: 0 auto; padding: 60px 20px; display: flex; flex-direction: column; gap: 16px; }
+        .import-panel input[type=text], .import-panel select {
+            background: rgba(255,255,255,0.1);
+            border: 1px solid rgba(255,255,255,0.2);
+            padding: 10px 20px;
+            border-radius: 25px;
+            color: white;
+            font-size: 14px;
+            outline: none;
+        }
+        .import-panel select option { color: black; }
+        .settings-panel input[type=number] {
+            background: rgba(255,255,255,0.1);
+            border: 1px solid rgba(255,255,255,0.2);
+            padding: 10px 20px;
+            border-radius: 25px;
+            color: white;
+            font-size: 14px;
+            outline: none;
+        }
+        .import-panel progress { width: 100%; height: 8px; }
+        .import-panel .status { color: rgba(255,255,255,0.7); }
         .iframe-container {
             width: 100%;
             height: 100%;
@@ -350,196 +600,1035 @@ fn main() {
 <body>
     <div class="top-bar">
         <div class="logo">🔮 CYPHER</div>
+        <div class="private-badge" id="privateBadge" title="History, cookies and downloads in this window are forgotten when it closes" hidden>🕶 Private</div>
         <div class="nav-buttons">
-            <button class="nav-btn" onclick="goBack()" title="Back">◄</button>
-            <button class="nav-btn" onclick="goForward()" title="Forward">►</button>
+            <button class="nav-btn" id="backButton" onclick="goBack()" title="Back" disabled>◄</button>
+            <button class="nav-btn" id="forwardButton" onclick="goForward()" title="Forward" disabled>►</button>
             <button class="nav-btn" onclick="reload()" title="Reload">↻</button>
             <button class="nav-btn" onclick="goHome()" title="Home">🏠</button>
+            <button class="nav-btn" id="indexToggle" onclick="toggleSiteIndexing()" title="Stop indexing this site">📇</button>
+            <button class="nav-btn blocker-btn off" id="blockToggle" onclick="toggleSiteBlocking()" title="No trackers blocked">🛡<span class="blocked-count" id="blockedCount" hidden></span></button>
+            <button class="nav-btn" onclick="showImport()" title="Import bookmarks and history">📥</button>
+            <button class="nav-btn" onclick="newPrivateWindow()" title="New private window">🕶</button>
+            <button class="nav-btn" data-url="cypher://downloads" onclick="navigateTo(this.dataset.url)" title="Downloads">⬇</button>
+            <button class="nav-btn" data-url="cypher://settings" onclick="navigateTo(this.dataset.url)" title="Settings">⚙</button>
         </div>
         <div class="url-bar">
             <input type="text" id="urlInput" placeholder="Search or enter URL..." />
+            <button class="nav-btn" id="bookmarkButton" onclick="toggleBookmarkEditor()" title="Bookmark this page">☆</button>
             <button class="go-btn" onclick="navigate()">Go</button>
         </div>
     </div>
-    <div class="content" id="content">
+    <div class="tab-strip" id="tabStrip"></div>
+    <div class="session-banner" id="sessionBanner" hidden>
+        <span id="sessionMessage"></span>
+        <button class="go-btn" onclick="restoreSession()">Restore</button>
+        <button class="go-btn secondary" onclick="discardSession()">Dismiss</button>
+    </div>
+    <div class="content" id="content"></div>
+    <template id="homeTemplate">
         <div class="home-screen">
             <div class="hero">
                 <h1>🔮 CYPHER</h1>
                 <p>Your Gateway to the Internet</p>
             </div>
             <div class="search-container">
-                <input type="text" id="searchInput" placeholder="What are you looking for?" />
+                <input type="text" class="search-input" placeholder="What are you looking for?" />
                 <button class="search-btn" onclick="performSearch()">Search</button>
+                <button class="search-btn secondary" onclick="performHistorySearch()" title="Search pages you have visited">History</button>
             </div>
-            <div class="quick-links">
-                <div class="quick-link" onclick="navigateTo('https://github.com')">
-                    <div class="icon">💻</div>
-                    <div class="name">GitHub</div>
-                </div>
-                <div class="quick-link" onclick="navigateTo('https://youtube.com')">
-                    <div class="icon">📺</div>
-                    <div class="name">YouTube</div>
-                </div>
-                <div class="quick-link" onclick="navigateTo('https://reddit.com')">
-                    <div class="icon">🗨️</div>
-                    <div class="name">Reddit</div>
-                </div>
-                <div class="quick-link" onclick="navigateTo('https://twitter.com')">
-                    <div class="icon">🐦</div>
-                    <div class="name">Twitter</div>
-                </div>
-            </div>
-        </div></div>
+            <div class="quick-links"></div>
+            <a class="manage-link" data-url="cypher://bookmarks" onclick="navigateTo(this.dataset.url)">All bookmarks</a>
+        </div>
+    </template>
+    <div class="bookmark-editor" id="bookmarkEditor" hidden>
+        <h3 id="bookmarkEditorTitle">Add bookmark</h3>
+        <input type="text" id="bookmarkTitle" placeholder="Title" />
+        <input type="text" id="bookmarkFolder" list="bookmarkFolders" placeholder="Folder" />
+        <datalist id="bookmarkFolders"></datalist>
+        <input type="text" id="bookmarkTags" placeholder="Tags, separated by commas" />
+        <div class="editor-actions">
+            <button class="go-btn" onclick="saveBookmark()">Save</button>
+            <button class="go-btn secondary" id="bookmarkRemove" onclick="removeCurrentBookmark()">Remove</button>
+            <button class="go-btn secondary" onclick="closeBookmarkEditor()">Cancel</button>
+        </div>
+    </div>
+    <div class="tab-menu" id="tabMenu" hidden></div>
+    <div class="toast" id="toast" hidden></div>
     <script>
-        const history = [];
-        let historyIndex = -1;
-        const homeContent = document.querySelector('.home-screen').outerHTML;
-        let isHome = true;
+        const homeContent = document.getElementById('homeTemplate').innerHTML;
+        const tabs = new Map();
+        let activeTab = null;
+        let tabInfos = [];
+        let draggedTab = null;
+        let windowIds = [];
+        let windowId = null;
+        let privateWindowIds = [];
+        let bookmarks = { folders: [], bookmarks: [] };
+        let settings = { search: { query_url: 'https://duckduckgo.com/?q={query}' } };
+        const QUICK_LINKS_FOLDER = 'Quick Links';
+        const LOADING_HTML = '<div class="loading"><div class="spinner"></div><h2>Loading...</h2></div>';
 
-        function addToHistory(url) {
-            if (historyIndex < history.length - 1) {
-                history.splice(historyIndex + 1);
+        function tabState(tabId) {
+            let state = tabs.get(tabId);
+            if (!state) {
+                const pane = document.createElement('div');
+                pane.className = 'tab-pane';
+                pane.hidden = tabId !== activeTab;
+                pane.addEventListener('scroll', () => reportScroll(tabId, pane.scrollTop));
+                document.getElementById('content').appendChild(pane);
+                state = { id: tabId, pane: pane, isHome: true, currentUrl: null, siteIndexed: true, label: '', pageOrigin: null, blocking: false, blockedCount: 0 };
+                tabs.set(tabId, state);
             }
-            history.push(url);
-            historyIndex++;
+            return state;
         }
 
-        function goBack() {
-            if (historyIndex > 0) {
-                historyIndex--;
-                const url = history[historyIndex];
-                if (url === 'HOME') {
-                    showHome();
-                } else if (url.startsWith('SEARCH:')) {
-                    const query = url.substring(7);
-                    performSearch(query, false);
-                } else {
-                    loadUrl(url, false);
+        function activeState() {
+            return tabState(activeTab);
+        }
+
+        function syncTabs(infos, activeId) {
+            const switched = activeTab !== activeId;
+            tabInfos = infos;
+            activeTab = activeId;
+            for (const [id, state] of tabs) {
+                if (!infos.some(t => t.id === id)) {
+                    state.pane.remove();
+                    tabs.delete(id);
                 }
             }
+            for (const info of infos) {
+                const isNew = !tabs.has(info.id);
+                const state = tabState(info.id);
+                if (isNew && info.target.kind === 'home') showHome(info.id);
+                state.pane.hidden = info.id !== activeId;
+            }
+            renderTabStrip();
+
+            const active = infos.find(t => t.id === activeId);
+            document.getElementById('backButton').disabled = !active || !active.can_go_back;
+            document.getElementById('forwardButton').disabled = !active || !active.can_go_forward;
+            if (switched) {
+                closeBookmarkEditor();
+                document.getElementById('urlInput').value = activeState().label;
+                updateBookmarkButton();
+                updateIndexToggle();
+                updateBlockToggle();
+            }
         }
 
-        function goForward() {
-            if (historyIndex < history.length - 1) {
-                historyIndex++;
-                const url = history[historyIndex];
-                if (url === 'HOME') {
-                    showHome();
-                } else if (url.startsWith('SEARCH:')) {
-                    const query = url.substring(7);
-                    performSearch(query, false);
-                } else {
-                    loadUrl(url, false);
-                }
+        function renderTabStrip() {
+            document.getElementById('tabStrip').innerHTML = tabInfos.map((t, index) => `
+                <div class="tab ${t.id === activeTab ? 'active' : ''} ${t.loading ? 'loading' : ''}" draggable="true"
+                     title="${escapeHtml(t.title)}" onclick="switchTab(${t.id})"
+                     onauxclick="if (event.button === 1) closeTab(${t.id})" oncontextmenu="showTabMenu(event, ${t.id})"
+                     ondragstart="draggedTab = ${t.id}" ondragover="event.preventDefault()" ondrop="dropTab(event, ${index})">
+                    <span class="tab-title">${escapeHtml(t.target.kind === 'home' ? 'New Tab' : t.title)}</span>
+                    <button class="tab-close" onclick="event.stopPropagation(); closeTab(${t.id})" title="Close tab">✕</button>
+                </div>
+            `).join('') + '<button class="tab-new" onclick="newTab()" title="New tab">+</button>';
+        }
+
+        function syncWindows(ids, current, privateIds) {
+            windowIds = ids;
+            windowId = current;
+            privateWindowIds = privateIds;
+            const isPrivate = privateIds.includes(current);
+            document.body.classList.toggle('private', isPrivate);
+            document.getElementById('privateBadge').hidden = !isPrivate;
+        }
+
+        function showTabMenu(event, id) {
+            event.preventDefault();
+            // Tabs can only move between windows on the same side of private browsing.
+            const isPrivate = privateWindowIds.includes(windowId);
+            const others = windowIds.filter(w => w !== windowId && privateWindowIds.includes(w) === isPrivate);
+            const menu = document.getElementById('tabMenu');
+            menu.innerHTML = `
+                <button onclick="moveTab(${id}, null)">Move to new window</button>
+                ${others.map(w => `<button onclick="moveTab(${id}, ${w})">Move to window ${windowIds.indexOf(w) + 1}</button>`).join('')}
+                <button onclick="closeTab(${id})">Close tab</button>
+            `;
+            menu.style.left = event.clientX + 'px';
+            menu.style.top = event.clientY + 'px';
+            menu.hidden = false;
+        }
+
+        function closeTabMenu() {
+            document.getElementById('tabMenu').hidden = true;
+        }
+
+        function moveTab(id, destination) {
+            closeTabMenu();
+            window.ipc.postMessage(JSON.stringify({ action: 'move_tab', tab: id, window: destination }));
+        }
+
+        function reportScroll(tabId, y) {
+            const state = tabState(tabId);
+            clearTimeout(state.scrollTimer);
+            state.scrollTimer = setTimeout(() => {
+                window.ipc.postMessage(JSON.stringify({ action: 'scroll', tab: tabId, scroll: Math.round(y) }));
+            }, 500);
+        }
+
+        function restoreScroll(tabId, y) {
+            const pane = tabState(tabId).pane;
+            const iframe = pane.querySelector('.iframe-container iframe');
+            if (!iframe) {
+                pane.scrollTop = y;
+                return;
             }
+            const apply = () => iframe.contentWindow.postMessage({ cypher: true, type: 'scroll-to', y: y }, '*');
+            if (iframe.dataset.loaded) apply();
+            else iframe.addEventListener('load', apply, { once: true });
         }
 
-        function reload() {
-            if (isHome) {
-                showHome();
-            } else if (historyIndex >= 0 && history[historyIndex]) {
-                const url = history[historyIndex];
-                if (url.startsWith('SEARCH:')) {
-                    const query = url.substring(7);
-                    performSearch(query, false);
-                } else {
-                    loadUrl(url, false);
-                }
+        function offerSessionRestore(windowCount) {
+            document.getElementById('sessionMessage').textContent = windowCount === 1
+                ? 'Cypher did not shut down correctly. Restore your last session?'
+                : `Cypher did not shut down correctly. Restore your last session (${windowCount} windows)?`;
+            document.getElementById('sessionBanner').hidden = false;
+        }
+
+        function sessionRestoreHandled() {
+            document.getElementById('sessionBanner').hidden = true;
+        }
+
+        function restoreSession() {
+            sessionRestoreHandled();
+            window.ipc.postMessage(JSON.stringify({ action: 'restore_session' }));
+        }
+
+        function discardSession() {
+            sessionRestoreHandled();
+            window.ipc.postMessage(JSON.stringify({ action: 'discard_session' }));
+        }
+
+        function newWindow(url = null) {
+            window.ipc.postMessage(JSON.stringify({ action: 'new_window', url: url }));
+        }
+
+        function newPrivateWindow(url = null) {
+            window.ipc.postMessage(JSON.stringify({ action: 'new_private_window', url: url }));
+        }
+
+        function newTab(url = null) {
+            window.ipc.postMessage(JSON.stringify({ action: 'new_tab', tab: activeTab, url: url }));
+        }
+
+        function openInBackground(url) {
+            window.ipc.postMessage(JSON.stringify({ action: 'open_in_background', tab: activeTab, url: url }));
+        }
+
+        function switchTab(id) {
+            if (id !== activeTab) window.ipc.postMessage(JSON.stringify({ action: 'switch_tab', tab: id }));
+        }
+
+        function closeTab(id) {
+            window.ipc.postMessage(JSON.stringify({ action: 'close_tab', tab: id }));
+        }
+
+        function dropTab(event, index) {
+            event.preventDefault();
+            if (draggedTab !== null) {
+                window.ipc.postMessage(JSON.stringify({ action: 'reorder_tab', tab: draggedTab, index: index }));
+                draggedTab = null;
             }
         }
 
+        function goBack() {
+            window.ipc.postMessage(JSON.stringify({ action: 'go_back', tab: activeTab }));
+        }
+
+        function goForward() {
+            window.ipc.postMessage(JSON.stringify({ action: 'go_forward', tab: activeTab }));
+        }
+
+        function reload() {
+            window.ipc.postMessage(JSON.stringify({ action: 'reload', tab: activeTab }));
+        }
+
         function goHome() {
-            showHome();
-            addToHistory('HOME');
+            showHome(activeTab);
+            window.ipc.postMessage(JSON.stringify({ action: 'go_home', tab: activeTab }));
         }
 
-        function showHome() {
-            isHome = true;
-            document.getElementById('content').innerHTML = homeContent;
-            document.getElementById('urlInput').value = '';
+        function describeTarget(target) {
+            switch (target.kind) {
+                case 'home': return '';
+                case 'url': return target.url;
+                case 'search': {
+                    const label = target.provider === 'local' ? 'History' : 'Search';
+                    return target.page > 1 ? `${label}: ${target.query} (page ${target.page})` : `${label}: ${target.query}`;
+                }
+            }
+            return '';
+        }
 
-            const searchInput = document.getElementById('searchInput');
-            if (searchInput) {
-                searchInput.addEventListener('keypress', (e) => {
-                    if (e.key === 'Enter') performSearch();
-                });
+        function beginNavigation(tabId, target) {
+            const state = tabState(tabId);
+            if (tabId === activeTab) closeBookmarkEditor();
+            setPageBlocking(tabId, null, false);
+            if (target.kind === 'home') {
+                showHome(tabId);
+                return;
             }
+            state.isHome = false;
+            state.currentUrl = target.kind === 'url' ? target.url : null;
+            state.label = describeTarget(target);
+            if (tabId === activeTab) {
+                document.getElementById('urlInput').value = state.label;
+                updateBookmarkButton();
+            }
+            state.pending = null;
+            state.pane.innerHTML = LOADING_HTML;
+        }
+
+        function showHome(tabId) {
+            const state = tabState(tabId);
+            state.isHome = true;
+            state.currentUrl = null;
+            state.label = '';
+            state.pane.innerHTML = homeContent;
+            renderQuickLinks();
+            if (tabId === activeTab) {
+                document.getElementById('urlInput').value = '';
+                updateBookmarkButton();
+            }
+
+            state.pane.querySelector('.search-input').addEventListener('keypress', (e) => {
+                if (e.key === 'Enter') performSearch();
+            });
         }
 
         function navigate() {
             const input = document.getElementById('urlInput').value.trim();
             if (input) {
                 let url = input;
-                if (!url.startsWith('http://') && !url.startsWith('https://')) {
-                    url = url.includes('.') && !url.includes(' ') ? 'https://' + url : 'https://duckduckgo.com/?q=' + encodeURIComponent(url);
+                if (!url.startsWith('http://') && !url.startsWith('https://') && !url.startsWith('cypher://')) {
+                    url = url.includes('.') && !url.includes(' ')
+                        ? 'https://' + url
+                        : settings.search.query_url.replace('{query}', encodeURIComponent(url));
                 }
                 navigateTo(url);
             }
         }
 
         function navigateTo(url) {
-            isHome = false;
-            addToHistory(url);
-            document.getElementById('urlInput').value = url;
-            document.getElementById('content').innerHTML = '<div class="loading"><div class="spinner"></div><h2>Loading...</h2></div>';
-            window.ipc.postMessage(JSON.stringify({ action: 'load_url', url: url }));
+            beginNavigation(activeTab, { kind: 'url', url: url });
+            window.ipc.postMessage(JSON.stringify({ action: 'load_url', tab: activeTab, url: url }));
         }
 
-        function loadUrl(url, addHistory = true) {
-            isHome = false;
-            if (addHistory) addToHistory(url);
-            document.getElementById('urlInput').value = url;
-            document.getElementById('content').innerHTML = '<div class="loading"><div class="spinner"></div><h2>Loading...</h2></div>';
-            window.ipc.postMessage(JSON.stringify({ action: 'load_url', url: url }));
+        function searchInputValue() {
+            const input = activeState().pane.querySelector('.search-input');
+            return input ? input.value.trim() : '';
         }
 
-        function performSearch(query = null, addHistory = true) {
-            const searchQuery = query || document.getElementById('searchInput').value.trim();
+        function performSearch(query = null, page = 1) {
+            const searchQuery = query || searchInputValue();
             if (searchQuery) {
-                if (addHistory) addToHistory('SEARCH:' + searchQuery);
-                document.getElementById('urlInput').value = `Search: ${searchQuery}`;
-                document.getElementById('content').innerHTML = '<div class="loading"><div class="spinner"></div><h2>Searching...</h2></div>';
-                window.ipc.postMessage(JSON.stringify({ action: 'search', url: searchQuery }));
+                beginNavigation(activeTab, { kind: 'search', provider: 'duckduckgo', query: searchQuery, page: page });
+                window.ipc.postMessage(JSON.stringify({ action: 'search', tab: activeTab, url: searchQuery, page: page }));
             }
         }
 
-        function displayResults(results, query) {
-            isHome = false;
+        function performHistorySearch(query = null, page = 1) {
+            const searchQuery = query || searchInputValue();
+            if (searchQuery) {
+                beginNavigation(activeTab, { kind: 'search', provider: 'local', query: searchQuery, page: page });
+                window.ipc.postMessage(JSON.stringify({ action: 'search_history', tab: activeTab, url: searchQuery, page: page }));
+            }
+        }
+
+        function toggleSiteIndexing() {
+            const state = activeState();
+            if (state.isHome || !state.currentUrl) return;
+            const action = state.siteIndexed ? 'exclude_site_from_index' : 'include_site_in_index';
+            window.ipc.postMessage(JSON.stringify({ action: action, tab: activeTab, url: state.currentUrl }));
+        }
+
+        function setSiteIndexing(tabId, indexed) {
+            tabState(tabId).siteIndexed = indexed;
+            if (tabId === activeTab) updateIndexToggle();
+        }
+
+        function updateIndexToggle() {
+            const indexed = activeState().siteIndexed;
+            const button = document.getElementById('indexToggle');
+            button.classList.toggle('off', !indexed);
+            button.title = indexed ? 'Stop indexing this site' : 'Index this site in your history search';
+        }
+
+        function setPageBlocking(tabId, url, blocking) {
+            const state = tabState(tabId);
+            state.pageOrigin = url ? new URL(url).origin : null;
+            state.blocking = blocking;
+            state.blockedCount = 0;
+            if (tabId === activeTab) updateBlockToggle();
+        }
+
+        // Counts are kept per origin, since that is often all a blocked
+        // request says about the page that made it.
+        function setBlockedCount(origin, count) {
+            for (const state of tabs.values()) {
+                if (state.pageOrigin === origin) state.blockedCount = count;
+            }
+            updateBlockToggle();
+        }
+
+        function toggleSiteBlocking() {
+            const state = activeState();
+            if (!state.pageOrigin) return;
+            const action = state.blocking ? 'allow_on_site' : 'block_on_site';
+            window.ipc.postMessage(JSON.stringify({ action: action, tab: activeTab, url: state.currentUrl }));
+        }
+
+        function updateBlockToggle() {
+            const state = activeState();
+            const button = document.getElementById('blockToggle');
+            const badge = document.getElementById('blockedCount');
+            button.classList.toggle('off', !state.blocking);
+            badge.hidden = !state.blocking || state.blockedCount === 0;
+            badge.textContent = state.blockedCount;
+            button.title = !state.pageOrigin
+                ? 'No trackers blocked'
+                : state.blocking
+                    ? `${state.blockedCount} blocked on this page. Click to stop blocking on this site`
+                    : 'Blocking is off for this site. Click to turn it on';
+        }
+
+        function escapeHtml(text) {
+            const div = document.createElement('div');
+            div.textContent = text;
+            return div.innerHTML.replace(/"/g, '&quot;').replace(/'/g, '&#39;');
+        }
+
+        function displayResults(tabId, results, target) {
+            const state = tabState(tabId);
+            state.isHome = false;
+            const heading = target.provider === 'local' ? 'History Results' : 'Search Results';
+            const search = target.provider === 'local' ? 'performHistorySearch' : 'performSearch';
             const html = `
                 <div class="results">
-                    <h2>Search Results for "${query}"</h2>
+                    <h2>${heading} for "${escapeHtml(target.query)}"</h2>
                     ${results.map(r => `
-                        <div class="result-item" onclick="navigateTo('${r.url.replace(/'/g, "\\'")}')">
-                            <h3>${r.title}</h3>
-                            <div class="url">${r.url}</div>
+                        <div class="result-item" data-url="${escapeHtml(r.url)}" onclick="navigateTo(this.dataset.url)">
+                            <h3>${escapeHtml(r.title)}</h3>
+                            <div class="url">${escapeHtml(r.url)}</div>
                             <div class="snippet">${r.snippet}</div>
                         </div>
                     `).join('')}
+                    <div class="pager" data-query="${escapeHtml(target.query)}">
+                        ${target.page > 1 ? `<button class="go-btn" onclick="${search}(this.parentNode.dataset.query, ${target.page - 1})">Previous</button>` : ''}
+                        ${results.length > 0 ? `<button class="go-btn" onclick="${search}(this.parentNode.dataset.query, ${target.page + 1})">Next</button>` : ''}
+                    </div>
                 </div>
             `;
-            document.getElementById('content').innerHTML = html;
+            state.pane.innerHTML = html;
+        }
+
+        function formatVisitTime(seconds) {
+            return new Date(seconds * 1000).toLocaleString();
         }
 
-        function loadProxiedContent(html, url) {
-            isHome = false;
+        function visitLink(visit) {
+            const target = visit.target;
+            if (target.kind === 'url') {
+                return `<span class="title" data-url="${escapeHtml(target.url)}" onclick="navigateTo(this.dataset.url)">${escapeHtml(visit.title)}</span>`;
+            }
+            const search = target.provider === 'local' ? 'performHistorySearch' : 'performSearch';
+            return `<span class="title" data-query="${escapeHtml(target.query)}" onclick="${search}(this.dataset.query, ${target.page})">${escapeHtml(visit.title)}</span>`;
+        }
+
+        function showHistoryPage(tabId, visits, query) {
+            const state = tabState(tabId);
+            state.isHome = false;
+            state.currentUrl = 'cypher://history';
+            state.label = state.currentUrl;
+            if (tabId === activeTab) {
+                document.getElementById('urlInput').value = state.label;
+                updateBookmarkButton();
+            }
+            if (!state.pane.querySelector('.history-query')) {
+                state.pane.innerHTML = `
+                    <div class="results">
+                        <h2>History</h2>
+                        <div class="search-container" style="margin-bottom: 30px;">
+                            <input type="text" class="history-query" placeholder="Search your history" style="flex: 1; background: transparent; border: none; padding: 10px 20px; color: white; outline: none;" />
+                            <button class="search-btn" onclick="clearHistory()">Clear history</button>
+                        </div>
+                        <div class="history-list"></div>
+                    </div>
+                `;
+                const input = state.pane.querySelector('.history-query');
+                input.value = query;
+                input.addEventListener('input', () => {
+                    window.ipc.postMessage(JSON.stringify({ action: 'search_visits', tab: tabId, url: input.value }));
+                });
+            }
+            state.pane.querySelector('.history-list').innerHTML = visits.length === 0
+                ? '<p class="status">No matching history.</p>'
+                : visits.map(v => `
+                    <div class="bookmark-row">
+                        ${visitLink(v)}
+                        <span class="tags">${escapeHtml(v.target.url || '')}</span>
+                        <span class="tags">${formatVisitTime(v.visited)}</span>
+                        <button onclick="removeVisit(${v.id})" title="Remove from history">✕</button>
+                    </div>
+                `).join('');
+        }
+
+        function removeVisit(id) {
+            const query = activeState().pane.querySelector('.history-query')?.value || '';
+            window.ipc.postMessage(JSON.stringify({ action: 'remove_visit', tab: activeTab, id: id, url: query }));
+        }
+
+        function clearHistory() {
+            if (confirm('Clear all browsing history?')) {
+                window.ipc.postMessage(JSON.stringify({ action: 'clear_history', tab: activeTab }));
+            }
+        }
+
+        function loadProxiedPage(tabId, viewUrl, url, indexed, blocking) {
+            const state = tabState(tabId);
+            state.isHome = false;
+            state.currentUrl = url;
+            state.pageTitle = '';
+            state.pageFavicon = null;
+            setSiteIndexing(tabId, indexed);
+            setPageBlocking(tabId, url, blocking);
+            if (tabId === activeTab) updateBookmarkButton();
             const iframe = document.createElement('iframe');
             iframe.style.width = '100%';
             iframe.style.height = '100%';
             iframe.style.border = 'none';
             iframe.sandbox = 'allow-same-origin allow-scripts allow-popups allow-forms';
+            iframe.addEventListener('load', () => {
+                iframe.dataset.loaded = 'true';
+            });
 
             const container = document.createElement('div');
             container.className = 'iframe-container';
             container.appendChild(iframe);
 
-            document.getElementById('content').innerHTML = '';
-            document.getElementById('content').appendChild(container);
+            state.pane.innerHTML = '';
+            state.pane.appendChild(container);
+
+            iframe.src = viewUrl;
+        }
+
+        // Proxied pages run on origins of their own, out of the chrome's
+        // reach, and report links, scrolling and titles through postMessage
+        // instead (see FRAME_SCRIPT in protocol.rs).
+        window.addEventListener('message', (event) => {
+            const data = event.data;
+            if (!data || !data.cypher) return;
+            const state = [...tabs.values()].find(s => {
+                const iframe = s.pane.querySelector('.iframe-container iframe');
+                return iframe && iframe.contentWindow === event.source;
+            });
+            if (!state) return;
+            switch (data.type) {
+                case 'open':
+                    if (data.target === 'background') openInBackground(data.url);
+                    else if (data.target === 'tab') newTab(data.url);
+                    else if (state.id === activeTab) navigateTo(data.url);
+                    break;
+                case 'submit':
+                    beginNavigation(state.id, { kind: 'url', url: data.form.action });
+                    window.ipc.postMessage(JSON.stringify({ action: 'submit_form', tab: state.id, form: data.form }));
+                    break;
+                case 'scroll':
+                    reportScroll(state.id, data.y);
+                    break;
+                case 'loaded':
+                    // The page may name itself, but where it came from is
+                    // only taken from the browser, which knows what it served.
+                    state.pageTitle = data.title;
+                    state.pageFavicon = data.favicon;
+                    window.ipc.postMessage(JSON.stringify({ action: 'frame_loaded', tab: state.id, document: data.document }));
+                    break;
+            }
+        });
 
-            iframe.srcdoc = html;
+        function showFrameAddress(tabId, url) {
+            const state = tabState(tabId);
+            if (url === state.currentUrl) return;
+            state.currentUrl = url;
+            state.label = url;
+            if (tabId === activeTab) {
+                document.getElementById('urlInput').value = state.label;
+                updateBookmarkButton();
+            }
         }
 
-        function showError(message) {
-            document.getElementById('content').innerHTML = `
+        function showImport() {
+            const state = activeState();
+            state.isHome = false;
+            state.currentUrl = null;
+            state.label = '';
+            document.getElementById('urlInput').value = '';
+            updateBookmarkButton();
+            state.pane.innerHTML = `
+                <div class="import-panel">
+                    <h2>Import bookmarks and history</h2>
+                    <input type="text" id="importPath" placeholder="Path to bookmarks.html, places.sqlite, History or Bookmarks" />
+                    <select id="importFormat">
+                        <option value="">Detect from file name</option>
+                        <option value="netscape">Bookmarks HTML (Netscape format)</option>
+                        <option value="firefox">Firefox places.sqlite</option>
+                        <option value="chromium_history">Chromium History</option>
+                        <option value="chromium_bookmarks">Chromium Bookmarks</option>
+                    </select>
+                    <label><input type="checkbox" id="importFetch" /> Fetch and index the page contents</label>
+                    <button class="go-btn" id="importButton" onclick="startImport()">Import</button>
+                    <progress id="importProgress" value="0" max="1" hidden></progress>
+                    <div class="status" id="importStatus"></div>
+                </div>
+            `;
+        }
+
+        function startImport() {
+            const path = document.getElementById('importPath').value.trim();
+            if (!path) return;
+            document.getElementById('importButton').disabled = true;
+            document.getElementById('importProgress').hidden = false;
+            document.getElementById('importStatus').textContent = 'Reading ' + path + '...';
+            window.ipc.postMessage(JSON.stringify({
+                action: 'import',
+                path: path,
+                format: document.getElementById('importFormat').value || null,
+                fetch: document.getElementById('importFetch').checked
+            }));
+        }
+
+        function importProgress(done, total) {
+            const progress = document.getElementById('importProgress');
+            if (!progress) return;
+            progress.max = Math.max(total, 1);
+            progress.value = done;
+            document.getElementById('importStatus').textContent = `Indexed ${done} of ${total}`;
+        }
+
+        function importFinished(count, error) {
+            const status = document.getElementById('importStatus');
+            if (!status) return;
+            document.getElementById('importButton').disabled = false;
+            status.textContent = error ? error : `Imported ${count} entries into your history search`;
+        }
+
+        function notify(message) {
+            const toast = document.getElementById('toast');
+            toast.textContent = message;
+            toast.hidden = false;
+            clearTimeout(toast.timer);
+            toast.timer = setTimeout(() => { toast.hidden = true; }, 4000);
+        }
+
+        function setBookmarks(list) {
+            bookmarks = list;
+            renderQuickLinks();
+            updateBookmarkButton();
+            for (const state of tabs.values()) {
+                if (state.currentUrl === 'cypher://bookmarks') showBookmarksPage(state.id, list);
+            }
+        }
+
+        function bookmarkFor(url) {
+            return bookmarks.bookmarks.find(b => b.url === url);
+        }
+
+        function bookmarkIcon(b) {
+            const letter = escapeHtml((b.title || b.url).charAt(0).toUpperCase());
+            return b.favicon
+                ? `<img src="${escapeHtml(b.favicon)}" data-letter="${letter}" onerror="this.parentNode.textContent = this.dataset.letter">`
+                : letter;
+        }
+
+        function renderQuickLinks() {
+            const html = bookmarks.bookmarks
+                .filter(b => b.folder === QUICK_LINKS_FOLDER)
+                .map(b => `
+                    <div class="quick-link" data-url="${escapeHtml(b.url)}" onclick="navigateTo(this.dataset.url)">
+                        <div class="icon">${bookmarkIcon(b)}</div>
+                        <div class="name">${escapeHtml(b.title)}</div>
+                    </div>
+                `).join('');
+            document.querySelectorAll('.content .quick-links').forEach(container => {
+                container.innerHTML = html;
+            });
+        }
+
+        function updateBookmarkButton() {
+            const button = document.getElementById('bookmarkButton');
+            const currentUrl = activeState().currentUrl;
+            const bookmarked = currentUrl && bookmarkFor(currentUrl);
+            button.textContent = bookmarked ? '★' : '☆';
+            button.title = bookmarked ? 'Edit bookmark' : 'Bookmark this page';
+        }
+
+        function currentPageTitle() {
+            return activeState().pageTitle || '';
+        }
+
+        function currentPageFavicon() {
+            return activeState().pageFavicon || null;
+        }
+
+        function toggleBookmarkEditor() {
+            const editor = document.getElementById('bookmarkEditor');
+            if (!editor.hidden) {
+                closeBookmarkEditor();
+                return;
+            }
+            const currentUrl = activeState().currentUrl;
+            if (!currentUrl || !currentUrl.startsWith('http')) {
+                notify('Only web pages can be bookmarked');
+                return;
+            }
+            const existing = bookmarkFor(currentUrl);
+            document.getElementById('bookmarkEditorTitle').textContent = existing ? 'Edit bookmark' : 'Add bookmark';
+            document.getElementById('bookmarkTitle').value = existing ? existing.title : (currentPageTitle() || currentUrl);
+            document.getElementById('bookmarkFolder').value = existing ? existing.folder : QUICK_LINKS_FOLDER;
+            document.getElementById('bookmarkTags').value = existing ? existing.tags.join(', ') : '';
+            document.getElementById('bookmarkFolders').innerHTML = bookmarks.folders
+                .map(f => `<option value="${escapeHtml(f)}">`).join('');
+            document.getElementById('bookmarkRemove').hidden = !existing;
+            editor.hidden = false;
+        }
+
+        function closeBookmarkEditor() {
+            document.getElementById('bookmarkEditor').hidden = true;
+        }
+
+        function saveBookmark() {
+            const currentUrl = activeState().currentUrl;
+            const existing = bookmarkFor(currentUrl);
+            const message = {
+                action: existing ? 'update_bookmark' : 'add_bookmark',
+                id: existing ? existing.id : null,
+                url: currentUrl,
+                title: document.getElementById('bookmarkTitle').value,
+                folder: document.getElementById('bookmarkFolder').value,
+                tags: document.getElementById('bookmarkTags').value.split(','),
+                favicon: existing ? null : currentPageFavicon()
+            };
+            window.ipc.postMessage(JSON.stringify(message));
+            closeBookmarkEditor();
+        }
+
+        function removeCurrentBookmark() {
+            const existing = bookmarkFor(activeState().currentUrl);
+            if (existing) removeBookmark(existing.id);
+            closeBookmarkEditor();
+        }
+
+        function removeBookmark(id) {
+            window.ipc.postMessage(JSON.stringify({ action: 'remove_bookmark', id: id }));
+        }
+
+        function moveBookmark(id, folder) {
+            window.ipc.postMessage(JSON.stringify({ action: 'move_bookmark', id: id, folder: folder }));
+        }
+
+        function createBookmarkFolder() {
+            const input = activeState().pane.querySelector('.new-folder');
+            const folder = input ? input.value.trim() : '';
+            if (folder) window.ipc.postMessage(JSON.stringify({ action: 'create_bookmark_folder', folder: folder }));
+        }
+
+        function removeBookmarkFolder(folder) {
+            if (confirm(`Delete the folder "${folder}" and everything in it?`)) {
+                window.ipc.postMessage(JSON.stringify({ action: 'remove_bookmark_folder', folder: folder }));
+            }
+        }
+
+        function showBookmarksPage(tabId, list) {
+            const state = tabState(tabId);
+            state.isHome = false;
+            state.currentUrl = 'cypher://bookmarks';
+            state.label = state.currentUrl;
+            if (tabId === activeTab) {
+                document.getElementById('urlInput').value = state.label;
+                updateBookmarkButton();
+            }
+            const folders = ['', ...list.folders];
+            const folderOptions = (selected) => folders
+                .map(f => `<option value="${escapeHtml(f)}" ${f === selected ? 'selected' : ''}>${f ? escapeHtml(f) : '(No folder)'}</option>`)
+                .join('');
+            const sections = folders.map(folder => {
+                const items = list.bookmarks.filter(b => b.folder === folder);
+                if (!folder && items.length === 0) return '';
+                return `
+                    <div class="bookmark-folder">
+                        <h3>${folder ? escapeHtml(folder) : 'Unsorted'}
+                            ${folder ? `<button data-folder="${escapeHtml(folder)}" onclick="removeBookmarkFolder(this.dataset.folder)" title="Delete folder">🗑</button>` : ''}
+                        </h3>
+                        ${items.map(b => `
+                            <div class="bookmark-row">
+                                <span class="title" data-url="${escapeHtml(b.url)}" onclick="navigateTo(this.dataset.url)">${escapeHtml(b.title)}</span>
+                                <span class="tags">${b.tags.map(t => '#' + escapeHtml(t)).join(' ')}</span>
+                                <select onchange="moveBookmark(${b.id}, this.value)">${folderOptions(b.folder)}</select>
+                                <button onclick="removeBookmark(${b.id})" title="Remove">✕</button>
+                            </div>
+                        `).join('')}
+                    </div>
+                `;
+            }).join('');
+            state.pane.innerHTML = `
+                <div class="results">
+                    <h2>Bookmarks</h2>
+                    <div class="search-container" style="margin-bottom: 30px;">
+                        <input type="text" class="new-folder" placeholder="New folder name" style="flex: 1; background: transparent; border: none; padding: 10px 20px; color: white; outline: none;" />
+                        <button class="search-btn" onclick="createBookmarkFolder()">Add folder</button>
+                    </div>
+                    ${sections}
+                </div>
+            `;
+        }
+
+        function applySettings(value) {
+            settings = value;
+            for (const state of tabs.values()) {
+                if (state.currentUrl === 'cypher://settings') showSettingsPage(state.id, value);
+            }
+        }
+
+        const SETTINGS_FIELDS = [
+            ['window', 'title', 'Window title', 'text'],
+            ['window', 'width', 'Default window width', 'number'],
+            ['window', 'height', 'Default window height', 'number'],
+            ['search', 'results_url', 'Search results endpoint', 'text'],
+            ['search', 'query_url', 'Address bar search URL ({query} is replaced)', 'text'],
+            ['search', 'results_per_page', 'Results per page', 'number'],
+            ['network', 'user_agent', 'User agent', 'text'],
+            ['network', 'proxy_url', 'Proxy server URL', 'text'],
+            ['network', 'max_page_mb', 'Largest page to load (MB)', 'number'],
+            ['downloads', 'directory', 'Save downloads to', 'text'],
+            ['blocking', 'enabled', 'Block trackers and ads with the filter lists', 'checkbox'],
+            ['url_cleaning', 'enabled', 'Remove tracking parameters from addresses', 'checkbox'],
+        ];
+
+        function showSettingsPage(tabId, value, stripped) {
+            const state = tabState(tabId);
+            state.isHome = false;
+            state.currentUrl = 'cypher://settings';
+            state.label = state.currentUrl;
+            if (tabId === activeTab) {
+                document.getElementById('urlInput').value = state.label;
+                updateBookmarkButton();
+            }
+            state.pane.innerHTML = `
+                <div class="import-panel settings-panel">
+                    <h2>Settings</h2>
+                    ${SETTINGS_FIELDS.map(([section, key, label, type]) => `
+                        <label class="status">${escapeHtml(label)}</label>
+                        <input type="${type}" data-section="${section}" data-key="${key}" value="${escapeHtml(String(value[section][key]))}" ${type === 'checkbox' && value[section][key] ? 'checked' : ''} />
+                    `).join('')}
+                    <div class="editor-actions">
+                        <button class="go-btn" onclick="saveSettings()">Save</button>
+                        <button class="go-btn secondary" onclick="resetSettings()">Restore defaults</button>
+                    </div>
+                    <div class="status">${stripped} tracking parameters removed since Cypher started</div>
+                    <a class="manage-link" onclick="reloadFilters()">Reload filter lists</a>
+                    <a class="manage-link" data-url="cypher://cookies" onclick="navigateTo(this.dataset.url)">Manage cookies</a>
+                    <a class="manage-link" data-url="cypher://profile" onclick="navigateTo(this.dataset.url)">Where is my data stored?</a>
+                </div>
+            `;
+        }
+
+        function saveSettings() {
+            const value = JSON.parse(JSON.stringify(settings));
+            activeState().pane.querySelectorAll('.settings-panel input').forEach(input => {
+                value[input.dataset.section][input.dataset.key] =
+                    input.type === 'number' ? Number(input.value) : input.type === 'checkbox' ? input.checked : input.value;
+            });
+            window.ipc.postMessage(JSON.stringify({ action: 'save_settings', settings: value }));
+        }
+
+        function reloadFilters() {
+            window.ipc.postMessage(JSON.stringify({ action: 'reload_filters' }));
+        }
+
+        function resetSettings() {
+            if (confirm('Restore all settings to their defaults?')) {
+                window.ipc.postMessage(JSON.stringify({ action: 'reset_settings' }));
+            }
+        }
+
+        function showProfilePage(tabId, page) {
+            const state = tabState(tabId);
+            state.isHome = false;
+            state.currentUrl = 'cypher://profile';
+            state.label = state.currentUrl;
+            if (tabId === activeTab) {
+                document.getElementById('urlInput').value = state.label;
+                updateBookmarkButton();
+            }
+            state.pane.innerHTML = `
+                <div class="results">
+                    <h2>Profile "${escapeHtml(page.name)}"${page.private ? ' (private window)' : ''}</h2>
+                    ${page.storage.map(s => `
+                        <div class="bookmark-row">
+                            <span class="title">${escapeHtml(s.label)}</span>
+                            <span class="tags">${escapeHtml(s.path)}</span>
+                        </div>
+                    `).join('')}
+                </div>
+            `;
+        }
+
+        function formatBytes(bytes) {
+            if (bytes < 1024) return `${bytes} B`;
+            const units = ['KB', 'MB', 'GB', 'TB'];
+            let value = bytes / 1024;
+            let unit = 0;
+            while (value >= 1024 && unit < units.length - 1) {
+                value /= 1024;
+                unit++;
+            }
+            return `${value.toFixed(1)} ${units[unit]}`;
+        }
+
+        function fileName(path) {
+            return path.split(/[\\/]/).pop();
+        }
+
+        function describeDownload(d) {
+            const size = d.total ? `${formatBytes(d.received)} of ${formatBytes(d.total)}` : formatBytes(d.received);
+            switch (d.state) {
+                case 'downloading': return `Downloading, ${size}`;
+                case 'paused': return `Paused, ${size}`;
+                case 'completed': return `${formatBytes(d.received)}, saved to ${d.path}`;
+                default: return `Failed: ${d.error || 'unknown error'}`;
+            }
+        }
+
+        function showDownloadsPage(tabId, list) {
+            const state = tabState(tabId);
+            state.isHome = false;
+            state.currentUrl = 'cypher://downloads';
+            state.label = state.currentUrl;
+            if (tabId === activeTab) {
+                document.getElementById('urlInput').value = state.label;
+                updateBookmarkButton();
+            }
+            const rows = list.slice().reverse().map(d => `
+                <div class="bookmark-row download-row">
+                    <div class="details">
+                        <span class="title" data-url="${escapeHtml(d.url)}" onclick="navigateTo(this.dataset.url)">${escapeHtml(fileName(d.path))}</span>
+                        <span class="tags">${escapeHtml(d.url)}</span>
+                        ${d.state === 'downloading' || d.state === 'paused'
+                            ? `<progress value="${d.received}" ${d.total ? `max="${d.total}"` : ''}></progress>` : ''}
+                        <span class="tags">${escapeHtml(describeDownload(d))}</span>
+                        ${d.sha256 ? `<span class="tags checksum">SHA-256 ${d.sha256}</span>` : ''}
+                    </div>
+                    ${d.state === 'downloading' ? `<button onclick="pauseDownload(${d.id})" title="Pause">⏸</button>` : ''}
+                    ${d.state === 'paused' || d.state === 'failed' ? `<button onclick="resumeDownload(${d.id})" title="Resume">▶</button>` : ''}
+                    <button onclick="removeDownload(${d.id})" title="${d.state === 'completed' ? 'Remove from list' : 'Cancel'}">✕</button>
+                </div>
+            `).join('');
+            state.pane.innerHTML = `
+                <div class="results">
+                    <h2>Downloads</h2>
+                    ${rows || '<p class="status">Nothing has been downloaded yet.</p>'}
+                </div>
+            `;
+        }
+
+        function syncDownloads(list) {
+            for (const state of tabs.values()) {
+                if (state.currentUrl === 'cypher://downloads') showDownloadsPage(state.id, list);
+            }
+        }
+
+        function describeCookie(c) {
+            const flags = [
+                c.host_only ? c.domain : '.' + c.domain,
+                c.path,
+                c.expires ? `expires ${formatVisitTime(c.expires)}` : 'until the browser closes',
+                c.secure ? 'secure' : '',
+                c.http_only ? 'HttpOnly' : '',
+                `SameSite=${c.same_site}`,
+            ];
+            return flags.filter(Boolean).join(' · ');
+        }
+
+        // Cookies are grouped by the site they were set on, which is also
+        // the only place they are sent from.
+        function showCookiesPage(tabId, sites) {
+            const state = tabState(tabId);
+            state.isHome = false;
+            state.currentUrl = 'cypher://cookies';
+            state.label = state.currentUrl;
+            if (tabId === activeTab) {
+                document.getElementById('urlInput').value = state.label;
+                updateBookmarkButton();
+            }
+            const sections = sites.map(site => `
+                <div class="bookmark-folder">
+                    <h3>${escapeHtml(site.site)}
+                        <button data-site="${escapeHtml(site.site)}" onclick="clearCookies(this.dataset.site)" title="Clear cookies for this site">🗑</button>
+                    </h3>
+                    ${site.cookies.map(c => `
+                        <div class="bookmark-row download-row">
+                            <div class="details">
+                                <span class="title">${escapeHtml(c.name || '(unnamed)')}</span>
+                                <span class="tags">${escapeHtml(c.value)}</span>
+                                <span class="tags">${escapeHtml(describeCookie(c))}</span>
+                            </div>
+                        </div>
+                    `).join('')}
+                </div>
+            `).join('');
+            state.pane.innerHTML = `
+                <div class="results">
+                    <h2>Cookies</h2>
+                    ${sections
+                        ? `${sections}<button class="go-btn secondary" onclick="clearCookies(null)">Clear all cookies</button>`
+                        : '<p class="status">No site has stored cookies.</p>'}
+                </div>
+            `;
+        }
+
+        function syncCookies(sites) {
+            for (const state of tabs.values()) {
+                if (state.currentUrl === 'cypher://cookies') showCookiesPage(state.id, sites);
+            }
+        }
+
+        function clearCookies(site) {
+            const question = site ? `Clear all cookies set while visiting ${site}?` : 'Clear all cookies?';
+            if (confirm(question)) {
+                window.ipc.postMessage(JSON.stringify({ action: 'clear_cookies', url: site }));
+            }
+        }
+
+        // Pages that cannot be shown in the content pane are saved instead.
+        function showDownloadStarted(tabId, download) {
+            tabState(tabId).pane.innerHTML = `
+                <div class="loading">
+                    <h2>Downloading ${escapeHtml(fileName(download.path))}</h2>
+                    <p style="color: rgba(255,255,255,0.7); margin-top: 20px;">${escapeHtml(download.content_type || 'Unknown type')}</p>
+                    <button class="go-btn" style="margin-top: 30px;" data-url="cypher://downloads" onclick="navigateTo(this.dataset.url)">Show downloads</button>
+                </div>
+            `;
+        }
+
+        function pauseDownload(id) {
+            window.ipc.postMessage(JSON.stringify({ action: 'pause_download', id: id }));
+        }
+
+        function resumeDownload(id) {
+            window.ipc.postMessage(JSON.stringify({ action: 'resume_download', id: id }));
+        }
+
+        function removeDownload(id) {
+            window.ipc.postMessage(JSON.stringify({ action: 'remove_download', id: id }));
+        }
+
+        function showError(tabId, message) {
+            tabState(tabId).pane.innerHTML = `
                 <div class="loading">
                     <h2 style="color: #ef4444;">Error Loading Page</h2>
                     <p style="color: rgba(255,255,255,0.7); margin-top: 20px; max-width: 600px; text-align: center;">${message}</p>
@@ -552,77 +1641,206 @@ fn main() {
             if (e.key === 'Enter') navigate();
         });
 
-        document.getElementById('searchInput')?.addEventListener('keypress', (e) => {
-            if (e.key === 'Enter') performSearch();
+        document.addEventListener('click', (e) => {
+            if (!e.target.closest('#tabMenu')) closeTabMenu();
+        });
+
+        document.addEventListener('auxclick', (e) => {
+            const link = e.button === 1 ? e.target.closest('.content [data-url]') : null;
+            if (link) {
+                e.preventDefault();
+                openInBackground(link.dataset.url);
+            }
+        });
+
+        document.addEventListener('keydown', (e) => {
+            if (!(e.ctrlKey || e.metaKey)) return;
+            if (e.key === 't') {
+                e.preventDefault();
+                newTab();
+            } else if (e.key === 'N' || (e.key === 'n' && e.shiftKey)) {
+                e.preventDefault();
+                newPrivateWindow();
+            } else if (e.key === 'n') {
+                e.preventDefault();
+                newWindow();
+            } else if (e.key === 'w') {
+                e.preventDefault();
+                closeTab(activeTab);
+            }
         });
+
+        window.ipc.postMessage(JSON.stringify({ action: 'get_settings' }));
+        window.ipc.postMessage(JSON.stringify({ action: 'list_bookmarks' }));
+        window.ipc.postMessage(JSON.stringify({ action: 'list_tabs' }));
+        window.ipc.postMessage(JSON.stringify({ action: 'list_windows' }));
+        window.ipc.postMessage(JSON.stringify({ action: 'session_status' }));
     </script>
 </body>
 </html>"#;
 
-    let webview: Arc<Mutex<Option<wry::WebView>>> = Arc::new(Mutex::new(None));
-    let webview_clone = webview.clone();
+    let saved_windows = context.windows().lock().unwrap().entries();
+    let mut open_windows = HashMap::new();
+    for (id, tabs) in saved_windows {
+        let bounds = context.windows().lock().unwrap().bounds(id);
+        let (window, webview) = build_window(&event_loop, context.for_window(id, tabs), home_html, bounds).unwrap();
+        open_windows.insert(window.id(), (id, window, webview));
+    }
+    context.windows().lock().unwrap().save(false);
 
-    let wv = WebViewBuilder::new()
-        .with_html(home_html)
-        .with_ipc_handler(move |request| {
-            let body = request.body();
-
-            if let Ok(msg) = serde_json::from_str::<NavigateMessage>(body) {
-                match msg.action.as_str() {
-                    "search" => {
-                        if let Some(query) = msg.url {
-                            let results = fetch_search_results(&query);
-                            if let Ok(json) = serde_json::to_string(&results) {
-                                let script = format!(
-                                    "displayResults({}, '{}');",
-                                    json,
-                                    query.replace("'", "\\'").replace("\\", "\\\\")
-                                );
-                                if let Some(wv) = webview_clone.lock().unwrap().as_ref() {
-                                    let _ = wv.evaluate_script(&script);
-                                }
-                            }
-                        }
+    event_loop.run(move |event, target, control_flow| {
+        *control_flow = ControlFlow::Wait;
+        let closing = match event {
+            Event::UserEvent(BrowserEvent::EvaluateScript(id, call)) => {
+                if let Some((_, _, webview)) = open_windows.values().find(|(w, _, _)| *w == id) {
+                    let _ = webview.evaluate_script(&call.to_script());
+                }
+                None
+            }
+            Event::UserEvent(BrowserEvent::OpenWindow(tabs, bounds, private)) => {
+                let (id, tabs) = context.windows().lock().unwrap().open(tabs, bounds, private);
+                match build_window(target, context.for_window(id, tabs), home_html, bounds) {
+                    Ok((window, webview)) => {
+                        open_windows.insert(window.id(), (id, window, webview));
                     }
-                    "load_url" => {
-                        if let Some(url) = msg.url {
-                            match fetch_through_proxy(&url) {
-                                Ok(html) => {
-                                    let escaped_html = html
-                                        .replace("\\", "\\\\")
-                                        .replace("`", "\\`")
-                                        .replace("${", "\\${");
-                                    let escaped_url = url.replace("'", "\\'");
-                                    let script = format!("loadProxiedContent(`{}`, '{}');", escaped_html, escaped_url);
-                                    if let Some(wv) = webview_clone.lock().unwrap().as_ref() {
-                                        let _ = wv.evaluate_script(&script);
-                                    }
-                                }
-                                Err(e) => {
-                                    let script = format!("showError('{}');", e.replace("'", "\\'"));
-                                    if let Some(wv) = webview_clone.lock().unwrap().as_ref() {
-                                        let _ = wv.evaluate_script(&script);
-                                    }
-                                }
-                            }
-                        }
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        context.close_window(id);
                     }
-                    _ => {}
                 }
-            }})
+                context.send_windows();
+                None
+            }
+            Event::UserEvent(BrowserEvent::CloseWindow(id)) => Some(id),
+            Event::UserEvent(BrowserEvent::ApplySettings(settings)) => {
+                for (id, window, _) in open_windows.values() {
+                    let private = context.windows().lock().unwrap().is_private(*id);
+                    window.set_title(&window_title(&settings, private));
+                }
+                None
+            }
+            Event::WindowEvent { event: WindowEvent::CloseRequested, window_id, .. } => {
+                open_windows.get(&window_id).map(|(id, _, _)| *id)
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Moved(_) | WindowEvent::Resized(_),
+                window_id,
+                ..
+            } => {
+                if let Some((id, window, _)) = open_windows.get(&window_id) {
+                    context.windows().lock().unwrap().set_bounds(*id, window_bounds(window));
+                }
+                None
+            }
+            _ => None,
+        };
+
+        if let Some(id) = closing {
+            if context.close_window(id) {
+                open_windows.retain(|_, (w, _, _)| *w != id);
+                context.send_windows();
+            } else {
+                context.windows().lock().unwrap().save(true);
+                context.flush();
+                *control_flow = ControlFlow::Exit;
+            }
+        }
+    });
+}
+
+fn window_title(settings: &Settings, private: bool) -> String {
+    if private {
+        format!("{} (Private)", settings.window.title)
+    } else {
+        settings.window.title.clone()
+    }
+}
+
+fn window_bounds(window: &Window) -> WindowBounds {
+    let position = window.outer_position().unwrap_or_default();
+    let size = window.inner_size();
+    WindowBounds {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+        maximized: window.is_maximized(),
+    }
+}
+
+fn build_window(
+    target: &EventLoopWindowTarget<BrowserEvent>,
+    context: BrowserContext,
+    html: &str,
+    bounds: Option<WindowBounds>,
+) -> Result<(Window, WebView), String> {
+    let settings = context.settings();
+    let private = context.is_private();
+    let builder = WindowBuilder::new().with_title(window_title(&settings, private));
+    let builder = match bounds {
+        Some(bounds) => builder
+            .with_position(tao::dpi::PhysicalPosition::new(bounds.x, bounds.y))
+            .with_inner_size(tao::dpi::PhysicalSize::new(bounds.width, bounds.height))
+            .with_maximized(bounds.maximized),
+        None => builder.with_inner_size(tao::dpi::LogicalSize::new(settings.window.width, settings.window.height)),
+    };
+    let window = builder
+        .build(target)
+        .map_err(|e| format!("Failed to create window: {}", e))?;
+
+    let popup_context = context.clone();
+    let protocol_context = context.clone();
+    let webview = WebViewBuilder::new()
+        .with_html(html)
+        // Private windows keep the pages' own storage out of the profile too.
+        .with_incognito(private)
+        // Fetching happens off the UI thread; the webview waits on the responder.
+        .with_asynchronous_custom_protocol(SCHEME.to_string(), move |_, request, responder| {
+            let context = protocol_context.clone();
+            let request = PageRequest {
+                method: request.method().to_string(),
+                url: request.uri().to_string(),
+                content_type: request
+                    .headers()
+                    .get(CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string),
+                headers: ["referer", "origin", "sec-fetch-dest"]
+                    .iter()
+                    .filter_map(|name| Some((name.to_string(), request.headers().get(*name)?.to_str().ok()?.to_string())))
+                    .collect(),
+                body: request.body().clone(),
+            };
+            thread::spawn(move || {
+                let resource = context.serve_proxy_request(request);
+                let response = Response::builder()
+                    .status(resource.status)
+                    .header(CONTENT_TYPE, resource.content_type)
+                    .body(resource.body)
+                    .unwrap_or_else(|e| Response::builder().status(500).body(e.to_string().into_bytes()).unwrap());
+                responder.respond(response);
+            });
+        })
+        .with_ipc_handler(move |request| {
+            if let Ok(msg) = serde_json::from_str::<NavigateMessage>(request.body()) {
+                let context = context.clone();
+                thread::spawn(move || context.handle(msg));
+            }
+        })
+        .with_new_window_req_handler(move |url| {
+            // Links in proxied pages point at the proxied address, which only
+            // the frame can load.
+            let url = from_proxy_url(&url).unwrap_or(url);
+            let context = popup_context.clone();
+            thread::spawn(move || context.open_tab(Some(url), None, true));
+            false
+        })
         .with_navigation_handler(|uri| {
             println!("Navigating to: {}", uri);
             true
         })
         .build(&window)
-        .unwrap();
-
-    *webview.lock().unwrap() = Some(wv);
+        .map_err(|e| format!("Failed to create webview: {}", e))?;
 
-    event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Wait;
-        if let Event::WindowEvent { event: WindowEvent::CloseRequested, .. } = event {
-            *control_flow = ControlFlow::Exit;
-        }
-    });
+    Ok((window, webview))
 }
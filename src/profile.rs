@@ -0,0 +1,203 @@
+use std::env;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const APP_DIR: &str = "Cypher";
+pub const DEFAULT_PROFILE: &str = "default";
+
+pub struct Profile {
+    name: String,
+    data_dir: PathBuf,
+    config_dir: PathBuf,
+    cache_dir: PathBuf,
+    _lock: File,
+}
+
+impl Profile {
+    pub fn open_default() -> Result<Self, String> {
+        Self::open_named(DEFAULT_PROFILE)
+    }
+
+    pub fn open_named(name: &str) -> Result<Self, String> {
+        let name = name.trim();
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(format!("Invalid profile name: {:?}", name));
+        }
+        Self::open(
+            name,
+            data_home().join(APP_DIR).join(name),
+            config_home().join(APP_DIR).join(name),
+            cache_home().join(APP_DIR).join(name),
+        )
+    }
+
+    // A profile directory given on the command line keeps everything
+    // together, which makes it easy to carry around or throw away.
+    pub fn open_dir(dir: PathBuf) -> Result<Self, String> {
+        let name = dir
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+        Self::open(&name, dir.clone(), dir.clone(), dir.join("cache"))
+    }
+
+    fn open(name: &str, data_dir: PathBuf, config_dir: PathBuf, cache_dir: PathBuf) -> Result<Self, String> {
+        for dir in [&data_dir, &config_dir, &cache_dir] {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create profile directory {}: {}", dir.display(), e))?;
+        }
+
+        let lock_path = data_dir.join("lock");
+        let mut lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .map_err(|e| format!("Failed to open {}: {}", lock_path.display(), e))?;
+        match lock.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(format!("Profile \"{}\" is already in use by another Cypher window", name));
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(format!("Failed to lock {}: {}", lock_path.display(), e));
+            }
+        }
+        let _ = lock.set_len(0);
+        let _ = write!(lock, "{}", std::process::id());
+
+        Ok(Self {
+            name: name.to_string(),
+            data_dir,
+            config_dir,
+            cache_dir,
+            _lock: lock,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    pub fn index_path(&self) -> PathBuf {
+        self.data_dir.join("index.json")
+    }
+
+    pub fn index_policy_path(&self) -> PathBuf {
+        self.data_dir.join("index_policy.json")
+    }
+
+    pub fn bookmarks_path(&self) -> PathBuf {
+        self.data_dir.join("bookmarks.json")
+    }
+
+    pub fn history_path(&self) -> PathBuf {
+        self.data_dir.join("history.json")
+    }
+
+    pub fn session_path(&self) -> PathBuf {
+        self.data_dir.join("session.json")
+    }
+
+    pub fn downloads_path(&self) -> PathBuf {
+        self.data_dir.join("downloads.json")
+    }
+
+    pub fn cookies_path(&self) -> PathBuf {
+        self.data_dir.join("cookies.json")
+    }
+
+    pub fn filters_dir(&self) -> PathBuf {
+        self.data_dir.join("filters")
+    }
+
+    pub fn blocking_allowlist_path(&self) -> PathBuf {
+        self.data_dir.join("blocking_allowlist.json")
+    }
+
+    pub fn settings_path(&self) -> PathBuf {
+        self.config_dir.join("settings.toml")
+    }
+
+    pub fn storage(&self) -> Vec<(&'static str, PathBuf)> {
+        vec![
+            ("Data", self.data_dir.clone()),
+            ("Configuration", self.config_dir.clone()),
+            ("Cache", self.cache_dir.clone()),
+            ("History", self.history_path()),
+            ("Bookmarks", self.bookmarks_path()),
+            ("Search index", self.index_path()),
+            ("Indexing exclusions", self.index_policy_path()),
+            ("Session", self.session_path()),
+            ("Downloads list", self.downloads_path()),
+            ("Cookies", self.cookies_path()),
+            ("Filter lists", self.filters_dir()),
+            ("Sites without blocking", self.blocking_allowlist_path()),
+            ("Settings", self.settings_path()),
+        ]
+    }
+}
+
+fn home() -> PathBuf {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+// The XDG base directory spec says relative paths in these variables are
+// invalid and must be ignored.
+fn xdg_dir(var: &str, fallback: &[&str]) -> PathBuf {
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .unwrap_or_else(|| fallback.iter().fold(home(), |path, part| path.join(part)))
+}
+
+fn data_home() -> PathBuf {
+    if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from).unwrap_or_else(home)
+    } else if cfg!(target_os = "macos") {
+        home().join("Library").join("Application Support")
+    } else {
+        xdg_dir("XDG_DATA_HOME", &[".local", "share"])
+    }
+}
+
+fn config_home() -> PathBuf {
+    if cfg!(windows) || cfg!(target_os = "macos") {
+        data_home()
+    } else {
+        xdg_dir("XDG_CONFIG_HOME", &[".config"])
+    }
+}
+
+fn cache_home() -> PathBuf {
+    if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from).unwrap_or_else(data_home)
+    } else if cfg!(target_os = "macos") {
+        home().join("Library").join("Caches")
+    } else {
+        xdg_dir("XDG_CACHE_HOME", &[".cache"])
+    }
+}
+
+// Where downloads go unless the settings say otherwise.
+pub fn download_home() -> PathBuf {
+    if cfg!(windows) || cfg!(target_os = "macos") {
+        home().join("Downloads")
+    } else {
+        xdg_dir("XDG_DOWNLOAD_DIR", &["Downloads"])
+    }
+}
+
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<(), String> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    fs::rename(&tmp_path, path).map_err(|e| format!("Failed to replace {}: {}", path.display(), e))
+}
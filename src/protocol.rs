@@ -0,0 +1,370 @@
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Mutex;
+use url::{Host, Url};
+use crate::blocking::ContentBlocker;
+use crate::cookies::{send_with_cookies, CookieJar};
+use crate::fetch::PageRequest;
+use crate::rewrite::{rewrite_css, rewrite_html};
+use crate::settings::Settings;
+
+// Proxied pages are shown under `cypher-proxy://<host>/<path>`, so every site
+// keeps one origin of its own from visit to visit. Plain http sites get a
+// `.http` suffix on the host, which no real domain can end in.
+pub const SCHEME: &str = "cypher-proxy";
+const HTTP_SUFFIX: &str = ".http";
+
+// Main documents fetched for a tab wait here until the webview asks for them,
+// so they are not fetched twice. Pages nobody asked for fall off the end.
+const PENDING_PAGES: usize = 8;
+
+// How many served documents can still report that they have loaded.
+const SERVED_DOCUMENTS: usize = 256;
+
+pub struct Resource {
+    pub status: u16,
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+impl Resource {
+    // What the frame gets instead of something the filter lists block.
+    pub fn blocked() -> Self {
+        Self {
+            status: 403,
+            content_type: "text/plain; charset=utf-8".to_string(),
+            body: Vec::new(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/html; charset=utf-8".to_string(),
+            body: format!(
+                "<!DOCTYPE html><html><head><title>Error</title></head><body><h1>Error</h1><p>{}</p></body></html>",
+                crate::snippet::escape_html(message)
+            )
+            .into_bytes(),
+        }
+    }
+}
+
+// Also remembers the real address of every document handed to a frame, under
+// an id only that document's frame script is told. The address bar is set
+// from here rather than from what a page says about itself.
+#[derive(Default)]
+pub struct PageCache {
+    pages: VecDeque<(String, Resource)>,
+    documents: VecDeque<(String, String)>,
+}
+
+impl PageCache {
+    pub fn insert(&mut self, url: &str, resource: Resource) {
+        let url = without_fragment(url);
+        self.pages.retain(|(cached, _)| *cached != url);
+        self.pages.push_back((url, resource));
+        while self.pages.len() > PENDING_PAGES {
+            self.pages.pop_front();
+        }
+    }
+
+    pub fn take(&mut self, url: &str) -> Option<Resource> {
+        let url = without_fragment(url);
+        let position = self.pages.iter().position(|(cached, _)| *cached == url)?;
+        self.pages.remove(position).map(|(_, resource)| resource)
+    }
+
+    pub fn register(&mut self, url: &str) -> String {
+        let state = RandomState::new();
+        let id = format!("{:016x}{:016x}", state.hash_one(0u8), state.hash_one(1u8));
+        self.documents.push_back((id.clone(), url.to_string()));
+        while self.documents.len() > SERVED_DOCUMENTS {
+            self.documents.pop_front();
+        }
+        id
+    }
+
+    pub fn document_url(&self, id: &str) -> Option<&str> {
+        self.documents.iter().find(|(known, _)| known == id).map(|(_, url)| url.as_str())
+    }
+}
+
+// Normalised as well, since the webview's spelling of an address need not
+// match the fetcher's character for character.
+fn without_fragment(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(mut url) => {
+            url.set_fragment(None);
+            url.to_string()
+        }
+        Err(_) => url.to_string(),
+    }
+}
+
+// `https://example.com:8443/a?b#c` becomes `cypher-proxy://example.com:8443/a?b#c`.
+pub fn to_proxy_url(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let suffix = match url.scheme() {
+        "https" => "",
+        "http" => HTTP_SUFFIX,
+        _ => return None,
+    };
+    // WebView2 puts the host inside its own hostname, which an IPv6 literal
+    // cannot be.
+    let host = match url.host()? {
+        Host::Domain(domain) => domain.to_string(),
+        Host::Ipv4(address) => address.to_string(),
+        Host::Ipv6(_) => return None,
+    };
+    let port = url.port().map(|port| format!(":{}", port)).unwrap_or_default();
+    let mut proxied = format!("{}://{}{}{}{}", SCHEME, host, suffix, port, url.path());
+    if let Some(query) = url.query() {
+        proxied.push('?');
+        proxied.push_str(query);
+    }
+    if let Some(fragment) = url.fragment() {
+        proxied.push('#');
+        proxied.push_str(fragment);
+    }
+    Some(proxied)
+}
+
+// The inverse of `to_proxy_url`. WebView2 spells custom schemes as
+// `http://cypher-proxy.<host>/`, so that form is accepted too.
+pub fn from_proxy_url(proxied: &str) -> Option<String> {
+    let rest = [
+        format!("{}://", SCHEME),
+        format!("http://{}.", SCHEME),
+        format!("https://{}.", SCHEME),
+    ]
+    .iter()
+    .find_map(|prefix| proxied.strip_prefix(prefix.as_str()))?;
+    let parsed = Url::parse(&format!("https://{}", rest)).ok()?;
+    let host = parsed.host_str()?;
+    let (scheme, host) = match host.strip_suffix(HTTP_SUFFIX) {
+        Some(host) => ("http", host),
+        None => ("https", host),
+    };
+    let mut url = Url::parse(&format!("{}://{}", scheme, host)).ok()?;
+    url.set_port(parsed.port()).ok()?;
+    url.set_path(parsed.path());
+    url.set_query(parsed.query());
+    url.set_fragment(parsed.fragment());
+    Some(url.to_string())
+}
+
+// What the content frame is pointed at. Only WebView2 needs the http form.
+pub fn view_url(url: &str) -> Option<String> {
+    let proxied = to_proxy_url(url)?;
+    if cfg!(windows) {
+        Some(proxied.replacen(&format!("{}://", SCHEME), &format!("http://{}.", SCHEME), 1))
+    } else {
+        Some(proxied)
+    }
+}
+
+// The real address of the page a frame request came from. The Referer names
+// it, or failing that the Origin; the chrome's own requests have neither.
+pub fn requested_by(request: &PageRequest) -> Option<String> {
+    request
+        .headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("referer") || name.eq_ignore_ascii_case("origin"))
+        .find_map(|(_, value)| from_proxy_url(value))
+}
+
+// Answers one request from the content frame: the main document if it is
+// waiting in the cache, otherwise whatever the fetcher brings back. Posts
+// reach here from forms the frame script could not catch, such as ones sent
+// with `form.submit()`, and from the page's own scripts.
+pub fn serve(
+    settings: &Settings,
+    cache: &Mutex<PageCache>,
+    cookies: &Mutex<CookieJar>,
+    blocker: &Mutex<ContentBlocker>,
+    request: PageRequest,
+) -> Resource {
+    let Some(url) = from_proxy_url(&request.url) else {
+        return Resource::error(400, &format!("Not a proxied address: {}", request.url));
+    };
+    if !matches!(request.method.as_str(), "GET" | "HEAD" | "POST") {
+        return Resource::error(405, &format!("{} requests are not supported", request.method));
+    }
+    if request.method == "GET" {
+        if let Some(resource) = cache.lock().unwrap().take(&url) {
+            return resource;
+        }
+    }
+    // The page that asked decides whose cookies go along. The headers that
+    // say so describe the frame, not the site, and are not sent upstream.
+    let top_level = requested_by(&request).unwrap_or_else(|| url.clone());
+    let headers = request
+        .headers
+        .into_iter()
+        .filter(|(name, _)| !["referer", "origin", "sec-fetch-dest"].iter().any(|n| name.eq_ignore_ascii_case(n)))
+        .collect();
+    let request = PageRequest {
+        url: without_fragment(&url),
+        headers,
+        ..request
+    };
+    match send_with_cookies(settings, cookies, &request, Some(&top_level), |_, _| {}) {
+        Ok(page) if page.mime() == "text/html" => {
+            let hiding = if settings.blocking.enabled {
+                blocker.lock().unwrap().hiding_css(&page.url)
+            } else {
+                String::new()
+            };
+            let document = cache.lock().unwrap().register(&page.url);
+            Resource {
+                status: page.status,
+                content_type: "text/html; charset=utf-8".to_string(),
+                body: prepare_page(&page.text(), &page.url, &hiding, &document),
+            }
+        }
+        Ok(page) if page.mime() == "text/css" => Resource {
+            status: page.status,
+            content_type: "text/css; charset=utf-8".to_string(),
+            body: rewrite_css(&page.text(), &page.url, &view_url).into_bytes(),
+        },
+        Ok(page) => Resource {
+            status: page.status,
+            content_type: page.content_type,
+            body: page.body,
+        },
+        Err(e) => Resource::error(502, &e),
+    }
+}
+
+// Points everything the page refers to back through the proxy and adds the
+// frame script, and the filter lists' hiding rules when there are any.
+// `document` is the id from `PageCache::register`.
+pub fn prepare_page(html: &str, url: &str, hiding_css: &str, document: &str) -> Vec<u8> {
+    let mut head = frame_script(document);
+    if !hiding_css.is_empty() {
+        head.push_str(&format!("<style>{}</style>", hiding_css));
+    }
+    inject_head(&rewrite_html(html, url, &view_url), &head).into_bytes()
+}
+
+pub fn inject_frame_script(html: &str, document: &str) -> String {
+    inject_head(html, &frame_script(document))
+}
+
+fn frame_script(document: &str) -> String {
+    format!("<script>{}({});</script>", FRAME_SCRIPT, serde_json::Value::from(document))
+}
+
+// Puts `markup` at the top of <head>, or as near it as the markup allows,
+// without pushing the doctype out of first place.
+fn inject_head(html: &str, markup: &str) -> String {
+    let lower = html.to_ascii_lowercase();
+    let after_tag = |name: &str| {
+        let start = lower.find(&format!("<{}", name))?;
+        let next = lower.as_bytes().get(start + name.len() + 1).copied();
+        if !matches!(next, Some(b'>' | b' ' | b'\t' | b'\n' | b'\r')) {
+            return None;
+        }
+        lower[start..].find('>').map(|end| start + end + 1)
+    };
+    let position = after_tag("head")
+        .or_else(|| after_tag("html"))
+        .or_else(|| after_tag("!doctype"))
+        .unwrap_or(0);
+    format!("{}{}{}", &html[..position], markup, &html[position..])
+}
+
+// Runs inside every proxied page. The frame has an origin of its own, so the
+// chrome cannot reach in; instead this reports to it with postMessage.
+const FRAME_SCRIPT: &str = r#"(function (documentId) {
+    if (window.parent === window) return;
+    const post = (message) => window.parent.postMessage(Object.assign({ cypher: true }, message), '*');
+    const realUrl = (href) => {
+        const match = /^(?:cypher-proxy:\/\/|https?:\/\/cypher-proxy\.)([^\/?#]*)(.*)$/.exec(href);
+        if (!match) return href;
+        let host = match[1];
+        let port = '';
+        const portMatch = /:\d+$/.exec(host);
+        if (portMatch) {
+            port = portMatch[0];
+            host = host.slice(0, -port.length);
+        }
+        const scheme = host.endsWith('.http') ? 'http' : 'https';
+        if (scheme === 'http') host = host.slice(0, -5);
+        return scheme + '://' + host + port + match[2];
+    };
+    const linkFor = (event) => {
+        const link = event.target.closest ? event.target.closest('a[href]') : null;
+        if (!link || link.getAttribute('href').startsWith('#')) return null;
+        if (link.hash && link.href.split('#')[0] === location.href.split('#')[0]) return null;
+        const url = realUrl(link.href);
+        return /^https?:/.test(url) ? { link: link, url: url } : null;
+    };
+    document.addEventListener('click', (event) => {
+        const found = event.defaultPrevented ? null : linkFor(event);
+        if (!found) return;
+        event.preventDefault();
+        const background = event.ctrlKey || event.metaKey;
+        post({ type: 'open', url: found.url, target: background ? 'background' : (found.link.target === '_blank' ? 'tab' : 'self') });
+    });
+    document.addEventListener('auxclick', (event) => {
+        const found = event.button === 1 ? linkFor(event) : null;
+        if (found) {
+            event.preventDefault();
+            post({ type: 'open', url: found.url, target: 'background' });
+        }
+    });
+    const base64 = (buffer) => {
+        const bytes = new Uint8Array(buffer);
+        let binary = '';
+        for (let i = 0; i < bytes.length; i += 0x8000) {
+            binary += String.fromCharCode.apply(null, bytes.subarray(i, i + 0x8000));
+        }
+        return btoa(binary);
+    };
+    // Forms are encoded and sent by the browser so the result shows up as a
+    // navigation of the tab. Pages that handle submission themselves have
+    // called preventDefault by the time this runs.
+    document.addEventListener('submit', async (event) => {
+        const form = event.target;
+        const submitter = event.submitter || null;
+        const attribute = (name, fallback) =>
+            (submitter && submitter.hasAttribute('form' + name)) ? submitter.getAttribute('form' + name) : fallback;
+        const method = attribute('method', form.getAttribute('method') || 'get').toLowerCase();
+        const action = realUrl(submitter && submitter.hasAttribute('formaction') ? submitter.formAction : form.action);
+        if (event.defaultPrevented || method === 'dialog' || !/^https?:/.test(action)) return;
+        event.preventDefault();
+        let data;
+        try {
+            data = new FormData(form, submitter);
+        } catch (e) {
+            data = new FormData(form);
+            if (submitter && submitter.name) data.append(submitter.name, submitter.value);
+        }
+        const fields = await Promise.all(Array.from(data.entries()).map(async ([name, value]) =>
+            typeof value === 'string'
+                ? { name: name, value: value }
+                : { name: name, value: '', file: { name: value.name, content_type: value.type, data: base64(await value.arrayBuffer()) } }));
+        post({
+            type: 'submit',
+            form: {
+                action: action,
+                method: method,
+                enctype: attribute('enctype', form.enctype),
+                accept_charset: form.acceptCharset,
+                fields: fields,
+            },
+        });
+    });
+    window.addEventListener('scroll', () => post({ type: 'scroll', y: window.scrollY }));
+    window.addEventListener('message', (event) => {
+        if (event.source === window.parent && event.data && event.data.cypher && event.data.type === 'scroll-to') {
+            window.scrollTo(0, event.data.y);
+        }
+    });
+    window.addEventListener('load', () => {
+        const icon = document.querySelector('link[rel~="icon"][href]');
+        post({ type: 'loaded', document: documentId, title: document.title, favicon: icon ? realUrl(icon.href) : null });
+    });
+})"#;
@@ -0,0 +1,372 @@
+use html5ever::buffer_queue::BufferQueue;
+use html5ever::tendril::StrTendril;
+use html5ever::tokenizer::states::RawKind;
+use html5ever::tokenizer::{
+    Tag, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer, TokenizerOpts,
+};
+use url::Url;
+
+// Turns an absolute http(s) URL into the address the page should use for it
+// instead, or None to leave it alone.
+pub type UrlMapper<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+// Rewrites every URL a page refers to, resolved against `page_url` (or its
+// <base>), through `map`. The markup goes through html5ever's tokenizer and is
+// written back token by token, so only attribute values and stylesheets change;
+// scripts and text are left exactly as they were.
+pub fn rewrite_html(html: &str, page_url: &str, map: UrlMapper) -> String {
+    let Ok(base) = Url::parse(page_url) else {
+        return html.to_string();
+    };
+    let sink = Rewriter {
+        out: String::with_capacity(html.len() + html.len() / 8),
+        base,
+        base_seen: false,
+        map,
+        raw: None,
+        style: None,
+    };
+    let mut tokenizer = Tokenizer::new(sink, TokenizerOpts::default());
+    let mut input = BufferQueue::default();
+    input.push_back(StrTendril::from_slice(html));
+    let _ = tokenizer.feed(&mut input);
+    tokenizer.end();
+    tokenizer.sink.out
+}
+
+// Rewrites `url(...)` references and `@import` strings in a stylesheet,
+// resolved against `base`. Comments and everything else pass through.
+pub fn rewrite_css(css: &str, base: &str, map: UrlMapper) -> String {
+    match Url::parse(base) {
+        Ok(base) => rewrite_css_with(css, &base, map),
+        Err(_) => css.to_string(),
+    }
+}
+
+struct Rewriter<'a> {
+    out: String,
+    base: Url,
+    base_seen: bool,
+    map: UrlMapper<'a>,
+    // The element whose contents are raw text, copied verbatim until it ends.
+    raw: Option<String>,
+    // The contents of a <style> element, rewritten once it is complete.
+    style: Option<String>,
+}
+
+impl Rewriter<'_> {
+    fn resolve(&self, value: &str) -> Option<String> {
+        let value = value.trim();
+        if value.is_empty() || value.starts_with('#') {
+            return None;
+        }
+        let url = self.base.join(value).ok()?;
+        (self.map)(url.as_str())
+    }
+
+    fn rewrite_tag(&mut self, tag: &mut Tag) {
+        let name = tag.name.to_string();
+        // Only the first <base href> counts, as in browsers. Later URLs on the
+        // page resolve against it, and it is itself pointed into the proxy so
+        // scripts building URLs from document.baseURI stay there too.
+        if name == "base" && !self.base_seen {
+            if let Some(href) = tag.attrs.iter().find(|a| &*a.name.local == "href") {
+                if let Ok(base) = self.base.join(href.value.trim()) {
+                    self.base = base;
+                    self.base_seen = true;
+                }
+            }
+        }
+        let refresh = name == "meta"
+            && tag.attrs.iter().any(|a| {
+                &*a.name.local == "http-equiv" && a.value.trim().eq_ignore_ascii_case("refresh")
+            });
+
+        for attribute in tag.attrs.iter_mut() {
+            let attribute_name = attribute.name.local.to_string();
+            let value = attribute.value.to_string();
+            let rewritten = match (name.as_str(), attribute_name.as_str()) {
+                (_, "style") => Some(rewrite_css_with(&value, &self.base, self.map)),
+                (_, "srcset") | ("link", "imagesrcset") => Some(self.rewrite_srcset(&value)),
+                ("meta", "content") if refresh => self.rewrite_refresh(&value),
+                (element, attribute) if is_url_attribute(element, attribute) => self.resolve(&value),
+                _ => None,
+            };
+            if let Some(rewritten) = rewritten {
+                attribute.value = StrTendril::from_slice(&rewritten);
+            }
+        }
+    }
+
+    // "small.png 1x, large.png 2x": each candidate is a URL followed by
+    // optional descriptors, separated by commas.
+    fn rewrite_srcset(&self, value: &str) -> String {
+        let mut candidates = Vec::new();
+        let mut rest = value;
+        loop {
+            rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+            if rest.is_empty() {
+                break;
+            }
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let mut url = &rest[..end];
+            rest = &rest[end..];
+            let descriptors = if url.ends_with(',') {
+                url = url.trim_end_matches(',');
+                ""
+            } else {
+                let end = rest.find(',').unwrap_or(rest.len());
+                let descriptors = rest[..end].trim();
+                rest = &rest[end..];
+                descriptors
+            };
+            let url = self.resolve(url).unwrap_or_else(|| url.to_string());
+            candidates.push(if descriptors.is_empty() {
+                url
+            } else {
+                format!("{} {}", url, descriptors)
+            });
+        }
+        candidates.join(", ")
+    }
+
+    // `<meta http-equiv="refresh" content="5; url=next.html">`
+    fn rewrite_refresh(&self, value: &str) -> Option<String> {
+        let (delay, target) = value.split_once([';', ','])?;
+        let target = target.trim_start();
+        let url = match target.get(..4) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("url=") => &target[4..],
+            _ => target,
+        };
+        let url = url.trim().trim_matches(['"', '\'']);
+        Some(format!("{}; url={}", delay.trim(), self.resolve(url)?))
+    }
+
+    fn write_tag(&mut self, tag: &Tag) {
+        match tag.kind {
+            TagKind::StartTag => {
+                self.out.push('<');
+                self.out.push_str(&tag.name);
+                for attribute in &tag.attrs {
+                    self.out.push(' ');
+                    self.out.push_str(&attribute.name.local);
+                    if !attribute.value.is_empty() {
+                        self.out.push_str("=\"");
+                        escape(&mut self.out, &attribute.value, true);
+                        self.out.push('"');
+                    }
+                }
+                self.out.push_str(if tag.self_closing { "/>" } else { ">" });
+            }
+            TagKind::EndTag => {
+                self.out.push_str("</");
+                self.out.push_str(&tag.name);
+                self.out.push('>');
+            }
+        }
+    }
+}
+
+impl TokenSink for Rewriter<'_> {
+    type Handle = ();
+
+    fn process_token(&mut self, token: Token, _line: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::TagToken(mut tag) => {
+                if tag.kind == TagKind::EndTag && self.raw.as_deref() == Some(&*tag.name) {
+                    self.raw = None;
+                    if let Some(css) = self.style.take() {
+                        let css = rewrite_css_with(&css, &self.base, self.map);
+                        self.out.push_str(&css);
+                    }
+                }
+                if tag.kind == TagKind::StartTag {
+                    self.rewrite_tag(&mut tag);
+                }
+                self.write_tag(&tag);
+                if tag.kind == TagKind::StartTag && !tag.self_closing {
+                    // The tokenizer on its own does not know which elements
+                    // hold raw text; a tree builder would tell it.
+                    let kind = match &*tag.name {
+                        "script" => Some(RawKind::ScriptData),
+                        "style" | "xmp" | "iframe" | "noembed" | "noframes" | "noscript" => Some(RawKind::Rawtext),
+                        "title" | "textarea" => Some(RawKind::Rcdata),
+                        "plaintext" => return TokenSinkResult::Plaintext,
+                        _ => None,
+                    };
+                    if let Some(kind) = kind {
+                        if kind != RawKind::Rcdata {
+                            self.raw = Some(tag.name.to_string());
+                        }
+                        if &*tag.name == "style" {
+                            self.style = Some(String::new());
+                        }
+                        return TokenSinkResult::RawData(kind);
+                    }
+                }
+            }
+            Token::CharacterTokens(text) => match (&mut self.style, &self.raw) {
+                (Some(style), _) => style.push_str(&text),
+                (None, Some(_)) => self.out.push_str(&text),
+                (None, None) => escape(&mut self.out, &text, false),
+            },
+            Token::CommentToken(text) => {
+                self.out.push_str("<!--");
+                self.out.push_str(&text);
+                self.out.push_str("-->");
+            }
+            Token::DoctypeToken(doctype) => {
+                self.out.push_str("<!DOCTYPE");
+                if let Some(name) = &doctype.name {
+                    self.out.push(' ');
+                    self.out.push_str(name);
+                }
+                match (&doctype.public_id, &doctype.system_id) {
+                    (Some(public), Some(system)) => {
+                        self.out.push_str(&format!(" PUBLIC \"{}\" \"{}\"", public, system));
+                    }
+                    (Some(public), None) => self.out.push_str(&format!(" PUBLIC \"{}\"", public)),
+                    (None, Some(system)) => self.out.push_str(&format!(" SYSTEM \"{}\"", system)),
+                    (None, None) => {}
+                }
+                self.out.push('>');
+            }
+            Token::NullCharacterToken => self.out.push('\0'),
+            Token::EOFToken => {
+                if let Some(css) = self.style.take() {
+                    let css = rewrite_css_with(&css, &self.base, self.map);
+                    self.out.push_str(&css);
+                }
+            }
+            Token::ParseError(_) => {}
+        }
+        TokenSinkResult::Continue
+    }
+}
+
+// Which attributes hold a single URL, by element.
+fn is_url_attribute(element: &str, attribute: &str) -> bool {
+    matches!(
+        (element, attribute),
+        ("a" | "area" | "link" | "base", "href")
+            | ("image" | "use" | "feimage", "href" | "xlink:href")
+            | ("img" | "script" | "iframe" | "frame" | "embed" | "source" | "track" | "audio" | "video" | "input", "src")
+            | ("video", "poster")
+            | ("form", "action")
+            | ("button" | "input", "formaction")
+            | ("object", "data")
+            | ("blockquote" | "q" | "del" | "ins", "cite")
+            | ("body" | "table" | "td" | "th", "background")
+    )
+}
+
+// The html5ever serializer's escaping: attribute values only need `&` and
+// `"` escaped, text needs `&`, `<` and `>`.
+fn escape(out: &mut String, text: &str, attribute: bool) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '\u{a0}' => out.push_str("&nbsp;"),
+            '"' if attribute => out.push_str("&quot;"),
+            '<' if !attribute => out.push_str("&lt;"),
+            '>' if !attribute => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn rewrite_css_with(css: &str, base: &Url, map: UrlMapper) -> String {
+    let resolve = |value: &str| -> Option<String> {
+        let value = value.trim();
+        if value.is_empty() || value.starts_with('#') {
+            return None;
+        }
+        map(base.join(value).ok()?.as_str())
+    };
+
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(position) = rest.find(['/', '"', '\'', 'u', 'U', '@']) {
+        out.push_str(&rest[..position]);
+        rest = &rest[position..];
+
+        if rest.starts_with("/*") {
+            let end = rest[2..].find("*/").map(|end| end + 4).unwrap_or(rest.len());
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if rest.starts_with(['"', '\'']) {
+            let end = string_end(rest);
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if starts_with_ignore_case(rest, "url(") && !out.ends_with(is_name_char) {
+            let inner = rest[4..].trim_start();
+            let (value, quote, end) = if inner.starts_with(['"', '\'']) {
+                let end = string_end(inner);
+                let quote = &inner[..1];
+                let value = inner[1..end].strip_suffix(quote).unwrap_or(&inner[1..end]);
+                (value, quote, end)
+            } else {
+                let end = inner.find(')').unwrap_or(inner.len());
+                (inner[..end].trim_end(), "", end)
+            };
+            let after = inner[end..].trim_start();
+            let Some(after) = after.strip_prefix(')') else {
+                out.push_str(&rest[..4]);
+                rest = &rest[4..];
+                continue;
+            };
+            match resolve(value) {
+                Some(url) => out.push_str(&format!("url({}{}{})", quote, url, quote)),
+                None => out.push_str(&rest[..rest.len() - after.len()]),
+            }
+            rest = after;
+        } else if starts_with_ignore_case(rest, "@import") {
+            out.push_str(&rest[..7]);
+            rest = &rest[7..];
+            let spaces = rest.len() - rest.trim_start().len();
+            out.push_str(&rest[..spaces]);
+            rest = &rest[spaces..];
+            // `@import url(...)` is taken care of on the next pass.
+            if rest.starts_with(['"', '\'']) {
+                let end = string_end(rest);
+                let quote = &rest[..1];
+                let value = rest[1..end].strip_suffix(quote).unwrap_or(&rest[1..end]);
+                match resolve(value) {
+                    Some(url) => out.push_str(&format!("{}{}{}", quote, url, quote)),
+                    None => out.push_str(&rest[..end]),
+                }
+                rest = &rest[end..];
+            }
+        } else {
+            let next = rest.chars().next().map(char::len_utf8).unwrap_or(1);
+            out.push_str(&rest[..next]);
+            rest = &rest[next..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// Where the quoted string at the start of `text` ends, past its closing quote.
+fn string_end(text: &str) -> usize {
+    let quote = text.as_bytes()[0];
+    let bytes = text.as_bytes();
+    let mut i = 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'\n' => return i,
+            byte if byte == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.get(..prefix.len()).is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
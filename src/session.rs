@@ -0,0 +1,51 @@
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::profile::write_atomic;
+use crate::tabs::TabManager;
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct WindowBounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub maximized: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SessionWindow {
+    pub tabs: TabManager,
+    pub bounds: Option<WindowBounds>,
+}
+
+// The session is rewritten with `clean_exit: false` while the browser runs and
+// only marked clean on a normal shutdown, so finding an unclean session at
+// startup means the previous run crashed.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    pub clean_exit: bool,
+    pub windows: Vec<SessionWindow>,
+}
+
+impl Session {
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read session: {}", e))?;
+        let mut session: Self = serde_json::from_str(&data)
+            .map_err(|e| format!("Invalid session file: {}", e))?;
+        for window in session.windows.iter_mut() {
+            window.tabs.repair();
+        }
+        Ok(Some(session))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let data = serde_json::to_string(self)
+            .map_err(|e| format!("Failed to serialize session: {}", e))?;
+        write_atomic(path, data.as_bytes())
+    }
+}
@@ -0,0 +1,335 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use reqwest::header::HeaderValue;
+use serde::{Deserialize, Serialize};
+use crate::profile::{download_home, write_atomic};
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowSettings {
+    pub title: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        Self {
+            title: "Cypher Browser".to_string(),
+            width: 1920,
+            height: 1080,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchSettings {
+    pub results_url: String,
+    pub query_url: String,
+    pub results_per_page: usize,
+}
+
+impl Default for SearchSettings {
+    fn default() -> Self {
+        Self {
+            results_url: "https://html.duckduckgo.com/html/".to_string(),
+            query_url: "https://duckduckgo.com/?q={query}".to_string(),
+            results_per_page: 15,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fetcher {
+    Direct,
+    #[default]
+    Proxy,
+}
+
+impl Fetcher {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "direct" => Some(Self::Direct),
+            "proxy" => Some(Self::Proxy),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    pub user_agent: String,
+    pub proxy_url: String,
+    pub fetcher: Fetcher,
+    pub max_page_mb: u64,
+}
+
+impl NetworkSettings {
+    pub fn max_page_bytes(&self) -> u64 {
+        self.max_page_mb * 1024 * 1024
+    }
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            user_agent: "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36".to_string(),
+            proxy_url: "http://localhost:8080".to_string(),
+            fetcher: Fetcher::Proxy,
+            // The same ceiling the proxy itself enforces.
+            max_page_mb: 50,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownloadSettings {
+    pub directory: PathBuf,
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        Self { directory: download_home() }
+    }
+}
+
+// Filter lists are read from the `filters` folder in the profile.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockingSettings {
+    pub enabled: bool,
+}
+
+impl Default for BlockingSettings {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+// Query parameters whose names match one of `params` are removed from
+// addresses on `sites` and their subdomains, or on every site when `sites`
+// is empty. `*` in a pattern stands for any run of characters.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CleaningRule {
+    #[serde(default)]
+    pub sites: Vec<String>,
+    pub params: Vec<String>,
+}
+
+impl CleaningRule {
+    fn new(sites: &[&str], params: &[&str]) -> Self {
+        Self {
+            sites: sites.iter().map(|s| s.to_string()).collect(),
+            params: params.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+}
+
+// `exceptions` lists the sites whose addresses are left exactly as they are.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UrlCleaningSettings {
+    pub enabled: bool,
+    pub rules: Vec<CleaningRule>,
+    pub exceptions: Vec<String>,
+}
+
+impl Default for UrlCleaningSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rules: vec![
+                CleaningRule::new(
+                    &[],
+                    &[
+                        "utm_*", "fbclid", "gclid", "gclsrc", "dclid", "gbraid", "wbraid", "msclkid", "yclid",
+                        "twclid", "ttclid", "igshid", "mc_cid", "mc_eid", "_hsenc", "_hsmi", "__hssc", "__hstc",
+                        "__hsfp", "mkt_tok", "oly_anon_id", "oly_enc_id", "vero_id", "_openstat",
+                    ],
+                ),
+                CleaningRule::new(
+                    &["amazon.com", "amazon.co.uk", "amazon.de", "amazon.fr", "amazon.ca"],
+                    &["pd_rd_*", "pf_rd_*", "ref_", "_encoding"],
+                ),
+                CleaningRule::new(&["youtube.com", "youtu.be"], &["si"]),
+                CleaningRule::new(&["twitter.com", "x.com"], &["s", "t"]),
+            ],
+            exceptions: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub window: WindowSettings,
+    pub search: SearchSettings,
+    pub network: NetworkSettings,
+    pub downloads: DownloadSettings,
+    pub blocking: BlockingSettings,
+    pub url_cleaning: UrlCleaningSettings,
+}
+
+impl Settings {
+    // A missing file is created with the defaults so there is something to
+    // edit; a broken one is reported and left alone.
+    pub fn load(path: &Path) -> Self {
+        if !path.exists() {
+            let settings = Self::default();
+            if let Err(e) = settings.save(path) {
+                eprintln!("{}", e);
+            }
+            return settings;
+        }
+        Self::read(path)
+    }
+
+    // Like `load`, but a missing file is left missing.
+    pub fn read(path: &Path) -> Self {
+        if !path.exists() {
+            return Self::default();
+        }
+
+        let result = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read settings: {}", e))
+            .and_then(|data| toml::from_str::<Self>(&data).map_err(|e| format!("Invalid settings file: {}", e)))
+            .and_then(|settings| settings.validate().map(|_| settings));
+        result.unwrap_or_else(|e| {
+            eprintln!("{} ({}); using defaults", e, path.display());
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        self.validate()?;
+        let data = toml::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize settings: {}", e))?;
+        write_atomic(path, data.as_bytes())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.window.title.trim().is_empty() {
+            return Err("The window title cannot be empty".to_string());
+        }
+        if !(400..=10_000).contains(&self.window.width) || !(300..=10_000).contains(&self.window.height) {
+            return Err(format!(
+                "Window size {}x{} is out of range",
+                self.window.width, self.window.height
+            ));
+        }
+        if !(1..=100).contains(&self.search.results_per_page) {
+            return Err("Results per page must be between 1 and 100".to_string());
+        }
+        if !self.search.query_url.contains("{query}") {
+            return Err("The search URL must contain {query}".to_string());
+        }
+        if self.downloads.directory.as_os_str().is_empty() {
+            return Err("The download folder cannot be empty".to_string());
+        }
+        if !(1..=1024).contains(&self.network.max_page_mb) {
+            return Err("The page size limit must be between 1 and 1024 MB".to_string());
+        }
+        if self.network.user_agent.trim().is_empty() {
+            return Err("The user agent cannot be empty".to_string());
+        }
+        // It goes out as a header, so a line break or control character would
+        // keep every request from being built.
+        HeaderValue::from_str(&self.network.user_agent)
+            .map_err(|_| "The user agent contains characters not allowed in a header".to_string())?;
+        for (name, value) in [
+            ("search results URL", &self.search.results_url),
+            ("search URL", &self.search.query_url),
+            ("proxy URL", &self.network.proxy_url),
+        ] {
+            let parsed = url::Url::parse(&value.replace("{query}", ""))
+                .map_err(|e| format!("Invalid {}: {}", name, e))?;
+            if parsed.scheme() != "http" && parsed.scheme() != "https" {
+                return Err(format!("The {} must use http or https", name));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn proxy_endpoint(&self, path: &str) -> String {
+        format!("{}{}", self.network.proxy_url.trim_end_matches('/'), path)
+    }
+}
+
+// Values given on the command line apply to this run only, so they are laid
+// over whatever is saved instead of being written to the settings file.
+#[derive(Clone, Default)]
+pub struct SettingsOverrides {
+    pub proxy_url: Option<String>,
+    pub fetcher: Option<Fetcher>,
+}
+
+impl SettingsOverrides {
+    fn apply(&self, mut settings: Settings) -> Settings {
+        if let Some(proxy_url) = &self.proxy_url {
+            settings.network.proxy_url = proxy_url.clone();
+        }
+        if let Some(fetcher) = self.fetcher {
+            settings.network.fetcher = fetcher;
+        }
+        settings
+    }
+
+    // Settings come back from the settings page with this run's values in
+    // them, so the overridden ones are put back to what was saved.
+    fn restore(&self, mut settings: Settings, saved: &Settings) -> Settings {
+        if self.proxy_url.is_some() {
+            settings.network.proxy_url = saved.network.proxy_url.clone();
+        }
+        if self.fetcher.is_some() {
+            settings.network.fetcher = saved.network.fetcher;
+        }
+        settings
+    }
+}
+
+pub struct SettingsStore {
+    settings: Settings,
+    // What is in the file, without the overrides.
+    saved: Settings,
+    overrides: SettingsOverrides,
+    // None when changes are not saved.
+    path: Option<PathBuf>,
+}
+
+impl SettingsStore {
+    pub fn load(path: PathBuf, overrides: SettingsOverrides) -> Result<Self, String> {
+        let saved = Settings::load(&path);
+        Self::new(saved, overrides, Some(path))
+    }
+
+    // For `--private`: the saved settings are used, but neither the file nor
+    // any change made on the settings page is written.
+    pub fn load_private(path: &Path, overrides: SettingsOverrides) -> Result<Self, String> {
+        Self::new(Settings::read(path), overrides, None)
+    }
+
+    fn new(saved: Settings, overrides: SettingsOverrides, path: Option<PathBuf>) -> Result<Self, String> {
+        let settings = overrides.apply(saved.clone());
+        settings.validate()?;
+        Ok(Self { settings, saved, overrides, path })
+    }
+
+    pub fn get(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn replace(&mut self, settings: Settings) -> Result<(), String> {
+        let saved = self.overrides.restore(settings, &self.saved);
+        match &self.path {
+            Some(path) => saved.save(path)?,
+            None => saved.validate()?,
+        }
+        self.settings = self.overrides.apply(saved.clone());
+        self.saved = saved;
+        Ok(())
+    }
+}
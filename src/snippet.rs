@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+use crate::indexer::tokenize;
+
+const SNIPPET_WORDS: usize = 32;
+
+pub fn make_snippet(content: &str, query: &str) -> String {
+    let terms: HashSet<String> = tokenize(query).into_iter().collect();
+    let words: Vec<&str> = content.split_whitespace().collect();
+    if words.is_empty() {
+        return String::new();
+    }
+
+    let matches: Vec<HashSet<String>> = words
+        .iter()
+        .map(|w| tokenize(w).into_iter().filter(|t| terms.contains(t)).collect())
+        .collect();
+
+    let window = SNIPPET_WORDS.min(words.len());
+    let mut best_start = 0;
+    let mut best_score = (0, 0);
+
+    for start in 0..=(words.len() - window) {
+        let mut distinct: HashSet<&String> = HashSet::new();
+        let mut total = 0;
+        for hit in &matches[start..start + window] {
+            distinct.extend(hit.iter());
+            total += hit.len();
+        }
+        let score = (distinct.len(), total);
+        if score > best_score {
+            best_score = score;
+            best_start = start;
+        }
+    }
+
+    let end = best_start + window;
+    let mut snippet = String::new();
+    if best_start > 0 {
+        snippet.push_str("… ");
+    }
+    snippet.push_str(&highlight(&words[best_start..end].join(" "), &terms));
+    if end < words.len() {
+        snippet.push_str(" …");
+    }
+    snippet
+}
+
+pub fn highlight(text: &str, terms: &HashSet<String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut word = String::new();
+
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            word.push(c);
+        } else {
+            push_word(&mut out, &word, terms);
+            word.clear();
+            push_escaped(&mut out, c);
+        }
+    }
+    push_word(&mut out, &word, terms);
+    out
+}
+
+// Turns what `highlight` made back into the text it was given, for output
+// that is not going into a page.
+pub fn unhighlight(html: &str) -> String {
+    html.replace("<mark>", "")
+        .replace("</mark>", "")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        push_escaped(&mut out, c);
+    }
+    out
+}
+
+fn push_word(out: &mut String, word: &str, terms: &HashSet<String>) {
+    if word.is_empty() {
+        return;
+    }
+    if terms.contains(&word.to_lowercase()) {
+        out.push_str("<mark>");
+        out.push_str(&escape_html(word));
+        out.push_str("</mark>");
+    } else {
+        out.push_str(&escape_html(word));
+    }
+}
+
+fn push_escaped(out: &mut String, c: char) {
+    match c {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '"' => out.push_str("&quot;"),
+        '\'' => out.push_str("&#39;"),
+        _ => out.push(c),
+    }
+}
@@ -0,0 +1,270 @@
+use serde::{Deserialize, Serialize};
+use crate::history::{HistoryEntry, HistoryTarget, NavigationStack};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Tab {
+    pub id: u64,
+    stack: NavigationStack,
+    #[serde(default)]
+    scroll: f64,
+    #[serde(skip)]
+    loading: bool,
+    #[serde(skip)]
+    navigation: u64,
+    // The address of the page the tab shows, which the next navigation in
+    // it starts from.
+    #[serde(skip)]
+    page: Option<String>,
+}
+
+impl Tab {
+    fn new(id: u64) -> Self {
+        let mut stack = NavigationStack::default();
+        stack.push(HistoryEntry::new(HistoryTarget::Home));
+        Self {
+            id,
+            stack,
+            scroll: 0.0,
+            loading: false,
+            navigation: 0,
+            page: None,
+        }
+    }
+
+    pub fn current(&self) -> Option<HistoryTarget> {
+        self.stack.current().map(|e| e.target.clone())
+    }
+
+    pub fn scroll(&self) -> f64 {
+        self.scroll
+    }
+
+    pub fn page(&self) -> Option<&str> {
+        self.page.as_deref()
+    }
+
+    fn info(&self) -> TabInfo {
+        let current = self.stack.current();
+        TabInfo {
+            id: self.id,
+            title: current.map(|e| e.title.clone()).unwrap_or_default(),
+            target: current.map(|e| e.target.clone()).unwrap_or(HistoryTarget::Home),
+            loading: self.loading,
+            can_go_back: self.stack.can_go_back(),
+            can_go_forward: self.stack.can_go_forward(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TabInfo {
+    pub id: u64,
+    pub title: String,
+    pub target: HistoryTarget,
+    pub loading: bool,
+    pub can_go_back: bool,
+    pub can_go_forward: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TabManager {
+    tabs: Vec<Tab>,
+    active: u64,
+    next_id: u64,
+}
+
+impl Default for TabManager {
+    fn default() -> Self {
+        Self {
+            tabs: vec![Tab::new(1)],
+            active: 1,
+            next_id: 2,
+        }
+    }
+}
+
+impl TabManager {
+    pub fn with_tab(tab: Tab) -> Self {
+        let mut manager = Self { tabs: Vec::new(), active: 0, next_id: 1 };
+        manager.insert(tab, None, true);
+        manager
+    }
+
+    // A saved tab set may be empty or point at a tab that no longer exists,
+    // and the window still needs something to show.
+    pub fn repair(&mut self) {
+        if self.tabs.is_empty() {
+            *self = Self::default();
+        }
+        if self.get(self.active).is_none() {
+            self.active = self.tabs[0].id;
+        }
+        self.next_id = self.next_id.max(self.tabs.iter().map(|t| t.id + 1).max().unwrap_or(1));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tabs.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tabs.len()
+    }
+
+    pub fn infos(&self) -> Vec<TabInfo> {
+        self.tabs.iter().map(Tab::info).collect()
+    }
+
+    pub fn active(&self) -> u64 {
+        self.active
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Tab> {
+        self.tabs.iter().find(|t| t.id == id)
+    }
+
+    fn get_mut(&mut self, id: u64) -> Option<&mut Tab> {
+        self.tabs.iter_mut().find(|t| t.id == id)
+    }
+
+    pub fn open(&mut self, after: Option<u64>, activate: bool) -> u64 {
+        self.insert(Tab::new(0), after, activate)
+    }
+
+    pub fn insert(&mut self, mut tab: Tab, after: Option<u64>, activate: bool) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        tab.id = id;
+        tab.loading = false;
+        let position = after
+            .and_then(|after| self.tabs.iter().position(|t| t.id == after))
+            .map(|p| p + 1)
+            .unwrap_or(self.tabs.len());
+        self.tabs.insert(position, tab);
+        if activate {
+            self.active = id;
+        }
+        id
+    }
+
+    // Reuses the active tab while it is still an untouched new tab.
+    pub fn open_with(&mut self, target: HistoryTarget) -> u64 {
+        let blank = self.get(self.active).is_some_and(|t| {
+            t.current() == Some(HistoryTarget::Home) && !t.stack.can_go_back() && !t.stack.can_go_forward()
+        });
+        let id = if blank { self.active } else { self.open(None, true) };
+        self.record(id, target);
+        id
+    }
+
+    pub fn take(&mut self, id: u64) -> Option<Tab> {
+        let position = self.tabs.iter().position(|t| t.id == id)?;
+        let tab = self.tabs.remove(position);
+        if self.active == id && !self.tabs.is_empty() {
+            self.active = self.tabs[position.min(self.tabs.len() - 1)].id;
+        }
+        Some(tab)
+    }
+
+    pub fn close(&mut self, id: u64) -> bool {
+        let Some(position) = self.tabs.iter().position(|t| t.id == id) else {
+            return false;
+        };
+        self.tabs.remove(position);
+        if self.tabs.is_empty() {
+            let id = self.next_id;
+            self.next_id += 1;
+            self.tabs.push(Tab::new(id));
+        }
+        if self.active == id {
+            self.active = self.tabs[position.min(self.tabs.len() - 1)].id;
+        }
+        true
+    }
+
+    pub fn activate(&mut self, id: u64) -> bool {
+        if self.get(id).is_none() {
+            return false;
+        }
+        self.active = id;
+        true
+    }
+
+    pub fn reorder(&mut self, id: u64, index: usize) -> bool {
+        let Some(position) = self.tabs.iter().position(|t| t.id == id) else {
+            return false;
+        };
+        let tab = self.tabs.remove(position);
+        self.tabs.insert(index.min(self.tabs.len()), tab);
+        true
+    }
+
+    pub fn record(&mut self, id: u64, target: HistoryTarget) {
+        if let Some(tab) = self.get_mut(id) {
+            tab.stack.push(HistoryEntry::new(target));
+            tab.scroll = 0.0;
+        }
+    }
+
+    pub fn back(&mut self, id: u64) -> Option<HistoryTarget> {
+        let tab = self.get_mut(id)?;
+        tab.scroll = 0.0;
+        tab.stack.back().map(|e| e.target.clone())
+    }
+
+    pub fn forward(&mut self, id: u64) -> Option<HistoryTarget> {
+        let tab = self.get_mut(id)?;
+        tab.scroll = 0.0;
+        tab.stack.forward().map(|e| e.target.clone())
+    }
+
+    pub fn set_scroll(&mut self, id: u64, scroll: f64) {
+        if let Some(tab) = self.get_mut(id) {
+            tab.scroll = scroll.max(0.0);
+        }
+    }
+
+    pub fn set_page(&mut self, id: u64, page: Option<String>) {
+        if let Some(tab) = self.get_mut(id) {
+            tab.page = page;
+        }
+    }
+
+    pub fn set_title(&mut self, id: u64, target: &HistoryTarget, title: &str) {
+        let title = title.trim();
+        if title.is_empty() {
+            return;
+        }
+        if let Some(tab) = self.get_mut(id) {
+            tab.stack.set_title(target, title);
+        }
+    }
+
+    pub fn clear_history(&mut self) {
+        for tab in self.tabs.iter_mut() {
+            let current = tab.stack.current().cloned();
+            tab.stack = NavigationStack::default();
+            if let Some(current) = current {
+                tab.stack.push(current);
+            }
+        }
+    }
+
+    // Each tab counts its own navigations so that only the newest one may
+    // replace the tab's content when several responses race.
+    pub fn begin_navigation(&mut self, id: u64) -> Option<u64> {
+        let tab = self.get_mut(id)?;
+        tab.navigation += 1;
+        tab.loading = true;
+        Some(tab.navigation)
+    }
+
+    pub fn is_current(&self, id: u64, navigation: u64) -> bool {
+        self.get(id).is_some_and(|t| t.navigation == navigation)
+    }
+
+    pub fn finish_navigation(&mut self, id: u64, navigation: u64) {
+        if let Some(tab) = self.get_mut(id).filter(|t| t.navigation == navigation) {
+            tab.loading = false;
+        }
+    }
+}
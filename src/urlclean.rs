@@ -0,0 +1,119 @@
+use url::Url;
+use crate::settings::{CleaningRule, UrlCleaningSettings};
+
+// Takes tracking parameters out of addresses before they are loaded or
+// shown, and keeps count of how many it has removed since the browser
+// started.
+#[derive(Default)]
+pub struct UrlCleaner {
+    stripped: usize,
+}
+
+impl UrlCleaner {
+    pub fn clean(&mut self, settings: &UrlCleaningSettings, url: &str) -> String {
+        let (cleaned, removed) = clean_url(settings, url);
+        self.stripped += removed;
+        cleaned
+    }
+
+    pub fn stripped(&self) -> usize {
+        self.stripped
+    }
+}
+
+// Returns the address without the parameters the rules name, and how many
+// were removed. The parameters that stay are kept exactly as written, so
+// a cleaned address differs from the original only where it had to.
+pub fn clean_url(settings: &UrlCleaningSettings, url: &str) -> (String, usize) {
+    let unchanged = (url.to_string(), 0);
+    if !settings.enabled {
+        return unchanged;
+    }
+    let Ok(mut parsed) = Url::parse(url) else {
+        return unchanged;
+    };
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return unchanged;
+    }
+    let (Some(host), Some(query)) = (parsed.host_str(), parsed.query()) else {
+        return unchanged;
+    };
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    if settings.exceptions.iter().any(|site| within(&host, site)) {
+        return unchanged;
+    }
+    // The redirect is dropped along with whatever it adds to the address it
+    // leads to, and that address is cleaned in turn.
+    if let Some(target) = redirect_target(&parsed, &host) {
+        let added = parsed.query_pairs().filter(|(name, _)| name != "uddg").count();
+        let (target, removed) = clean_url(settings, &target);
+        return (target, added + removed);
+    }
+    let rules: Vec<&CleaningRule> = settings
+        .rules
+        .iter()
+        .filter(|rule| rule.sites.is_empty() || rule.sites.iter().any(|site| within(&host, site)))
+        .collect();
+
+    let mut kept = Vec::new();
+    let mut removed = 0;
+    for pair in query.split('&') {
+        let name = pair.split('=').next().unwrap_or("");
+        let name = url::form_urlencoded::parse(name.as_bytes())
+            .next()
+            .map(|(name, _)| name.into_owned())
+            .unwrap_or_default();
+        let tracking = !name.is_empty()
+            && rules.iter().any(|rule| rule.params.iter().any(|pattern| matches(pattern, &name)));
+        if tracking {
+            removed += 1;
+        } else {
+            kept.push(pair);
+        }
+    }
+    if removed == 0 {
+        return unchanged;
+    }
+    let kept = kept.join("&");
+    parsed.set_query(if kept.is_empty() { None } else { Some(&kept) });
+    (parsed.to_string(), removed)
+}
+
+// DuckDuckGo's result links go through `/l/`, which records the click and
+// redirects to the address in `uddg`.
+fn redirect_target(url: &Url, host: &str) -> Option<String> {
+    if !within(host, "duckduckgo.com") || url.path() != "/l/" {
+        return None;
+    }
+    let (_, target) = url.query_pairs().find(|(name, _)| name == "uddg")?;
+    let target = Url::parse(&target).ok()?;
+    matches!(target.scheme(), "http" | "https").then(|| target.to_string())
+}
+
+fn within(host: &str, site: &str) -> bool {
+    let site = site.trim().trim_end_matches('.').to_ascii_lowercase();
+    !site.is_empty() && host.strip_suffix(&site).is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+}
+
+// Parameter names are compared without regard to case, with `*` standing
+// for any run of characters.
+fn matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let name = name.to_ascii_lowercase();
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
@@ -0,0 +1,129 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use crate::session::{Session, SessionWindow, WindowBounds};
+use crate::tabs::TabManager;
+
+struct ManagedWindow {
+    id: u64,
+    tabs: Arc<Mutex<TabManager>>,
+    bounds: Option<WindowBounds>,
+    private: bool,
+}
+
+pub struct WindowManager {
+    windows: Vec<ManagedWindow>,
+    next_id: u64,
+    path: Option<PathBuf>,
+    // Set while a recovered session is waiting to be restored or discarded,
+    // so saving does not overwrite it.
+    held: bool,
+}
+
+impl WindowManager {
+    // Returns the manager together with the session of a previous run that did
+    // not exit cleanly; that one is not reopened without asking the user. An
+    // unreadable session only costs the saved windows, not the launch.
+    pub fn load(path: PathBuf) -> (Self, Option<Session>) {
+        let mut manager = Self::in_memory();
+        let mut recovered = None;
+        match Session::load(&path) {
+            Ok(Some(session)) if session.clean_exit => {
+                for window in session.windows {
+                    manager.open(window.tabs, window.bounds, false);
+                }
+            }
+            Ok(Some(session)) => recovered = Some(session).filter(|s| !s.windows.is_empty()),
+            Ok(None) => {}
+            Err(e) => eprintln!("{}", e),
+        }
+        manager.path = Some(path);
+        manager.held = recovered.is_some();
+        (manager, recovered)
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            windows: Vec::new(),
+            next_id: 1,
+            path: None,
+            held: false,
+        }
+    }
+
+    // Private windows are left out, so they are not there to restore.
+    pub fn save(&self, clean_exit: bool) {
+        let Some(path) = self.path.as_ref().filter(|_| !self.held) else { return };
+        let session = Session {
+            clean_exit,
+            windows: self
+                .windows
+                .iter()
+                .filter(|w| !w.private)
+                .map(|w| SessionWindow {
+                    tabs: w.tabs.lock().unwrap().clone(),
+                    bounds: w.bounds,
+                })
+                .collect(),
+        };
+        if let Err(e) = session.save(path) {
+            eprintln!("{}", e);
+        }
+    }
+
+    // Called once the user has restored or discarded the recovered session.
+    pub fn release_recovered(&mut self) {
+        self.held = false;
+    }
+
+    pub fn entries(&self) -> Vec<(u64, Arc<Mutex<TabManager>>)> {
+        self.windows.iter().map(|w| (w.id, w.tabs.clone())).collect()
+    }
+
+    pub fn ids(&self) -> Vec<u64> {
+        self.windows.iter().map(|w| w.id).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.windows.len()
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<Mutex<TabManager>>> {
+        self.windows.iter().find(|w| w.id == id).map(|w| w.tabs.clone())
+    }
+
+    pub fn bounds(&self, id: u64) -> Option<WindowBounds> {
+        self.windows.iter().find(|w| w.id == id).and_then(|w| w.bounds)
+    }
+
+    pub fn is_private(&self, id: u64) -> bool {
+        self.windows.iter().any(|w| w.id == id && w.private)
+    }
+
+    pub fn set_bounds(&mut self, id: u64, bounds: WindowBounds) {
+        if let Some(window) = self.windows.iter_mut().find(|w| w.id == id) {
+            window.bounds = Some(bounds);
+        }
+    }
+
+    pub fn open(&mut self, tabs: TabManager, bounds: Option<WindowBounds>, private: bool) -> (u64, Arc<Mutex<TabManager>>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let tabs = Arc::new(Mutex::new(tabs));
+        self.windows.push(ManagedWindow { id, tabs: tabs.clone(), bounds, private });
+        (id, tabs)
+    }
+
+    // The last window is kept when it closes so that the next launch starts
+    // from the tabs it had open.
+    pub fn close(&mut self, id: u64) -> bool {
+        if self.windows.len() < 2 {
+            return false;
+        }
+        self.windows.retain(|w| w.id != id);
+        true
+    }
+}
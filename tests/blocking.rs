@@ -0,0 +1,184 @@
+mod common;
+
+use std::fs;
+use cypher::blocking::{ContentBlocker, FilterSet, RequestKind};
+use cypher::fetch::PageRequest;
+use cypher::settings::Fetcher;
+use serde_json::{json, Value};
+use common::server::{Response, TestServer};
+use common::{offline_settings, TestBrowser};
+
+const NEWS: &str = "https://news.example/story";
+
+const LIST: &str = "\
+[Adblock Plus 2.0]
+! Title: test list
+||ads.example.com^
+/banner/*/img^
+|https://track.
+||cdn.example.net/widgets/$third-party
+||social.example^$script,domain=news.example|~shop.news.example
+@@||ads.example.com/allowed^
+||evil.example^$important
+@@||evil.example^
+/^https?:\\/\\/regex\\.example\\//
+||redirected.example^$redirect=noop.js
+||casesensitive.example/Path$match-case
+";
+
+fn blocks(filters: &FilterSet, url: &str, kind: Option<RequestKind>) -> bool {
+    filters.blocks(url, NEWS, kind)
+}
+
+#[test]
+fn network_filters_match_the_way_adblock_lists_expect() {
+    let filters = FilterSet::parse(LIST);
+    assert!(blocks(&filters, "https://ads.example.com/banner.js", None));
+    assert!(blocks(&filters, "https://eu.ads.example.com/", None));
+    assert!(!blocks(&filters, "https://notads.example.com/", None));
+    assert!(!blocks(&filters, "https://ads.example.community/", None));
+    assert!(blocks(&filters, "https://static.example/banner/300x250/img?size=large", None));
+    assert!(blocks(&filters, "https://static.example/banner/a/img", None));
+    assert!(!blocks(&filters, "https://static.example/banner/a/imgs", None));
+    assert!(blocks(&filters, "https://track.example.org/pixel", None));
+    assert!(!blocks(&filters, "https://example.org/?u=https://track.example.org", None));
+
+    // Exceptions win unless the blocking filter is important.
+    assert!(!blocks(&filters, "https://ads.example.com/allowed/ad.js", None));
+    assert!(blocks(&filters, "https://evil.example/x.js", None));
+
+    // Options narrow filters down.
+    assert!(blocks(&filters, "https://cdn.example.net/widgets/w.js", None));
+    assert!(!filters.blocks("https://cdn.example.net/widgets/w.js", "https://www.example.net/", None));
+    assert!(blocks(&filters, "https://social.example/sdk.js", Some(RequestKind::Script)));
+    assert!(!blocks(&filters, "https://social.example/like.png", Some(RequestKind::Image)));
+    assert!(!filters.blocks("https://social.example/sdk.js", "https://shop.news.example/", Some(RequestKind::Script)));
+    assert!(!filters.blocks("https://social.example/sdk.js", "https://other.example/", Some(RequestKind::Script)));
+    assert!(blocks(&filters, "https://casesensitive.example/Path", None));
+    assert!(!blocks(&filters, "https://casesensitive.example/path", None));
+
+    // Filters that need features this engine lacks are left out entirely.
+    assert!(!blocks(&filters, "https://regex.example/", None));
+    assert!(!blocks(&filters, "https://redirected.example/x.js", None));
+    // The page itself is never blocked by ordinary filters.
+    assert!(!blocks(&filters, "https://ads.example.com/", Some(RequestKind::Document)));
+}
+
+#[test]
+fn request_kinds_come_from_the_fetch_destination_or_the_extension() {
+    assert_eq!(RequestKind::detect(Some("script"), "https://a.example/x"), Some(RequestKind::Script));
+    assert_eq!(RequestKind::detect(Some("iframe"), "https://a.example/x.js"), Some(RequestKind::Subdocument));
+    assert_eq!(RequestKind::detect(Some("empty"), "https://a.example/api"), Some(RequestKind::Xhr));
+    assert_eq!(RequestKind::detect(None, "https://a.example/app.JS?v=2"), Some(RequestKind::Script));
+    assert_eq!(RequestKind::detect(None, "https://a.example/logo.webp"), Some(RequestKind::Image));
+    assert_eq!(RequestKind::detect(None, "https://a.example/collect"), None);
+}
+
+#[test]
+fn cosmetic_filters_become_hiding_rules() {
+    let filters = FilterSet::parse(
+        "##.ad-banner
+##div[id^=\"sponsor\"]
+news.example##.promo
+~shop.news.example,news.example##.teaser
+news.example#@#.ad-banner
+##div:has-text(Advertisement)
+example.com##+js(set-constant, x, 1)
+example.com#?#.ad:upward(2)
+##a{color:red}
+shop.*##.deal
+",
+    );
+    let css = filters.hiding_css(NEWS);
+    assert_eq!(css, "div[id^=\"sponsor\"], .promo, .teaser { display: none !important; }\n");
+    assert_eq!(
+        filters.hiding_css("https://shop.news.example/"),
+        "div[id^=\"sponsor\"], .promo { display: none !important; }\n"
+    );
+    assert_eq!(
+        filters.hiding_css("https://other.example/"),
+        ".ad-banner, div[id^=\"sponsor\"] { display: none !important; }\n"
+    );
+    assert_eq!(filters.len(), 5);
+}
+
+#[test]
+fn page_exceptions_switch_filtering_off() {
+    let filters = FilterSet::parse(
+        "||ads.example.com^
+##.ad
+trusted.example##.promo
+@@||trusted.example^$document
+@@||plain.example^$generichide
+@@||bare.example^$elemhide
+plain.example##.promo
+",
+    );
+    assert!(!filters.blocks("https://ads.example.com/a.js", "https://trusted.example/", None));
+    assert!(filters.blocks("https://ads.example.com/a.js", "https://plain.example/", None));
+    assert_eq!(filters.hiding_css("https://trusted.example/"), "");
+    assert_eq!(filters.hiding_css("https://bare.example/"), "");
+    assert_eq!(filters.hiding_css("https://plain.example/"), ".promo { display: none !important; }\n");
+}
+
+#[test]
+fn allowlisted_sites_are_left_alone_and_counts_are_per_page() {
+    let mut blocker = ContentBlocker::with_filters(FilterSet::parse("||ads.example.com^\n##.ad"));
+    assert_eq!(blocker.check("https://ads.example.com/1.js", NEWS, None), Some(1));
+    assert_eq!(blocker.check("https://ads.example.com/2.js", "https://news.example/", None), Some(2));
+    assert_eq!(blocker.check("https://cdn.example/app.js", NEWS, None), None);
+    assert_eq!(blocker.blocked_on("https://news.example/other"), 2);
+
+    blocker.reset(NEWS);
+    assert_eq!(blocker.blocked_on(NEWS), 0);
+
+    blocker.set_allowed("https://www.news.example/", true);
+    assert!(blocker.is_allowed(NEWS));
+    assert_eq!(blocker.check("https://ads.example.com/1.js", NEWS, None), None);
+    assert_eq!(blocker.hiding_css(NEWS), "");
+    blocker.set_allowed(NEWS, false);
+    assert_eq!(blocker.check("https://ads.example.com/1.js", NEWS, None), Some(1));
+}
+
+#[test]
+fn proxied_pages_are_filtered() {
+    let site = TestServer::start(|request| match request.path.as_str() {
+        "/ads/banner.js" => Response::new(200, "application/javascript", "alert('ad')"),
+        "/app.js" => Response::new(200, "application/javascript", "run()"),
+        _ => Response::html("<html><head><title>News</title></head><body><div class=\"ad\">Buy</div></body></html>"),
+    });
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    let browser = TestBrowser::with_settings("blocking", settings);
+    fs::create_dir_all(browser.dir.join("filters")).unwrap();
+    fs::write(browser.dir.join("filters").join("easylist.txt"), "/ads/*\n##.ad\n").unwrap();
+    browser.send(r#"{"action": "reload_filters"}"#);
+    assert_eq!(browser.last("notify").unwrap().args[0], "Loaded 2 filters");
+
+    browser.send(&json!({ "action": "load_url", "url": site.url("/") }).to_string());
+    let page = browser.shown_page().unwrap();
+    assert!(page.html.contains("<style>.ad { display: none !important; }\n</style>"));
+    let resource = |path: &str| {
+        let mut request = PageRequest::get(&format!("{}{}", page.view.trim_end_matches('/'), path));
+        request.headers.push(("Referer".to_string(), page.view.clone()));
+        request.headers.push(("Sec-Fetch-Dest".to_string(), "script".to_string()));
+        browser.context().serve_proxy_request(request)
+    };
+
+    let blocked = resource("/ads/banner.js");
+    assert_eq!(blocked.status, 403);
+    assert!(blocked.body.is_empty());
+    assert_eq!(resource("/app.js").body, b"run()");
+    let count = browser.last("setBlockedCount").unwrap();
+    assert_eq!(count.args, [json!(site.url("").trim_end_matches('/')), json!(1)]);
+    assert!(site.requests().iter().all(|request| request.path != "/ads/banner.js"));
+
+    // Allowing the site reloads the page without any filtering.
+    browser.send(&json!({ "action": "allow_on_site", "url": site.url("/") }).to_string());
+    let reloaded = browser.last("loadProxiedPage").unwrap();
+    assert_eq!(reloaded.args[4], false);
+    assert_eq!(resource("/ads/banner.js").body, b"alert('ad')");
+    let saved: Value =
+        serde_json::from_str(&fs::read_to_string(browser.dir.join("blocking_allowlist.json")).unwrap()).unwrap();
+    assert_eq!(saved["allowed_sites"], json!(["127.0.0.1"]));
+}
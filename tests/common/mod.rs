@@ -0,0 +1,137 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use cypher::browser::ScriptCall;
+use cypher::fetch::PageRequest;
+use cypher::headless::HeadlessBrowser;
+use cypher::profile::Profile;
+use cypher::settings::{Settings, SettingsOverrides, SettingsStore};
+
+pub mod server;
+
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Nothing listens on the discard port, so anything that reaches the network
+// fails straight away instead of depending on a live server.
+pub const UNREACHABLE: &str = "http://127.0.0.1:9";
+
+pub fn offline_settings() -> Settings {
+    let mut settings = Settings::default();
+    settings.network.proxy_url = UNREACHABLE.to_string();
+    settings.search.results_url = format!("{}/html/", UNREACHABLE);
+    settings
+}
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "cypher-test-{}-{}-{}",
+        std::process::id(),
+        NEXT_DIR.fetch_add(1, Ordering::SeqCst),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+// A page the content frame was pointed at, fetched the way the webview
+// would fetch it.
+pub struct ShownPage {
+    pub view: String,
+    pub url: String,
+    pub indexed: bool,
+    pub html: String,
+    pub document: String,
+}
+
+// The id the frame script in `html` reports its document under.
+pub fn document_id(html: &str) -> &str {
+    let end = html.find("\");</script>").unwrap();
+    &html[end - 32..end]
+}
+
+// A headless browser on a throwaway profile that is deleted again on drop.
+pub struct TestBrowser {
+    browser: HeadlessBrowser,
+    pub dir: PathBuf,
+}
+
+impl TestBrowser {
+    pub fn open(name: &str) -> Self {
+        Self::with_settings(name, offline_settings())
+    }
+
+    pub fn with_settings(name: &str, settings: Settings) -> Self {
+        Self::with_profile(name, false, settings)
+    }
+
+    // The settings are written to the profile first so they are picked up
+    // the same way a user's settings file would be.
+    pub fn with_profile(name: &str, private: bool, settings: Settings) -> Self {
+        let dir = temp_dir(name);
+        let profile = Profile::open_dir(dir.clone()).unwrap();
+        settings.save(&profile.settings_path()).unwrap();
+        drop(profile);
+        Self::open_dir(dir, private)
+    }
+
+    // Opens a profile that has already been set up.
+    pub fn open_dir(dir: PathBuf, private: bool) -> Self {
+        let profile = Profile::open_dir(dir.clone()).unwrap();
+        let settings = if private {
+            SettingsStore::load_private(&profile.settings_path(), SettingsOverrides::default())
+        } else {
+            SettingsStore::load(profile.settings_path(), SettingsOverrides::default())
+        };
+        let settings = settings.unwrap();
+        let browser = HeadlessBrowser::open(profile, settings, private).unwrap();
+        Self { browser, dir }
+    }
+
+    pub fn send(&self, json: &str) {
+        self.browser.send_json(json).unwrap();
+    }
+
+    // The last call to `function` among everything received so far.
+    pub fn last(&self, function: &str) -> Option<ScriptCall> {
+        self.browser
+            .calls()
+            .into_iter()
+            .filter(|(_, call)| call.function == function)
+            .map(|(_, call)| call)
+            .next_back()
+    }
+
+    pub fn shown_page(&self) -> Option<ShownPage> {
+        let call = self.last("loadProxiedPage")?;
+        let view = call.args[1].as_str()?.to_string();
+        let resource = self.browser.context().serve_proxy_request(PageRequest::get(&view));
+        let html = String::from_utf8(resource.body).ok()?;
+        Some(ShownPage {
+            url: call.args[2].as_str()?.to_string(),
+            indexed: call.args[3].as_bool()?,
+            document: document_id(&html).to_string(),
+            html,
+            view,
+        })
+    }
+}
+
+impl Deref for TestBrowser {
+    type Target = HeadlessBrowser;
+
+    fn deref(&self) -> &HeadlessBrowser {
+        &self.browser
+    }
+}
+
+impl Drop for TestBrowser {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde_json::json;
+
+#[derive(Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(String::as_str)
+    }
+
+    pub fn query(&self, name: &str) -> Option<String> {
+        let url = url::Url::parse(&format!("http://localhost{}", self.path)).ok()?;
+        url.query_pairs().find(|(key, _)| key == name).map(|(_, value)| value.into_owned())
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self { status, content_type, headers: Vec::new(), body: body.into() }
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    pub fn json(status: u16, body: serde_json::Value) -> Self {
+        Self::new(status, "application/json", body.to_string())
+    }
+
+    pub fn html(body: &str) -> Self {
+        Self::new(200, "text/html; charset=utf-8", body)
+    }
+}
+
+type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+
+// A tiny HTTP/1.1 server on a random local port. Every connection gets one
+// response and is closed, which is all reqwest's blocking client needs.
+pub struct TestServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Request>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl TestServer {
+    pub fn start(handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let log = requests.clone();
+        let stop = stopped.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let handler = handler.clone();
+                let log = log.clone();
+                thread::spawn(move || serve(stream, &*handler, &log));
+            }
+        });
+
+        Self { addr, requests, stopped }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wakes the accept loop so it sees the flag.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+fn serve(stream: TcpStream, handler: &Handler, log: &Mutex<Vec<Request>>) {
+    let Some(request) = read_request(&stream) else { return };
+    log.lock().unwrap().push(request.clone());
+    let response = handler(&request);
+    let mut stream = stream;
+    let extra: String = response.headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len(),
+        extra
+    );
+    let _ = stream.write_all(head.as_bytes());
+    let _ = stream.write_all(&response.body);
+}
+
+fn read_request(stream: &TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length = headers.get("content-length").and_then(|l| l.parse().ok()).unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    Some(Request {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        _ => "Unknown",
+    }
+}
+
+pub enum Upstream {
+    Page(String),
+    Response {
+        status: u16,
+        final_url: String,
+        content_type: String,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    },
+    Failure(String),
+}
+
+// Emulates the C++ proxy's `POST /fetch` contract: `{"url": ...}` in,
+// `{"success": true, "status": ..., "body": <base64>, ...}` or a 502 with
+// `{"success": false, "error": ...}` out, 400 for a missing url and 429 once
+// `limit` requests have been served. Pages are looked up by url alone,
+// whatever the method. `GET /download/...` streams them as the proxy does.
+pub fn mock_proxy(pages: Vec<(&str, Upstream)>, limit: Option<usize>) -> TestServer {
+    let pages: HashMap<String, Upstream> = pages.into_iter().map(|(url, page)| (url.to_string(), page)).collect();
+    let served = AtomicUsize::new(0);
+    TestServer::start(move |request| {
+        if limit.is_some_and(|limit| served.fetch_add(1, Ordering::SeqCst) >= limit) {
+            return Response::json(429, json!({ "error": "Rate limit exceeded" }));
+        }
+        if let Some(encoded) = request.path.strip_prefix("/download/") {
+            return download(&pages, encoded, request.header("range"));
+        }
+        if request.method != "POST" || request.path != "/fetch" {
+            return Response::html("<html><head><title>Cypher Proxy</title></head></html>");
+        }
+        if request.body.is_empty() {
+            return Response::json(400, json!({ "error": "No request body" }));
+        }
+        let url = serde_json::from_str::<serde_json::Value>(&request.body)
+            .ok()
+            .and_then(|body| body["url"].as_str().map(str::to_string));
+        let Some(url) = url else {
+            return Response::json(400, json!({ "error": "Missing url field" }));
+        };
+        match pages.get(&url) {
+            Some(Upstream::Page(html)) => Response::json(
+                200,
+                json!({
+                    "success": true,
+                    "status": 200,
+                    "finalUrl": url,
+                    "contentType": "text/html; charset=utf-8",
+                    "charset": "utf-8",
+                    "headers": [["Content-Type", "text/html; charset=utf-8"]],
+                    "body": URL_SAFE_NO_PAD.encode(html),
+                }),
+            ),
+            Some(Upstream::Response { status, final_url, content_type, headers, body }) => Response::json(
+                200,
+                json!({
+                    "success": true,
+                    "status": status,
+                    "finalUrl": final_url,
+                    "contentType": content_type,
+                    "charset": "",
+                    "headers": headers,
+                    "body": URL_SAFE_NO_PAD.encode(body),
+                }),
+            ),
+            Some(Upstream::Failure(error)) => Response::json(502, json!({ "success": false, "error": error })),
+            None => Response::json(502, json!({ "success": false, "error": "Could not resolve host" })),
+        }
+    })
+}
+
+// The proxy's `GET /download/<base64 url>`: the upstream body as it is, from
+// the offset a `Range: bytes=N-` asks for.
+fn download(pages: &HashMap<String, Upstream>, encoded: &str, range: Option<&str>) -> Response {
+    let url = URL_SAFE_NO_PAD.decode(encoded).ok().and_then(|url| String::from_utf8(url).ok());
+    let Some(Upstream::Response { body, .. }) = url.and_then(|url| pages.get(&url)) else {
+        return Response::new(502, "text/plain", "Failed to fetch");
+    };
+    let offset = range
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+    match offset {
+        Some(offset) => Response::new(206, "application/octet-stream", body[offset..].to_vec())
+            .with_header("Content-Range", &format!("bytes {}-{}/{}", offset, body.len() - 1, body.len())),
+        None => Response::new(200, "application/octet-stream", body.clone()),
+    }
+}
+
+// Serves the DuckDuckGo HTML fixture at `/html/`, or the no-results page for
+// queries listed in `empty`.
+pub fn search_server(empty: &'static [&'static str]) -> TestServer {
+    TestServer::start(move |request| {
+        if !request.path.starts_with("/html/") {
+            return Response::new(404, "text/plain", "Not found");
+        }
+        let query = request.query("q").unwrap_or_default();
+        if empty.contains(&query.as_str()) {
+            Response::html(include_str!("../fixtures/ddg_no_results.html"))
+        } else {
+            Response::html(include_str!("../fixtures/ddg_results.html"))
+        }
+    })
+}
@@ -0,0 +1,307 @@
+mod common;
+
+use std::fs;
+use cypher::cookies::{site_of, CookieJar, SameSite};
+use cypher::settings::Fetcher;
+use serde_json::{json, Value};
+use common::server::{Response, TestServer};
+use common::{offline_settings, TestBrowser};
+
+const SHOP: &str = "https://shop.example.com/cart/view";
+
+fn set(jar: &mut CookieJar, url: &str, cookies: &[&str]) {
+    let headers: Vec<(String, String)> = cookies.iter().map(|c| ("Set-Cookie".to_string(), c.to_string())).collect();
+    jar.store_response(url, url, &headers);
+}
+
+fn sent(jar: &mut CookieJar, url: &str) -> Option<String> {
+    jar.header_for(None, None, url, "GET")
+}
+
+#[test]
+fn sites_are_the_registrable_part_of_the_host() {
+    assert_eq!(site_of("www.example.com"), "example.com");
+    assert_eq!(site_of("a.b.example.co.uk"), "example.co.uk");
+    assert_eq!(site_of("example.com."), "example.com");
+    assert_eq!(site_of("localhost"), "localhost");
+    assert_eq!(site_of("127.0.0.1"), "127.0.0.1");
+}
+
+#[test]
+fn domain_and_path_decide_where_cookies_go() {
+    let mut jar = CookieJar::in_memory();
+    set(&mut jar, SHOP, &["basket=3", "shared=1; Domain=.example.com; Path=/", "deep=2; Path=/cart/view/items"]);
+
+    // Without a Path the cookie belongs to the directory it was set from.
+    assert_eq!(sent(&mut jar, "https://shop.example.com/cart/checkout").as_deref(), Some("basket=3; shared=1"));
+    assert_eq!(sent(&mut jar, "https://shop.example.com/cartoon").as_deref(), Some("shared=1"));
+    assert_eq!(
+        sent(&mut jar, "https://shop.example.com/cart/view/items/1").as_deref(),
+        Some("deep=2; basket=3; shared=1")
+    );
+    // Host-only cookies stay on their host; Domain cookies reach the rest
+    // of the site.
+    assert_eq!(sent(&mut jar, "https://www.example.com/").as_deref(), Some("shared=1"));
+    assert_eq!(sent(&mut jar, "https://example.org/"), None);
+}
+
+#[test]
+fn cookies_cannot_be_set_for_other_sites() {
+    let mut jar = CookieJar::in_memory();
+    set(
+        &mut jar,
+        "https://www.example.co.uk/",
+        &["a=1; Domain=other.co.uk", "b=2; Domain=co.uk", "c=3; Domain=uk", "d=4; Domain=example.co.uk"],
+    );
+    set(&mut jar, "https://10.0.0.1/", &["e=5; Domain=0.0.1"]);
+    assert_eq!(jar.header_for(None, None, "https://mail.example.co.uk/", "GET").as_deref(), Some("d=4"));
+    assert_eq!(jar.header_for(None, None, "https://10.0.0.1/", "GET"), None);
+}
+
+#[test]
+fn expiry_and_deletion() {
+    let mut jar = CookieJar::in_memory();
+    set(
+        &mut jar,
+        SHOP,
+        &[
+            "future=1; Expires=Wed, 21 Oct 2037 07:28:00 GMT",
+            "past=1; Expires=Sun, 06 Nov 1994 08:49:37 GMT",
+            "maxage=1; Max-Age=3600; Expires=Sun, 06 Nov 1994 08:49:37 GMT",
+            "session=1",
+        ],
+    );
+    assert_eq!(sent(&mut jar, SHOP).as_deref(), Some("future=1; maxage=1; session=1"));
+    let sites = serde_json::to_value(jar.sites()).unwrap();
+    assert_eq!(sites[0]["site"], "example.com");
+    assert_eq!(sites[0]["cookies"][0]["expires"], 2139722880u64);
+    assert_eq!(sites[0]["cookies"][2]["expires"], Value::Null);
+
+    // A server deletes a cookie by setting it again already expired.
+    set(&mut jar, SHOP, &["future=; Max-Age=0", "maxage=gone; Expires=Thu, 01-Jan-1970 00:00:01 GMT"]);
+    assert_eq!(sent(&mut jar, SHOP).as_deref(), Some("session=1"));
+
+    // Replacing keeps one cookie per name, domain and path.
+    set(&mut jar, SHOP, &["session=2"]);
+    assert_eq!(sent(&mut jar, SHOP).as_deref(), Some("session=2"));
+}
+
+#[test]
+fn secure_cookies_need_https() {
+    let mut jar = CookieJar::in_memory();
+    set(&mut jar, "http://example.com/", &["plain=1; Secure", "__Secure-id=1", "open=1"]);
+    set(
+        &mut jar,
+        "https://example.com/",
+        &["token=1; Secure", "__Host-id=1; Secure; Path=/", "__Host-bad=1; Secure; Domain=example.com; Path=/"],
+    );
+    assert_eq!(sent(&mut jar, "https://example.com/").as_deref(), Some("open=1; token=1; __Host-id=1"));
+    assert_eq!(sent(&mut jar, "http://example.com/").as_deref(), Some("open=1"));
+}
+
+#[test]
+fn same_site_limits_cross_site_requests() {
+    let mut jar = CookieJar::in_memory();
+    let headers: Vec<(String, String)> = [
+        "strict=1; SameSite=Strict",
+        "lax=1",
+        "none=1; SameSite=None; Secure",
+        "insecure=1; SameSite=None",
+    ]
+    .iter()
+    .map(|c| ("Set-Cookie".to_string(), c.to_string()))
+    .collect();
+    // Set while on news.example, by a widget loaded from it.
+    jar.store_response("https://news.example/", "https://widgets.cdn.example/embed", &headers);
+
+    // The same widget under the same page is a cross-site subresource.
+    let header = jar.header_for(Some("https://news.example/story"), None, "https://widgets.cdn.example/embed", "GET");
+    assert_eq!(header.as_deref(), Some("none=1"));
+    // A request to news.example itself is same-site.
+    let mut jar = CookieJar::in_memory();
+    jar.store_response("https://news.example/", "https://news.example/", &headers);
+    assert_eq!(
+        jar.header_for(Some("https://news.example/"), None, "https://news.example/api", "POST").as_deref(),
+        Some("strict=1; lax=1; none=1")
+    );
+    let sites = serde_json::to_value(jar.sites()).unwrap();
+    assert_eq!(sites[0]["cookies"][0]["same_site"], json!(SameSite::Strict));
+
+    // Navigating to it is same-site from its own pages or from nowhere, but
+    // not when a link on another site was followed.
+    let navigate = |jar: &mut CookieJar, from, method| jar.header_for(None, from, "https://news.example/", method);
+    assert_eq!(navigate(&mut jar, None, "GET").as_deref(), Some("strict=1; lax=1; none=1"));
+    assert_eq!(navigate(&mut jar, Some("https://www.news.example/a"), "POST").as_deref(), Some("strict=1; lax=1; none=1"));
+    assert_eq!(navigate(&mut jar, Some("https://evil.example/"), "GET").as_deref(), Some("lax=1; none=1"));
+    assert_eq!(navigate(&mut jar, Some("https://evil.example/"), "POST").as_deref(), Some("none=1"));
+}
+
+#[test]
+fn cookies_are_partitioned_by_top_level_site() {
+    let mut jar = CookieJar::in_memory();
+    let tracker = vec![("Set-Cookie".to_string(), "uid=42; SameSite=None; Secure".to_string())];
+    jar.store_response("https://news.example/", "https://tracker.example/pixel", &tracker);
+
+    assert_eq!(
+        jar.header_for(Some("https://news.example/"), None, "https://tracker.example/pixel", "GET").as_deref(),
+        Some("uid=42")
+    );
+    assert_eq!(jar.header_for(Some("https://shop.example/"), None, "https://tracker.example/pixel", "GET"), None);
+    assert_eq!(jar.header_for(None, None, "https://tracker.example/", "GET"), None);
+
+    jar.clear_site("news.example");
+    assert!(jar.sites().is_empty());
+}
+
+#[test]
+fn sites_keep_their_logins_across_restarts() {
+    let site = TestServer::start(|request| match request.path.as_str() {
+        "/login" => Response::html("<html><head><title>Welcome</title></head></html>")
+            .with_header("Set-Cookie", "session=abc123; Max-Age=86400; HttpOnly")
+            .with_header("Set-Cookie", "flash=hello"),
+        _ => {
+            let cookies = request.header("cookie").unwrap_or("none").to_string();
+            Response::html(&format!("<html><head><title>{}</title></head></html>", cookies))
+        }
+    });
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    let browser = TestBrowser::with_settings("cookie-login", settings);
+
+    browser.send(&json!({ "action": "load_url", "url": site.url("/login") }).to_string());
+    browser.send(&json!({ "action": "load_url", "url": site.url("/account") }).to_string());
+    assert_eq!(browser.context().tabs().lock().unwrap().infos()[0].title, "session=abc123; flash=hello");
+
+    // Only cookies with an expiry outlive the browser.
+    let saved: Value = serde_json::from_str(&fs::read_to_string(browser.dir.join("cookies.json")).unwrap()).unwrap();
+    let saved = saved["sites"]["127.0.0.1"].as_array().unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0]["name"], "session");
+    assert_eq!(saved[0]["http_only"], true);
+
+    let loaded = CookieJar::load(browser.dir.join("cookies.json"));
+    let sites = serde_json::to_value(loaded.sites()).unwrap();
+    assert_eq!(sites[0]["cookies"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn frame_requests_use_the_jar_of_the_page_that_asked() {
+    let site = TestServer::start(|request| {
+        let cookies = request.header("cookie").unwrap_or("none").to_string();
+        Response::new(200, "text/plain", cookies).with_header("Set-Cookie", "seen=1")
+    });
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    let browser = TestBrowser::with_settings("cookie-frames", settings);
+    let context = browser.context();
+    let port = site.url("").rsplit(':').next().unwrap().to_string();
+    let resource = |referer: &str| {
+        let mut request = cypher::fetch::PageRequest::get(&format!("cypher-proxy://127.0.0.1.http:{}/pixel", port));
+        request.headers.push(("Referer".to_string(), referer.to_string()));
+        String::from_utf8(context.serve_proxy_request(request).body).unwrap()
+    };
+
+    let own_page = format!("cypher-proxy://127.0.0.1.http:{}/page", port);
+    assert_eq!(resource(&own_page), "none");
+    assert_eq!(resource(&own_page), "seen=1");
+    // The same address embedded in another site starts from an empty jar.
+    assert_eq!(resource("cypher-proxy://news.example/story"), "none");
+
+    browser.send(r#"{"action": "load_url", "url": "cypher://cookies"}"#);
+    let page = browser.last("showCookiesPage").unwrap();
+    let sites: Vec<&str> = page.args[1].as_array().unwrap().iter().map(|s| s["site"].as_str().unwrap()).collect();
+    assert_eq!(sites, ["127.0.0.1", "news.example"]);
+
+    browser.send(r#"{"action": "clear_cookies", "url": "127.0.0.1"}"#);
+    let synced = browser.last("syncCookies").unwrap();
+    assert_eq!(synced.args[0].as_array().unwrap().len(), 1);
+    assert_eq!(resource(&own_page), "none");
+
+    browser.send(r#"{"action": "clear_cookies"}"#);
+    assert!(browser.last("syncCookies").unwrap().args[0].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn cookies_set_after_a_redirect_belong_to_where_it_ended() {
+    let target = TestServer::start(|request| match request.path.as_str() {
+        "/set" => Response::html("<html><head><title>Moved</title></head></html>").with_header("Set-Cookie", "moved=1"),
+        _ => {
+            let cookies = request.header("cookie").unwrap_or("none").to_string();
+            Response::html(&format!("<html><head><title>{}</title></head></html>", cookies))
+        }
+    });
+    let moved = target.url("/set").replace("127.0.0.1", "localhost");
+    let origin = TestServer::start(move |_| Response::new(302, "text/plain", "").with_header("Location", &moved));
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    let browser = TestBrowser::with_settings("cookie-redirect", settings);
+
+    browser.send(&json!({ "action": "load_url", "url": origin.url("/go") }).to_string());
+    let check = target.url("/check").replace("127.0.0.1", "localhost");
+    browser.send(&json!({ "action": "load_url", "url": check }).to_string());
+    assert_eq!(browser.context().tabs().lock().unwrap().infos()[0].title, "moved=1");
+    browser.send(r#"{"action": "load_url", "url": "cypher://cookies"}"#);
+    let page = browser.last("showCookiesPage").unwrap();
+    let sites: Vec<&str> = page.args[1].as_array().unwrap().iter().map(|s| s["site"].as_str().unwrap()).collect();
+    assert_eq!(sites, ["localhost"]);
+}
+
+#[test]
+fn cookies_set_by_a_redirect_are_kept_and_sent_on() {
+    let site = TestServer::start(|request| match request.path.as_str() {
+        "/login" => Response::new(302, "text/plain", "")
+            .with_header("Location", "/account")
+            .with_header("Set-Cookie", "session=abc; Path=/; SameSite=Strict"),
+        _ => {
+            let cookies = request.header("cookie").unwrap_or("none").to_string();
+            Response::html(&format!("<html><head><title>{}</title></head></html>", cookies))
+        }
+    });
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    let browser = TestBrowser::with_settings("cookie-redirect-hop", settings);
+
+    browser.send(&json!({ "action": "load_url", "url": site.url("/login") }).to_string());
+    let requests = site.requests();
+    assert_eq!(requests.iter().map(|r| r.path.as_str()).collect::<Vec<_>>(), ["/login", "/account"]);
+    assert_eq!(browser.context().tabs().lock().unwrap().infos()[0].title, "session=abc");
+    assert_eq!(browser.shown_page().unwrap().url, site.url("/account"));
+}
+
+#[test]
+fn strict_cookies_stay_behind_when_another_site_links_in() {
+    let site = TestServer::start(|request| match request.path.as_str() {
+        "/login" => Response::html("<html><head><title>Welcome</title></head></html>")
+            .with_header("Set-Cookie", "session=abc; Path=/; SameSite=Strict"),
+        _ => {
+            let cookies = request.header("cookie").unwrap_or("none").to_string();
+            Response::html(&format!("<html><head><title>{}</title></head></html>", cookies))
+        }
+    });
+    let other = site.url("/elsewhere").replace("127.0.0.1", "localhost");
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    let browser = TestBrowser::with_settings("cookie-strict", settings);
+    let load = |url: &str| browser.send(&json!({ "action": "load_url", "url": url }).to_string());
+    let title = || {
+        let tabs = browser.context().tabs().lock().unwrap();
+        tabs.infos().into_iter().find(|info| info.id == tabs.active()).unwrap().title
+    };
+
+    load(&site.url("/login"));
+    load(&site.url("/account"));
+    assert_eq!(title(), "session=abc");
+
+    // From a page on another site, in the same tab or a new one.
+    load(&other);
+    load(&site.url("/account"));
+    assert_eq!(title(), "none");
+    load(&other);
+    browser.send(&json!({ "action": "new_tab", "tab": 1, "url": site.url("/account") }).to_string());
+    assert_eq!(title(), "none");
+
+    // Typed in a tab that shows none of the web.
+    browser.send(r#"{"action": "go_home"}"#);
+    load(&site.url("/account"));
+    assert_eq!(title(), "session=abc");
+}
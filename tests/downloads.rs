@@ -0,0 +1,246 @@
+mod common;
+
+use std::fs;
+use std::sync::atomic::AtomicBool;
+use cypher::downloads::{sha256_file, transfer, Download, DownloadState};
+use cypher::settings::Fetcher;
+use serde_json::json;
+use common::server::{mock_proxy, Response, TestServer, Upstream};
+use common::{offline_settings, temp_dir, TestBrowser, TIMEOUT};
+
+// Bytes that are not valid UTF-8, so any text conversion on the way would show.
+fn payload() -> Vec<u8> {
+    (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+// Serves the payload as a PDF and honours `Range: bytes=N-`.
+fn file_server() -> TestServer {
+    TestServer::start(|request| {
+        let body = payload();
+        let offset = request
+            .header("range")
+            .and_then(|range| range.strip_prefix("bytes="))
+            .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+        match offset {
+            Some(offset) => Response::new(206, "application/pdf", body[offset..].to_vec()),
+            None => Response::new(200, "application/pdf", body),
+        }
+    })
+}
+
+#[test]
+fn non_html_page_is_downloaded_intact() {
+    let server = file_server();
+    let downloads = temp_dir("download-target");
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    settings.downloads.directory = downloads.clone();
+    let browser = TestBrowser::with_settings("download", settings);
+
+    browser.send(&json!({ "action": "load_url", "url": server.url("/files/report%202024") }).to_string());
+    let started = browser.last("showDownloadStarted").unwrap();
+    assert_eq!(started.args[1]["content_type"], "application/pdf");
+    assert!(browser.last("loadProxiedPage").is_none());
+
+    let download = loop {
+        browser.wait_for("syncDownloads", TIMEOUT).unwrap();
+        let download = browser.context().downloads().lock().unwrap().list()[0].clone();
+        if download.state != DownloadState::Downloading {
+            break download;
+        }
+    };
+    assert!(download.state == DownloadState::Completed, "{:?}", download.error);
+    assert_eq!(download.path, downloads.join("report 2024.pdf"));
+    assert_eq!(fs::read(&download.path).unwrap(), payload());
+    assert_eq!(download.received, payload().len() as u64);
+    assert_eq!(download.sha256, Some(sha256_file(&download.path).unwrap()));
+    assert!(!download.part_path().exists());
+    // The response that turned out to be a file is the one that was saved.
+    assert_eq!(server.requests().len(), 1);
+
+    browser.send(r#"{"action": "load_url", "url": "cypher://downloads"}"#);
+    let page = browser.last("showDownloadsPage").unwrap();
+    assert_eq!(page.args[1][0]["state"], "completed");
+    let _ = fs::remove_dir_all(downloads);
+}
+
+#[test]
+fn paused_download_resumes_with_a_range_request() {
+    let server = file_server();
+    let dir = temp_dir("download-resume");
+    fs::create_dir_all(&dir).unwrap();
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+
+    let download = Download {
+        id: 1,
+        url: server.url("/payload.bin"),
+        path: dir.join("payload.bin"),
+        content_type: "application/octet-stream".to_string(),
+        received: 50_000,
+        total: Some(200_000),
+        state: DownloadState::Downloading,
+        error: None,
+        sha256: None,
+    };
+    fs::write(download.part_path(), &payload()[..50_000]).unwrap();
+
+    let mut reported = Vec::new();
+    let sha256 = transfer(&settings, &download, &AtomicBool::new(false), |received, total| {
+        reported.push((received, total));
+    })
+    .unwrap();
+
+    assert_eq!(server.requests()[0].header("range"), Some("bytes=50000-"));
+    assert_eq!(reported.first(), Some(&(50_000, Some(200_000))));
+    assert_eq!(reported.last(), Some(&(200_000, Some(200_000))));
+    assert_eq!(fs::read(&download.path).unwrap(), payload());
+    assert_eq!(sha256, Some(sha256_file(&download.path).unwrap()));
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn downloads_through_the_proxy_resume_where_they_stopped() {
+    let url = "https://files.example/payload.bin";
+    let file = Upstream::Response {
+        status: 200,
+        final_url: url.to_string(),
+        content_type: "application/octet-stream".to_string(),
+        headers: Vec::new(),
+        body: payload(),
+    };
+    let proxy = mock_proxy(vec![(url, file)], None);
+    let dir = temp_dir("download-proxy-resume");
+    fs::create_dir_all(&dir).unwrap();
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+
+    let download = Download {
+        id: 1,
+        url: url.to_string(),
+        path: dir.join("payload.bin"),
+        content_type: "application/octet-stream".to_string(),
+        received: 50_000,
+        total: Some(200_000),
+        state: DownloadState::Downloading,
+        error: None,
+        sha256: None,
+    };
+    fs::write(download.part_path(), &payload()[..50_000]).unwrap();
+    transfer(&settings, &download, &AtomicBool::new(false), |_, _| {}).unwrap();
+
+    let requests = proxy.requests();
+    assert_eq!(requests[0].path, "/download/aHR0cHM6Ly9maWxlcy5leGFtcGxlL3BheWxvYWQuYmlu");
+    assert_eq!(requests[0].header("range"), Some("bytes=50000-"));
+    assert_eq!(fs::read(&download.path).unwrap(), payload());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn server_without_range_support_restarts_the_file() {
+    let server = TestServer::start(|_| Response::new(200, "application/octet-stream", payload()));
+    let dir = temp_dir("download-restart");
+    fs::create_dir_all(&dir).unwrap();
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+
+    let download = Download {
+        id: 1,
+        url: server.url("/payload.bin"),
+        path: dir.join("payload.bin"),
+        content_type: String::new(),
+        received: 10,
+        total: None,
+        state: DownloadState::Downloading,
+        error: None,
+        sha256: None,
+    };
+    fs::write(download.part_path(), b"stale data").unwrap();
+
+    transfer(&settings, &download, &AtomicBool::new(false), |_, _| {}).unwrap();
+    assert_eq!(fs::read(&download.path).unwrap(), payload());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn stopped_transfer_keeps_its_partial_file() {
+    let server = file_server();
+    let dir = temp_dir("download-stop");
+    fs::create_dir_all(&dir).unwrap();
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+
+    let download = Download {
+        id: 1,
+        url: server.url("/payload.bin"),
+        path: dir.join("payload.bin"),
+        content_type: String::new(),
+        received: 0,
+        total: None,
+        state: DownloadState::Downloading,
+        error: None,
+        sha256: None,
+    };
+    let result = transfer(&settings, &download, &AtomicBool::new(true), |_, _| {}).unwrap();
+    assert_eq!(result, None);
+    assert!(download.part_path().exists());
+    assert!(!download.path.exists());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn files_over_the_page_size_limit_are_still_downloaded() {
+    let big: Vec<u8> = (0..3 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+    let served = big.clone();
+    let server = TestServer::start(move |request| match request.path.as_str() {
+        "/disk.iso" => Response::new(200, "application/octet-stream", served.clone()),
+        _ => Response::html(&"<p>long page</p>".repeat(100_000)),
+    });
+    let proxy = mock_proxy(
+        vec![(
+            "https://example.com/video",
+            Upstream::Response {
+                status: 200,
+                final_url: "https://example.com/video.mp4".to_string(),
+                content_type: "video/mp4".to_string(),
+                headers: Vec::new(),
+                body: big.clone(),
+            },
+        )],
+        None,
+    );
+    let downloads = temp_dir("download-large");
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    settings.network.max_page_mb = 1;
+    settings.network.proxy_url = proxy.url("");
+    settings.downloads.directory = downloads.clone();
+    let browser = TestBrowser::with_settings("download-large", settings.clone());
+
+    let finished = |name: &str| loop {
+        browser.wait_for("syncDownloads", TIMEOUT).unwrap();
+        let list = browser.context().downloads().lock().unwrap().list().to_vec();
+        let download = list.into_iter().find(|d| d.path.ends_with(name)).unwrap();
+        if download.state != DownloadState::Downloading {
+            break download;
+        }
+    };
+    browser.send(&json!({ "action": "load_url", "url": server.url("/disk.iso") }).to_string());
+    assert!(browser.last("showError").is_none());
+    let download = finished("disk.iso");
+    assert!(download.state == DownloadState::Completed, "{:?}", download.error);
+    assert_eq!(fs::read(&download.path).unwrap(), big);
+
+    // Pages are still held to the limit.
+    browser.send(&json!({ "action": "load_url", "url": server.url("/long") }).to_string());
+    assert!(browser.last("showError").unwrap().args[1].as_str().unwrap().contains("1 MB limit"));
+
+    settings.network.fetcher = Fetcher::Proxy;
+    browser.send(&json!({ "action": "save_settings", "settings": settings }).to_string());
+    browser.send(r#"{"action": "load_url", "url": "https://example.com/video"}"#);
+    let download = finished("video.mp4");
+    assert!(download.state == DownloadState::Completed, "{:?}", download.error);
+    assert_eq!(download.url, "https://example.com/video.mp4");
+    assert_eq!(fs::read(&download.path).unwrap(), big);
+    let _ = fs::remove_dir_all(downloads);
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use cypher::charset::decode;
+use cypher::settings::Fetcher;
+use serde_json::json;
+use common::server::{mock_proxy, Response, TestServer, Upstream};
+use common::{offline_settings, TestBrowser};
+
+const SHIFT_JIS: &[u8] = include_bytes!("fixtures/encodings/shift_jis_meta.html");
+const WINDOWS_1252: &[u8] = include_bytes!("fixtures/encodings/windows-1252_header.html");
+const GBK: &[u8] = include_bytes!("fixtures/encodings/gbk_undeclared.html");
+const KOI8_R: &[u8] = include_bytes!("fixtures/encodings/koi8-r_http_equiv.html");
+const UTF_16LE_BOM: &[u8] = include_bytes!("fixtures/encodings/utf-16le_bom.html");
+const UTF_8_BOM: &[u8] = include_bytes!("fixtures/encodings/utf-8_bom.html");
+
+#[test]
+fn meta_charset_is_honoured() {
+    let (text, encoding) = decode(SHIFT_JIS, None, "https://example.jp/");
+    assert_eq!(encoding.name(), "Shift_JIS");
+    assert!(text.contains("<h1>こんにちは</h1>"));
+    assert!(text.contains("東京の天気は晴れ"));
+}
+
+#[test]
+fn http_equiv_content_type_is_honoured() {
+    let (text, encoding) = decode(KOI8_R, None, "https://example.com/");
+    assert_eq!(encoding.name(), "KOI8-R");
+    assert!(text.contains("Москва - столица России"));
+}
+
+#[test]
+fn header_charset_is_used_when_the_page_declares_none() {
+    let (text, encoding) = decode(WINDOWS_1252, Some("windows-1252"), "https://example.com/");
+    assert_eq!(encoding.name(), "windows-1252");
+    assert!(text.contains("Prix : 12 € — « très cher »"));
+}
+
+#[test]
+fn header_charset_beats_the_meta_declaration() {
+    let (_, encoding) = decode(SHIFT_JIS, Some("EUC-JP"), "https://example.jp/");
+    assert_eq!(encoding.name(), "EUC-JP");
+}
+
+#[test]
+fn byte_order_mark_beats_everything_else() {
+    let (text, encoding) = decode(UTF_16LE_BOM, Some("iso-8859-1"), "https://example.com/");
+    assert_eq!(encoding.name(), "UTF-16LE");
+    assert!(text.starts_with("<!DOCTYPE html>"));
+    assert!(text.contains("Crème brûlée"));
+
+    let (text, encoding) = decode(UTF_8_BOM, Some("windows-1252"), "https://example.com/");
+    assert_eq!(encoding.name(), "UTF-8");
+    assert!(text.starts_with("<!DOCTYPE html>"));
+    assert!(text.contains("こんにちは"));
+}
+
+#[test]
+fn undeclared_pages_are_detected_statistically() {
+    let (text, encoding) = decode(GBK, None, "https://example.com/");
+    assert_eq!(encoding.name(), "GBK");
+    assert!(text.contains("<h1>你好，世界</h1>"));
+
+    let (text, encoding) = decode(WINDOWS_1252, None, "https://example.fr/");
+    assert_eq!(encoding.name(), "windows-1252");
+    assert!(text.contains("Café crème"));
+}
+
+#[test]
+fn unknown_charset_labels_are_ignored() {
+    let (text, encoding) = decode(SHIFT_JIS, Some("no-such-charset"), "https://example.jp/");
+    assert_eq!(encoding.name(), "Shift_JIS");
+    assert!(text.contains("こんにちは"));
+}
+
+#[test]
+fn plain_utf8_is_left_alone() {
+    let html = "<html><body><p>Grüße aus Zürich 👋</p></body></html>";
+    let (text, encoding) = decode(html.as_bytes(), None, "https://example.ch/");
+    assert_eq!(encoding.name(), "UTF-8");
+    assert_eq!(text, html);
+}
+
+#[test]
+fn direct_pages_are_transcoded_before_display() {
+    let site = TestServer::start(|request| match request.path.as_str() {
+        "/jp" => Response::new(200, "text/html", SHIFT_JIS),
+        _ => Response::new(200, "text/html; charset=windows-1252", WINDOWS_1252),
+    });
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    let browser = TestBrowser::with_settings("direct-encoding", settings);
+
+    browser.send(&json!({ "action": "load_url", "url": site.url("/jp") }).to_string());
+    assert!(browser.shown_page().unwrap().html.contains("こんにちは"));
+    assert_eq!(browser.context().tabs().lock().unwrap().infos()[0].title, "日本語");
+
+    browser.send(&json!({ "action": "load_url", "url": site.url("/fr") }).to_string());
+    assert!(browser.shown_page().unwrap().html.contains("Crème brûlée"));
+}
+
+#[test]
+fn proxied_pages_are_transcoded_and_indexed() {
+    let proxy = mock_proxy(
+        vec![(
+            "https://example.cn/",
+            Upstream::Response {
+                status: 200,
+                final_url: "https://example.cn/".to_string(),
+                content_type: "text/html".to_string(),
+                headers: Vec::new(),
+                body: GBK.to_vec(),
+            },
+        )],
+        None,
+    );
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+    let browser = TestBrowser::with_settings("proxied-encoding", settings);
+
+    browser.send(r#"{"action": "load_url", "url": "https://example.cn/"}"#);
+    assert!(browser.shown_page().unwrap().html.contains("你好，世界"));
+    assert_eq!(browser.context().tabs().lock().unwrap().infos()[0].title, "中文网页");
+
+    let results = browser.context().index().search("中文网页");
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].url, "https://example.cn/");
+}
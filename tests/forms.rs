@@ -0,0 +1,223 @@
+mod common;
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use cypher::fetch::PageRequest;
+use cypher::forms::{FormField, FormFile, FormSubmission};
+use cypher::history::HistoryTarget;
+use cypher::settings::Fetcher;
+use serde_json::{json, Value};
+use common::server::{mock_proxy, Response, TestServer, Upstream};
+use common::{offline_settings, TestBrowser};
+
+const RESULT: &str = "<html><head><title>Signed in</title></head><body><p>Welcome back</p></body></html>";
+
+fn field(name: &str, value: &str) -> FormField {
+    FormField { name: name.to_string(), value: value.to_string(), file: None }
+}
+
+fn form(method: &str, enctype: &str, fields: Vec<FormField>) -> FormSubmission {
+    FormSubmission {
+        action: "https://example.com/login?next=%2F#form".to_string(),
+        method: method.to_string(),
+        enctype: enctype.to_string(),
+        accept_charset: String::new(),
+        fields,
+    }
+}
+
+fn boundary(request: &PageRequest) -> String {
+    let content_type = request.content_type.as_deref().unwrap();
+    content_type.strip_prefix("multipart/form-data; boundary=").unwrap().to_string()
+}
+
+#[test]
+fn get_forms_replace_the_query_string() {
+    let request = form("get", "", vec![field("q", "rust lang"), field("lang", "日本")]).to_request().unwrap();
+    assert_eq!(request, PageRequest::get("https://example.com/login?q=rust+lang&lang=%E6%97%A5%E6%9C%AC#form"));
+
+    // No method at all is a GET too.
+    let request = form("", "", vec![field("q", "a&b=c")]).to_request().unwrap();
+    assert_eq!(request.url, "https://example.com/login?q=a%26b%3Dc#form");
+}
+
+#[test]
+fn posted_forms_are_urlencoded_by_default() {
+    let mut upload = field("avatar", "");
+    upload.file = Some(FormFile {
+        name: "me.png".to_string(),
+        content_type: "image/png".to_string(),
+        data: STANDARD.encode([0x89, b'P', b'N', b'G']),
+    });
+    let request = form("POST", "", vec![field("user", "ada"), field("note", "line one\nline two"), upload])
+        .to_request()
+        .unwrap();
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.url, "https://example.com/login?next=%2F");
+    assert_eq!(request.content_type.as_deref(), Some("application/x-www-form-urlencoded"));
+    assert_eq!(
+        String::from_utf8(request.body).unwrap(),
+        "user=ada&note=line+one%0D%0Aline+two&avatar=me.png"
+    );
+}
+
+#[test]
+fn multipart_forms_carry_files_byte_for_byte() {
+    let png = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, 0x00];
+    let mut upload = field("avatar", "");
+    upload.file = Some(FormFile {
+        name: "my \"photo\".png".to_string(),
+        content_type: "image/png".to_string(),
+        data: STANDARD.encode(png),
+    });
+    let mut empty = field("attachment", "");
+    empty.file = Some(FormFile { name: String::new(), content_type: String::new(), data: String::new() });
+    let request = form("post", "multipart/form-data", vec![field("user", "ada\r\n"), upload, empty])
+        .to_request()
+        .unwrap();
+
+    let boundary = boundary(&request);
+    let mut expected = format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"user\"\r\n\r\nada\r\n\r\n\
+         --{b}\r\nContent-Disposition: form-data; name=\"avatar\"; filename=\"my %22photo%22.png\"\r\n\
+         Content-Type: image/png\r\n\r\n",
+        b = boundary
+    )
+    .into_bytes();
+    expected.extend_from_slice(&png);
+    expected.extend_from_slice(
+        format!(
+            "\r\n--{b}\r\nContent-Disposition: form-data; name=\"attachment\"; filename=\"\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n\r\n--{b}--\r\n",
+            b = boundary
+        )
+        .as_bytes(),
+    );
+    assert_eq!(request.body, expected);
+}
+
+#[test]
+fn plain_text_forms_are_one_field_per_line() {
+    let request = form("post", "text/plain", vec![field("a", "1"), field("b", "two\nlines")]).to_request().unwrap();
+    assert_eq!(request.content_type.as_deref(), Some("text/plain"));
+    assert_eq!(request.body, b"a=1\r\nb=two\r\nlines\r\n");
+}
+
+#[test]
+fn accept_charset_picks_the_encoding() {
+    let mut submission = form("post", "", vec![field("q", "東京")]);
+    submission.accept_charset = "bogus shift_jis".to_string();
+    assert_eq!(submission.to_request().unwrap().body, b"q=%93%8C%8B%9E");
+
+    // UTF-16 cannot be sent in a form body; browsers use UTF-8 instead.
+    submission.accept_charset = "utf-16".to_string();
+    assert_eq!(submission.to_request().unwrap().body, b"q=%E6%9D%B1%E4%BA%AC");
+}
+
+#[test]
+fn forms_only_go_to_web_addresses() {
+    let mut submission = form("post", "", Vec::new());
+    submission.action = "javascript:alert(1)".to_string();
+    assert!(submission.to_request().unwrap_err().starts_with("Forms cannot be sent to"));
+    submission.action = "not a url".to_string();
+    assert!(submission.to_request().unwrap_err().starts_with("Invalid form action"));
+}
+
+#[test]
+fn posted_forms_go_through_the_proxy_and_are_shown() {
+    let proxy = mock_proxy(vec![("https://example.com/login?next=%2F", Upstream::Page(RESULT.to_string()))], None);
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+    let browser = TestBrowser::with_settings("form-proxy", settings);
+
+    let submission = form("post", "", vec![field("user", "ada"), field("password", "hunter 2")]);
+    browser.send(&json!({ "action": "submit_form", "form": submission }).to_string());
+
+    let requests = proxy.requests();
+    assert_eq!(requests.len(), 1);
+    let body: Value = serde_json::from_str(&requests[0].body).unwrap();
+    assert_eq!(body["url"], "https://example.com/login?next=%2F");
+    assert_eq!(body["method"], "POST");
+    assert_eq!(body["contentType"], "application/x-www-form-urlencoded");
+    let sent = URL_SAFE_NO_PAD.decode(body["body"].as_str().unwrap()).unwrap();
+    assert_eq!(sent, b"user=ada&password=hunter+2");
+
+    let page = browser.shown_page().unwrap();
+    assert!(page.html.contains("Welcome back"));
+    let tabs = browser.context().tabs().lock().unwrap().infos();
+    assert_eq!(tabs[0].title, "Signed in");
+    assert!(tabs[0].target == HistoryTarget::Url { url: "https://example.com/login?next=%2F".to_string() });
+}
+
+#[test]
+fn get_forms_are_ordinary_navigations() {
+    let proxy = mock_proxy(
+        vec![("https://example.com/login?q=cats", Upstream::Page(RESULT.to_string()))],
+        None,
+    );
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+    let browser = TestBrowser::with_settings("form-get", settings);
+
+    let mut submission = form("get", "", vec![field("q", "cats")]);
+    submission.action = "https://example.com/login?next=%2F".to_string();
+    browser.send(&json!({ "action": "submit_form", "form": submission }).to_string());
+
+    let body: Value = serde_json::from_str(&proxy.requests()[0].body).unwrap();
+    assert_eq!(body, json!({ "url": "https://example.com/login?q=cats", "method": "GET", "redirect": "manual" }));
+    assert_eq!(browser.shown_page().unwrap().url, "https://example.com/login?q=cats");
+    let history = browser.context().history().lock().unwrap();
+    let visits = history.search("", 10);
+    assert!(visits[0].entry.target == HistoryTarget::Url { url: "https://example.com/login?q=cats".to_string() });
+}
+
+#[test]
+fn direct_fetcher_sends_the_form_itself() {
+    let site = TestServer::start(|request| {
+        let page = format!(
+            "<html><head><title>{}</title></head><body>{}</body></html>",
+            request.method,
+            request.header("content-type").unwrap_or("")
+        );
+        Response::new(200, "text/html", page)
+    });
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    let browser = TestBrowser::with_settings("form-direct", settings);
+
+    let mut submission = form("post", "multipart/form-data", vec![field("comment", "Grüße")]);
+    submission.action = site.url("/comments");
+    browser.send(&json!({ "action": "submit_form", "form": submission }).to_string());
+
+    let requests = site.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, "/comments");
+    let content_type = requests[0].header("content-type").unwrap();
+    assert!(content_type.starts_with("multipart/form-data; boundary="));
+    assert!(requests[0].body.contains("Content-Disposition: form-data; name=\"comment\"\r\n\r\nGrüße\r\n"));
+    assert_eq!(browser.context().tabs().lock().unwrap().infos()[0].title, "POST");
+}
+
+#[test]
+fn frame_posts_are_forwarded_upstream() {
+    let proxy = mock_proxy(vec![("https://example.com/api", Upstream::Page(RESULT.to_string()))], None);
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+    let browser = TestBrowser::with_settings("frame-post", settings);
+
+    let resource = browser.context().serve_proxy_request(PageRequest {
+        method: "POST".to_string(),
+        url: "cypher-proxy://example.com/api#top".to_string(),
+        content_type: Some("application/json".to_string()),
+        headers: Vec::new(),
+        body: br#"{"vote":1}"#.to_vec(),
+    });
+    assert_eq!(resource.status, 200);
+
+    let body: Value = serde_json::from_str(&proxy.requests()[0].body).unwrap();
+    assert_eq!(body["url"], "https://example.com/api");
+    assert_eq!(body["method"], "POST");
+    assert_eq!(body["contentType"], "application/json");
+    assert_eq!(URL_SAFE_NO_PAD.decode(body["body"].as_str().unwrap()).unwrap(), br#"{"vote":1}"#);
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use std::fs;
+use cypher::history::{History, HistoryTarget};
+use cypher::settings::{Fetcher, Settings, SettingsOverrides, SettingsStore};
+use common::{temp_dir, TestBrowser, TIMEOUT};
+
+#[test]
+fn new_tab_is_opened_and_activated() {
+    let browser = TestBrowser::open("new-tab");
+    browser.send(r#"{"action": "new_tab"}"#);
+
+    let call = browser.last("syncTabs").unwrap();
+    assert_eq!(call.args[0].as_array().unwrap().len(), 2);
+    assert_eq!(call.args[1], 2);
+}
+
+#[test]
+fn closing_the_last_tab_leaves_a_fresh_one() {
+    let browser = TestBrowser::open("close-tab");
+    browser.send(r#"{"action": "close_tab", "tab": 1}"#);
+
+    let call = browser.last("syncTabs").unwrap();
+    let tabs = call.args[0].as_array().unwrap();
+    assert_eq!(tabs.len(), 1);
+    assert_ne!(tabs[0]["id"], 1);
+}
+
+#[test]
+fn list_tabs_reloads_every_tab() {
+    let browser = TestBrowser::open("list-tabs");
+    browser.send(r#"{"action": "list_tabs"}"#);
+
+    let (window, call) = browser.wait_for("beginNavigation", TIMEOUT).unwrap();
+    assert_eq!(window, browser.context().window());
+    assert_eq!(call.args[1]["kind"], "home");
+}
+
+#[test]
+fn profile_page_describes_the_profile() {
+    let browser = TestBrowser::open("profile-page");
+    browser.send(r#"{"action": "load_url", "url": "cypher://profile"}"#);
+
+    let call = browser.last("showProfilePage").unwrap();
+    assert_eq!(call.args[0], 1);
+    assert_eq!(call.args[1]["private"], false);
+    assert!(call.args[1]["storage"].as_array().unwrap().len() > 3);
+}
+
+#[test]
+fn unknown_internal_page_shows_an_error() {
+    let browser = TestBrowser::open("unknown-page");
+    browser.send(r#"{"action": "load_url", "url": "cypher://nowhere"}"#);
+
+    let call = browser.last("showError").unwrap();
+    assert_eq!(call.args[1], "Unknown page cypher://nowhere");
+}
+
+#[test]
+fn unreachable_proxy_shows_an_error_and_records_the_visit() {
+    let browser = TestBrowser::open("unreachable");
+    browser.send(r#"{"action": "load_url", "url": "https://example.com/"}"#);
+
+    let call = browser.last("showError").unwrap();
+    assert!(call.args[1].as_str().unwrap().contains("Request failed"));
+
+    let history = browser.context().history().lock().unwrap();
+    let visits = history.search("", 10);
+    assert_eq!(visits.len(), 1);
+    assert!(visits[0].entry.target == HistoryTarget::Url { url: "https://example.com/".to_string() });
+}
+
+#[test]
+fn history_is_written_when_flushed_and_at_once_when_cleared() {
+    let dir = temp_dir("history-flush");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("history.json");
+    let mut history = History::load(path.clone()).unwrap();
+    let target = HistoryTarget::Url { url: "https://example.com/".to_string() };
+    history.record(target.clone());
+    history.set_title(&target, "Example");
+    assert!(!path.exists());
+
+    history.flush();
+    assert_eq!(History::load(path.clone()).unwrap().search("example", 10)[0].entry.title, "Example");
+
+    history.clear();
+    assert!(History::load(path.clone()).unwrap().search("", 10).is_empty());
+
+    history.record(target);
+    drop(history);
+    assert_eq!(History::load(path).unwrap().search("", 10).len(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn local_search_on_an_empty_index_finds_nothing() {
+    let browser = TestBrowser::open("local-search");
+    browser.send(r#"{"action": "search_history", "url": "rust", "page": 2}"#);
+
+    let call = browser.last("displayResults").unwrap();
+    assert_eq!(call.args[1].as_array().unwrap().len(), 0);
+    assert_eq!(call.args[2]["query"], "rust");
+    assert_eq!(call.args[2]["page"], 2);
+}
+
+#[test]
+fn bookmarks_are_added_and_listed() {
+    let browser = TestBrowser::open("bookmarks");
+    browser.send(r#"{"action": "add_bookmark", "url": "https://www.rust-lang.org/", "title": "Rust"}"#);
+
+    let call = browser.last("setBookmarks").unwrap();
+    let bookmarks = call.args[0]["bookmarks"].as_array().unwrap();
+    let added = bookmarks.iter().find(|b| b["url"] == "https://www.rust-lang.org/").unwrap();
+    assert_eq!(added["title"], "Rust");
+
+    browser.send(r#"{"action": "remove_bookmark", "id": 999}"#);
+    assert!(browser.last("notify").is_some());
+}
+
+#[test]
+fn invalid_settings_are_rejected() {
+    let browser = TestBrowser::open("settings");
+    browser.send(r#"{"action": "save_settings", "settings": {"window": {"width": 5}}}"#);
+
+    let call = browser.last("notify").unwrap();
+    assert!(call.args[0].as_str().unwrap().contains("out of range"));
+    assert_eq!(browser.context().settings().window.width, 1920);
+}
+
+#[test]
+fn windows_open_and_close() {
+    let browser = TestBrowser::open("windows");
+    browser.send(r#"{"action": "new_window", "url": "cypher://history"}"#);
+    browser.calls();
+    let ids = browser.context().windows().lock().unwrap().ids();
+    assert_eq!(ids.len(), 2);
+
+    browser.send_to(ids[1], serde_json::from_str(r#"{"action": "close_window"}"#).unwrap()).unwrap();
+    browser.calls();
+    assert_eq!(browser.context().windows().lock().unwrap().len(), 1);
+}
+
+#[test]
+fn malformed_messages_are_reported() {
+    let browser = TestBrowser::open("malformed");
+    assert!(browser.send_json(r#"{"url": "missing action"}"#).is_err());
+    assert!(browser.send_json("not json").is_err());
+}
+
+#[test]
+fn command_line_overrides_are_not_saved() {
+    let dir = temp_dir("settings-overrides");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("settings.toml");
+    let overrides = SettingsOverrides { proxy_url: Some("http://localhost:9999".to_string()), fetcher: Some(Fetcher::Direct) };
+    let mut store = SettingsStore::load(path.clone(), overrides).unwrap();
+    assert_eq!(store.get().network.proxy_url, "http://localhost:9999");
+
+    // What the settings page sends back has this run's values in it.
+    let mut edited = store.get().clone();
+    edited.window.width = 1280;
+    store.replace(edited).unwrap();
+    assert!(store.get().network.fetcher == Fetcher::Direct);
+    let saved = Settings::load(&path);
+    assert_eq!(saved.window.width, 1280);
+    assert_eq!(saved.network.proxy_url, "http://localhost:8080");
+    assert!(saved.network.fetcher == Fetcher::Proxy);
+    fs::remove_dir_all(&dir).unwrap();
+}
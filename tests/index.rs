@@ -0,0 +1,79 @@
+mod common;
+
+use std::fs;
+use std::sync::Mutex;
+use cypher::import::import_into;
+use cypher::indexer::{Document, IndexPolicy, SharedIndex};
+use common::temp_dir;
+
+fn doc(url: &str, content: &str) -> Document {
+    Document { id: 0, title: String::new(), url: url.to_string(), content: content.to_string() }
+}
+
+#[test]
+fn added_documents_replace_those_with_the_same_address() {
+    let dir = temp_dir("index-add");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("index.json");
+    let index = SharedIndex::open(path.clone()).unwrap();
+    let docs: Vec<Document> = (0..600).map(|i| doc(&format!("https://a.example/{}", i), "first")).collect();
+    index.add_documents(docs).unwrap();
+
+    index
+        .add_documents(vec![
+            doc("https://a.example/1", "second"),
+            doc("https://a.example/599", "second"),
+            doc("https://a.example/1", "third"),
+            doc("https://b.example/", "third"),
+        ])
+        .unwrap();
+    assert_eq!(index.search("first").len(), 598);
+    let second: Vec<String> = index.search("second").into_iter().map(|d| d.url).collect();
+    assert_eq!(second, ["https://a.example/599"]);
+    assert_eq!(index.search("third").len(), 2);
+
+    let reopened = SharedIndex::open(path).unwrap();
+    assert_eq!(reopened.search("first").len(), 598);
+    assert_eq!(reopened.search("third").len(), 2);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn imports_go_into_the_index_at_once() {
+    let dir = temp_dir("index-import");
+    fs::create_dir_all(&dir).unwrap();
+    let index = SharedIndex::open(dir.join("index.json")).unwrap();
+    index.add_documents(vec![doc("https://a.example/7", "visited")]).unwrap();
+    let mut docs: Vec<Document> = (0..5000).map(|i| doc(&format!("https://a.example/{}", i), "imported")).collect();
+    docs.push(doc("https://a.example/7", "imported"));
+    docs.push(doc("ftp://a.example/skipped", "imported"));
+
+    let reported = Mutex::new(Vec::new());
+    let no_fetch = None::<fn(&str) -> Result<String, String>>;
+    let progress = |done, total| reported.lock().unwrap().push((done, total));
+    let imported = import_into(&index, &IndexPolicy::default(), docs, no_fetch, progress).unwrap();
+    assert_eq!(imported, 5000);
+    assert_eq!(index.search("imported").len(), 5000);
+    assert!(index.search("visited").is_empty());
+    assert_eq!(reported.into_inner().unwrap(), [(0, 5000), (5000, 5000)]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn visited_pages_are_written_when_flushed() {
+    let dir = temp_dir("index-flush");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("index.json");
+    let index = SharedIndex::open(path.clone()).unwrap();
+    index.index_page("https://a.example/", "<title>Visited</title><p>kept</p>").unwrap();
+    assert_eq!(index.search("kept").len(), 1);
+    assert!(!path.exists());
+
+    index.flush().unwrap();
+    assert_eq!(SharedIndex::open(path.clone()).unwrap().search("kept").len(), 1);
+
+    index.index_page("https://b.example/", "<title>Later</title><p>kept</p>").unwrap();
+    drop(index);
+    assert_eq!(SharedIndex::open(path).unwrap().search("kept").len(), 2);
+    fs::remove_dir_all(&dir).unwrap();
+}
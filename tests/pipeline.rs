@@ -0,0 +1,318 @@
+mod common;
+
+use cypher::fetch::{fetch_search_results, fetch_through_proxy, PageRequest};
+use cypher::protocol::inject_frame_script;
+use cypher::settings::Fetcher;
+use cypher::snippet::{highlight, unhighlight};
+use serde_json::json;
+use common::server::{mock_proxy, search_server, Response, TestServer, Upstream};
+use common::{document_id, offline_settings, TestBrowser};
+
+const EXAMPLE: &str = "<html><head><title>Example Domain</title></head>\
+    <body><h1>Example Domain</h1><p>This domain is for use in illustrative examples.</p></body></html>";
+
+fn proxied(name: &str, proxy: &TestServer) -> TestBrowser {
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+    TestBrowser::with_settings(name, settings)
+}
+
+#[test]
+fn proxied_page_is_shown_titled_and_indexed() {
+    let proxy = mock_proxy(vec![("https://example.com/", Upstream::Page(EXAMPLE.to_string()))], None);
+    let browser = proxied("proxied-page", &proxy);
+    browser.send(r#"{"action": "load_url", "url": "https://example.com/"}"#);
+
+    let page = browser.shown_page().unwrap();
+    assert_eq!(page.html, inject_frame_script(EXAMPLE, &page.document));
+    assert_eq!(page.url, "https://example.com/");
+    assert!(page.indexed);
+
+    let requests = proxy.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, "/fetch");
+    let body: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+    assert_eq!(body, json!({ "url": "https://example.com/", "method": "GET", "redirect": "manual" }));
+
+    let title = browser.context().tabs().lock().unwrap().infos()[0].title.clone();
+    assert_eq!(title, "Example Domain");
+
+    browser.send(r#"{"action": "search_history", "url": "illustrative"}"#);
+    let call = browser.last("displayResults").unwrap();
+    let results = call.args[1].as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["url"], "https://example.com/");
+    assert!(results[0]["snippet"].as_str().unwrap().contains("<mark>illustrative</mark>"));
+}
+
+#[test]
+fn upstream_failure_is_shown() {
+    let proxy = mock_proxy(
+        vec![("https://down.example/", Upstream::Failure("Connection timed out".to_string()))],
+        None,
+    );
+    let browser = proxied("upstream-failure", &proxy);
+    browser.send(r#"{"action": "load_url", "url": "https://down.example/"}"#);
+
+    let call = browser.last("showError").unwrap();
+    assert_eq!(call.args[1], "Connection timed out");
+    assert!(browser.last("loadProxiedPage").is_none());
+}
+
+#[test]
+fn rate_limited_proxy_is_reported() {
+    let proxy = mock_proxy(vec![("https://example.com/", Upstream::Page(EXAMPLE.to_string()))], Some(1));
+    let browser = proxied("rate-limited", &proxy);
+    browser.send(r#"{"action": "load_url", "url": "https://example.com/"}"#);
+    assert!(browser.last("loadProxiedPage").is_some());
+
+    browser.send(r#"{"action": "reload"}"#);
+    let call = browser.last("showError").unwrap();
+    assert_eq!(call.args[1], "The proxy is rate limiting requests: Rate limit exceeded");
+}
+
+#[test]
+fn binary_proxy_body_arrives_intact() {
+    let bytes: Vec<u8> = (0..=255u8).rev().collect();
+    let proxy = mock_proxy(
+        vec![(
+            "https://example.com/logo",
+            Upstream::Response {
+                status: 200,
+                final_url: "https://cdn.example.com/logo.png".to_string(),
+                content_type: "image/png".to_string(),
+                headers: vec![
+                    ("Content-Type".to_string(), "image/png".to_string()),
+                    ("Set-Cookie".to_string(), "a=1".to_string()),
+                    ("Set-Cookie".to_string(), "b=2".to_string()),
+                ],
+                body: bytes.clone(),
+            },
+        )],
+        None,
+    );
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+
+    let page = fetch_through_proxy(&settings, &PageRequest::get("https://example.com/logo"), |_, _| {}).unwrap();
+    assert_eq!(page.body, bytes);
+    assert_eq!(page.status, 200);
+    assert_eq!(page.url, "https://cdn.example.com/logo.png");
+    assert_eq!(page.mime(), "image/png");
+    assert_eq!(page.charset, None);
+    assert_eq!(page.header("content-type"), Some("image/png"));
+    assert_eq!(page.headers.iter().filter(|(name, _)| name == "Set-Cookie").count(), 2);
+}
+
+#[test]
+fn redirected_page_is_shown_under_its_final_url() {
+    let proxy = mock_proxy(
+        vec![(
+            "http://example.com/",
+            Upstream::Response {
+                status: 200,
+                final_url: "https://example.com/welcome".to_string(),
+                content_type: "text/html; charset=utf-8".to_string(),
+                headers: Vec::new(),
+                body: EXAMPLE.as_bytes().to_vec(),
+            },
+        )],
+        None,
+    );
+    let browser = proxied("redirected", &proxy);
+    browser.send(r#"{"action": "load_url", "url": "http://example.com/"}"#);
+
+    let page = browser.shown_page().unwrap();
+    assert_eq!(page.html, inject_frame_script(EXAMPLE, &page.document));
+    assert_eq!(page.url, "https://example.com/welcome");
+}
+
+#[test]
+fn upstream_error_status_is_reported() {
+    let proxy = mock_proxy(
+        vec![(
+            "https://example.com/missing",
+            Upstream::Response {
+                status: 404,
+                final_url: "https://example.com/missing".to_string(),
+                content_type: "text/html".to_string(),
+                headers: Vec::new(),
+                body: b"<h1>Not Found</h1>".to_vec(),
+            },
+        )],
+        None,
+    );
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+
+    let error = fetch_through_proxy(&settings, &PageRequest::get("https://example.com/missing"), |_, _| {}).unwrap_err();
+    assert!(error.contains("404"), "{}", error);
+}
+
+#[test]
+fn legacy_proxy_content_is_still_accepted() {
+    let proxy = TestServer::start(|_| {
+        Response::json(200, json!({ "success": true, "content": EXAMPLE, "contentType": "text/html" }))
+    });
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+
+    let page = fetch_through_proxy(&settings, &PageRequest::get("https://example.com/"), |_, _| {}).unwrap();
+    assert_eq!(page.text(), EXAMPLE);
+    assert_eq!(page.status, 200);
+    assert_eq!(page.url, "https://example.com/");
+}
+
+#[test]
+fn rejected_proxy_request_is_reported() {
+    let proxy = TestServer::start(|_| Response::json(400, json!({ "error": "Missing url field" })));
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+
+    let error = fetch_through_proxy(&settings, &PageRequest::get("https://example.com/"), |_, _| {}).unwrap_err();
+    assert_eq!(error, "The proxy rejected the request: Missing url field");
+}
+
+#[test]
+fn garbled_proxy_response_is_reported() {
+    let proxy = TestServer::start(|_| Response::new(502, "text/plain", "Failed to fetch"));
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("/");
+
+    let error = fetch_through_proxy(&settings, &PageRequest::get("https://example.com/"), |_, _| {}).unwrap_err();
+    assert!(error.starts_with("Invalid JSON response (502 Bad Gateway)"), "{}", error);
+    assert_eq!(proxy.requests()[0].path, "/fetch");
+}
+
+#[test]
+fn direct_fetcher_bypasses_the_proxy() {
+    let site = TestServer::start(|request| match request.path.as_str() {
+        "/" => Response::html(EXAMPLE),
+        _ => Response::new(404, "text/plain", "Not found"),
+    });
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    settings.network.user_agent = "CypherTest/1.0".to_string();
+    let browser = TestBrowser::with_settings("direct", settings);
+
+    browser.send(&json!({ "action": "load_url", "url": site.url("/") }).to_string());
+    let page = browser.shown_page().unwrap();
+    assert_eq!(page.html, inject_frame_script(EXAMPLE, &page.document));
+    assert_eq!(site.requests()[0].header("user-agent"), Some("CypherTest/1.0"));
+
+    browser.send(&json!({ "action": "load_url", "url": site.url("/missing") }).to_string());
+    let call = browser.last("showError").unwrap();
+    assert!(call.args[1].as_str().unwrap().contains("404"));
+}
+
+#[test]
+fn search_results_are_parsed_from_the_fixture() {
+    let search = search_server(&[]);
+    let mut settings = offline_settings();
+    settings.search.results_url = search.url("/html/");
+    let browser = TestBrowser::with_settings("search", settings);
+
+    browser.send(r#"{"action": "search", "url": "rust", "page": 2}"#);
+    let call = browser.last("displayResults").unwrap();
+    let results = call.args[1].as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0]["title"], "Rust Programming Language");
+    assert_eq!(results[0]["url"], "https://www.rust-lang.org/");
+    assert!(results[0]["snippet"].as_str().unwrap().contains("<mark>Rust</mark> is blazingly fast"));
+    assert_eq!(results[1]["url"], "https://doc.rust-lang.org/book/");
+    assert_eq!(results[2]["snippet"], "");
+    assert_eq!(call.args[2]["provider"], "duckduckgo");
+
+    let request = &search.requests()[0];
+    assert_eq!(request.method, "GET");
+    assert_eq!(request.query("q").as_deref(), Some("rust"));
+    assert_eq!(request.query("s").as_deref(), Some("15"));
+    assert_eq!(request.query("dc").as_deref(), Some("16"));
+    assert!(request.header("user-agent").unwrap().starts_with("Mozilla/5.0"));
+}
+
+#[test]
+fn highlighted_snippets_turn_back_into_plain_text() {
+    let text = "Rust & <b>\"safe\"</b> isn't &lt;slow&gt;";
+    let terms = ["rust".to_string(), "safe".to_string()].into_iter().collect();
+    let html = highlight(text, &terms);
+    assert!(html.starts_with("<mark>Rust</mark> &amp; &lt;b&gt;"));
+    assert_eq!(unhighlight(&html), text);
+}
+
+#[test]
+fn search_results_respect_the_page_size() {
+    let search = search_server(&["xyzzy"]);
+    let mut settings = offline_settings();
+    settings.search.results_url = search.url("/html/");
+    settings.search.results_per_page = 2;
+
+    assert_eq!(fetch_search_results(&settings, "rust", 1).unwrap().len(), 2);
+    assert!(fetch_search_results(&settings, "xyzzy", 1).unwrap().is_empty());
+    assert_eq!(search.requests()[1].query("s").as_deref(), Some("0"));
+}
+
+#[test]
+fn unreachable_search_server_gives_no_results() {
+    let browser = TestBrowser::open("search-down");
+    browser.send(r#"{"action": "search", "url": "rust"}"#);
+
+    let call = browser.last("displayResults").unwrap();
+    assert!(call.args[1].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn search_with_an_unusable_user_agent_reports_an_error() {
+    let mut settings = offline_settings();
+    settings.network.user_agent = "Cypher\r\nX-Injected: 1".to_string();
+    assert!(settings.validate().is_err());
+
+    let error = fetch_search_results(&settings, "rust", 1).err().unwrap();
+    assert!(error.starts_with("Failed to create client"), "{}", error);
+}
+
+#[test]
+fn large_pages_report_progress_and_arrive_intact() {
+    let html = format!("<html><head><title>Big</title></head><body>{}</body></html>", "<p>Grüße, 世界</p>".repeat(40_000));
+    let page = html.clone();
+    let site = TestServer::start(move |_| Response::html(&page));
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    let browser = TestBrowser::with_settings("large-page", settings);
+
+    browser.send(&json!({ "action": "load_url", "url": site.url("/") }).to_string());
+    let calls: Vec<_> = browser.calls().into_iter().map(|(_, call)| call).collect();
+    let progress = calls.iter().rfind(|call| call.function == "showPageProgress").unwrap();
+    assert_eq!(progress.args[1], html.len());
+    assert_eq!(progress.args[2], html.len());
+
+    let shown = calls.iter().rfind(|call| call.function == "loadProxiedPage").unwrap();
+    let resource = browser.context().serve_proxy_request(PageRequest::get(shown.args[1].as_str().unwrap()));
+    let body = String::from_utf8(resource.body).unwrap();
+    assert_eq!(body, inject_frame_script(&html, document_id(&body)));
+    assert_eq!(site.requests().len(), 1);
+    assert_eq!(browser.context().tabs().lock().unwrap().infos()[0].title, "Big");
+}
+
+#[test]
+fn pages_over_the_size_limit_are_refused() {
+    let big = "x".repeat(2 * 1024 * 1024);
+    let page = big.clone();
+    let site = TestServer::start(move |_| Response::html(&page));
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    settings.network.max_page_mb = 1;
+    let browser = TestBrowser::with_settings("size-limit", settings);
+
+    browser.send(&json!({ "action": "load_url", "url": site.url("/") }).to_string());
+    let call = browser.last("showError").unwrap();
+    assert!(call.args[1].as_str().unwrap().contains("larger than the 1 MB limit"));
+
+    let proxy = mock_proxy(vec![("https://example.com/", Upstream::Page(big))], None);
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+    settings.network.max_page_mb = 1;
+    let error = fetch_through_proxy(&settings, &PageRequest::get("https://example.com/"), |_, _| {}).unwrap_err();
+    assert!(error.contains("larger than the 1 MB limit"), "{}", error);
+}
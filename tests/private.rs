@@ -0,0 +1,201 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use cypher::downloads::DownloadState;
+use cypher::profile::Profile;
+use cypher::settings::{Fetcher, Settings, SettingsOverrides, SettingsStore};
+use cypher::tabs::TabManager;
+use cypher::windows::WindowManager;
+use serde_json::{json, Value};
+use common::server::{Response, TestServer};
+use common::{offline_settings, temp_dir, TestBrowser, TIMEOUT};
+
+// Pages that set a cookie and show the cookies they were sent as their
+// title, plus a file that is downloaded rather than shown.
+fn site() -> TestServer {
+    TestServer::start(|request| match request.path.as_str() {
+        "/report.pdf" => Response::new(200, "application/pdf", b"%PDF-1.4".to_vec()),
+        _ => {
+            let cookies = request.header("cookie").unwrap_or("none").to_string();
+            Response::html(&format!("<html><head><title>{}</title></head><body>cypherprivate</body></html>", cookies))
+                .with_header("Set-Cookie", "visitor=1; Max-Age=86400")
+        }
+    })
+}
+
+fn direct_settings() -> Settings {
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    settings
+}
+
+// Every file under `dir` with its contents.
+fn snapshot(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(snapshot(&path));
+        } else {
+            files.push((path.clone(), fs::read(&path).unwrap()));
+        }
+    }
+    files.sort();
+    files
+}
+
+fn title(browser: &TestBrowser, window: u64) -> String {
+    let tabs = browser.context().windows().lock().unwrap().get(window).unwrap();
+    let tabs = tabs.lock().unwrap();
+    tabs.infos().iter().find(|t| t.id == tabs.active()).unwrap().title.clone()
+}
+
+#[test]
+fn private_browsing_writes_nothing_to_the_profile() {
+    let server = site();
+    let downloads = temp_dir("private-downloads");
+    let mut settings = direct_settings();
+    settings.downloads.directory = downloads.clone();
+    // Taken before the browser opens, so files written at startup count too.
+    let dir = temp_dir("private");
+    settings.save(&Profile::open_dir(dir.clone()).unwrap().settings_path()).unwrap();
+    let before = snapshot(&dir);
+    let browser = TestBrowser::open_dir(dir, true);
+
+    browser.send(&json!({ "action": "load_url", "url": server.url("/login") }).to_string());
+    browser.send(&json!({ "action": "load_url", "url": server.url("/account") }).to_string());
+    assert_eq!(title(&browser, browser.context().window()), "visitor=1");
+    browser.send(r#"{"action": "search_history", "url": "cypherprivate"}"#);
+    browser.send(&json!({ "action": "download", "url": server.url("/report.pdf") }).to_string());
+    loop {
+        browser.wait_for("syncDownloads", TIMEOUT).unwrap();
+        let state = browser.context().downloads().lock().unwrap().list()[0].state;
+        if state != DownloadState::Downloading {
+            assert!(state == DownloadState::Completed);
+            break;
+        }
+    }
+    browser.send(r#"{"action": "new_window", "url": "cypher://history"}"#);
+    browser.calls();
+    let ids = browser.context().windows().lock().unwrap().ids();
+    assert!(browser.context().windows().lock().unwrap().is_private(ids[1]));
+    browser.send_to(ids[1], serde_json::from_str(r#"{"action": "close_window"}"#).unwrap()).unwrap();
+    browser.calls();
+
+    // Everything above was remembered, but only in memory.
+    assert_eq!(browser.context().history().lock().unwrap().search("", 10).len(), 3);
+    assert!(browser.context().index().search("cypherprivate").is_empty());
+    assert!(downloads.join("report.pdf").exists());
+    assert!(snapshot(&browser.dir) == before);
+    fs::remove_dir_all(&downloads).unwrap();
+}
+
+#[test]
+fn private_windows_forget_everything_when_closed() {
+    let server = site();
+    let browser = TestBrowser::with_settings("private-window", direct_settings());
+    let main = browser.context().window();
+    browser.send(&json!({ "action": "load_url", "url": server.url("/home") }).to_string());
+
+    browser.send(r#"{"action": "new_private_window"}"#);
+    browser.calls();
+    let private = browser.context().windows().lock().unwrap().ids()[1];
+    let load = |url: &str| serde_json::from_value(json!({ "action": "load_url", "url": url })).unwrap();
+    browser.send_to(private, load(&server.url("/private"))).unwrap();
+    browser.send_to(private, load(&server.url("/private/again"))).unwrap();
+    browser.send(r#"{"action": "list_windows"}"#);
+    let synced: Vec<Value> = browser
+        .calls()
+        .into_iter()
+        .filter(|(_, call)| call.function == "syncWindows")
+        .map(|(window, call)| json!([window, call.args[2]]))
+        .collect();
+    assert_eq!(synced, [json!([main, [private]]), json!([private, [private]])]);
+
+    // The private window starts without the ordinary window's cookies, and
+    // what it collects stays out of the ordinary window.
+    assert_eq!(title(&browser, private), "visitor=1");
+    browser.send(&json!({ "action": "load_url", "url": server.url("/home/again") }).to_string());
+    assert_eq!(title(&browser, main), "visitor=1");
+    assert_eq!(browser.context().history().lock().unwrap().search("", 10).len(), 2);
+    assert_eq!(browser.context().index().search("cypherprivate").len(), 2);
+    browser.context().flush();
+    let saved = fs::read_to_string(browser.dir.join("history.json")).unwrap();
+    assert!(!saved.contains("/private"));
+
+    // Tabs do not move between private and ordinary windows.
+    let tab = browser.context().tabs().lock().unwrap().active();
+    browser.send(&json!({ "action": "move_tab", "tab": tab, "window": private }).to_string());
+    browser.calls();
+    assert_eq!(browser.context().tabs().lock().unwrap().len(), 1);
+
+    browser.send_to(private, serde_json::from_str(r#"{"action": "close_window"}"#).unwrap()).unwrap();
+    browser.send(r#"{"action": "new_private_window"}"#);
+    browser.calls();
+    let reopened = browser.context().windows().lock().unwrap().ids()[1];
+    browser.send_to(reopened, load(&server.url("/private"))).unwrap();
+    assert_eq!(title(&browser, reopened), "none");
+    browser.send_to(reopened, load("cypher://history")).unwrap();
+    let page = browser.last("showHistoryPage").unwrap();
+    assert_eq!(page.args[1].as_array().unwrap().len(), 2);
+    assert!(!page.args[1].to_string().contains("/private/again"));
+}
+
+#[test]
+fn private_windows_are_not_saved_in_the_session() {
+    let dir = temp_dir("private-session");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("session.json");
+    let (mut windows, _) = WindowManager::load(path.clone());
+    windows.open(TabManager::default(), None, false);
+    windows.open(TabManager::default(), None, true);
+    windows.save(true);
+
+    let (restored, _) = WindowManager::load(path);
+    assert_eq!(restored.len(), 1);
+    assert!(!restored.is_private(restored.ids()[0]));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn site_choices_in_private_windows_are_not_saved() {
+    let server = site();
+    let browser = TestBrowser::with_settings("private-sites", direct_settings());
+    let main = browser.context().window();
+    browser.send(&json!({ "action": "load_url", "url": server.url("/home") }).to_string());
+    browser.send(r#"{"action": "new_private_window"}"#);
+    browser.calls();
+    let private = browser.context().windows().lock().unwrap().ids()[1];
+    let message = |action: &str, url: &str| serde_json::from_value(json!({ "action": action, "url": url })).unwrap();
+    browser.send_to(private, message("load_url", &server.url("/private"))).unwrap();
+    browser.calls();
+    let before = snapshot(&browser.dir);
+
+    browser.send_to(private, message("exclude_site_from_index", &server.url("/private"))).unwrap();
+    browser.send_to(private, message("allow_on_site", &server.url("/private"))).unwrap();
+    browser.send_to(private, message("search_history", "cypherprivate")).unwrap();
+    let results = browser.last("displayResults").unwrap();
+    assert_eq!(results.args[1].as_array().unwrap().len(), 0);
+    assert!(snapshot(&browser.dir) == before);
+
+    // The ordinary window still indexes the site and blocks ads on it.
+    browser.send_to(main, message("search_history", "cypherprivate")).unwrap();
+    let results = browser.last("displayResults").unwrap();
+    assert_eq!(results.args[1].as_array().unwrap().len(), 1);
+    assert!(!browser.context().blocker().lock().unwrap().is_allowed(&server.url("/home")));
+}
+
+#[test]
+fn private_runs_do_not_create_or_change_the_settings_file() {
+    let dir = temp_dir("private-settings");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("settings.toml");
+    let mut store = SettingsStore::load_private(&path, SettingsOverrides::default()).unwrap();
+    let mut settings = store.get().clone();
+    settings.search.results_per_page = 30;
+    store.replace(settings).unwrap();
+    assert_eq!(store.get().search.results_per_page, 30);
+    assert!(!path.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
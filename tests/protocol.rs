@@ -0,0 +1,160 @@
+mod common;
+
+use cypher::fetch::PageRequest;
+use serde_json::json;
+use cypher::protocol::{from_proxy_url, inject_frame_script, prepare_page, to_proxy_url};
+use common::server::{mock_proxy, Upstream};
+use common::{document_id, offline_settings, TestBrowser};
+
+const PAGE: &str = "<!DOCTYPE html><html><head><title>Styled</title>\
+    <link rel=\"stylesheet\" href=\"/style.css\"></head><body><a href=\"next\">Next</a></body></html>";
+
+fn css() -> Upstream {
+    Upstream::Response {
+        status: 200,
+        final_url: "https://example.com/style.css".to_string(),
+        content_type: "text/css".to_string(),
+        headers: Vec::new(),
+        body: b"body { color: red }".to_vec(),
+    }
+}
+
+#[test]
+fn addresses_map_to_one_origin_per_site_and_back() {
+    assert_eq!(
+        to_proxy_url("https://example.com/a/b?c=1#top").as_deref(),
+        Some("cypher-proxy://example.com/a/b?c=1#top")
+    );
+    assert_eq!(
+        to_proxy_url("http://example.com:8080/").as_deref(),
+        Some("cypher-proxy://example.com.http:8080/")
+    );
+    assert_eq!(
+        from_proxy_url("cypher-proxy://example.com/a/b?c=1#top").as_deref(),
+        Some("https://example.com/a/b?c=1#top")
+    );
+    assert_eq!(
+        from_proxy_url("cypher-proxy://example.com.http:8080/").as_deref(),
+        Some("http://example.com:8080/")
+    );
+    // WebView2's spelling of the same address.
+    assert_eq!(
+        from_proxy_url("http://cypher-proxy.example.com.http:8080/x").as_deref(),
+        Some("http://example.com:8080/x")
+    );
+
+    assert_eq!(to_proxy_url("ftp://example.com/"), None);
+    assert_eq!(to_proxy_url("http://[::1]/"), None);
+    assert_eq!(from_proxy_url("https://example.com/"), None);
+}
+
+#[test]
+fn subresources_are_fetched_through_the_proxy() {
+    let proxy = mock_proxy(
+        vec![
+            ("https://example.com/", Upstream::Page(PAGE.to_string())),
+            ("https://example.com/style.css", css()),
+        ],
+        None,
+    );
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+    let browser = TestBrowser::with_settings("subresources", settings);
+
+    browser.send(r#"{"action": "load_url", "url": "https://example.com/"}"#);
+    let page = browser.shown_page().unwrap();
+    assert!(page.view.ends_with("example.com/"));
+    assert_eq!(page.html.as_bytes(), prepare_page(PAGE, "https://example.com/", "", &page.document));
+    assert!(page.html.contains("href=\"cypher-proxy://example.com/style.css\""));
+
+    let resource = browser.context().serve_proxy_request(PageRequest::get("cypher-proxy://example.com/style.css"));
+    assert_eq!(resource.status, 200);
+    assert_eq!(resource.content_type, "text/css; charset=utf-8");
+    assert_eq!(resource.body, b"body { color: red }");
+    assert_eq!(proxy.requests().len(), 2);
+}
+
+#[test]
+fn main_document_is_only_served_from_the_cache_once() {
+    let proxy = mock_proxy(vec![("https://example.com/", Upstream::Page(PAGE.to_string()))], None);
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+    let browser = TestBrowser::with_settings("page-cache", settings);
+
+    browser.send(r#"{"action": "load_url", "url": "https://example.com/"}"#);
+    let page = browser.shown_page().unwrap();
+    assert_eq!(proxy.requests().len(), 1);
+
+    // A reload from inside the frame goes back upstream.
+    let again = browser.context().serve_proxy_request(PageRequest::get(&page.view));
+    let again = String::from_utf8(again.body).unwrap();
+    assert_ne!(document_id(&again), page.document);
+    assert_eq!(again.replace(document_id(&again), &page.document), page.html);
+    assert_eq!(proxy.requests().len(), 2);
+}
+
+#[test]
+fn bad_requests_get_error_pages() {
+    let proxy = mock_proxy(
+        vec![("https://down.example/", Upstream::Failure("Connection timed out".to_string()))],
+        None,
+    );
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+    let browser = TestBrowser::with_settings("protocol-errors", settings);
+    let context = browser.context();
+
+    let resource = context.serve_proxy_request(PageRequest::get("cypher-proxy://down.example/"));
+    assert_eq!(resource.status, 502);
+    assert!(String::from_utf8(resource.body).unwrap().contains("Connection timed out"));
+
+    let put = PageRequest {
+        method: "PUT".to_string(),
+        ..PageRequest::get("cypher-proxy://down.example/")
+    };
+    assert_eq!(context.serve_proxy_request(put).status, 405);
+    assert_eq!(context.serve_proxy_request(PageRequest::get("https://down.example/")).status, 400);
+    assert_eq!(proxy.requests().len(), 1);
+}
+
+#[test]
+fn frame_script_goes_at_the_top_of_the_head() {
+    let injected = inject_frame_script("<!DOCTYPE html>\n<html lang=\"en\"><HEAD><title>x</title></HEAD></html>", "");
+    assert!(injected.starts_with("<!DOCTYPE html>\n<html lang=\"en\"><HEAD><script>"));
+    assert!(injected.ends_with("</script><title>x</title></HEAD></html>"));
+
+    let injected = inject_frame_script("<html><header>Not the head</header></html>", "");
+    assert!(injected.starts_with("<html><script>"));
+
+    let injected = inject_frame_script("<p>Just a fragment</p>", "");
+    assert!(injected.starts_with("<script>"));
+    assert!(injected.ends_with("</script><p>Just a fragment</p>"));
+}
+
+#[test]
+fn the_address_bar_follows_what_was_served_not_what_the_page_says() {
+    let proxy = mock_proxy(
+        vec![
+            ("https://example.com/", Upstream::Page(PAGE.to_string())),
+            ("https://example.com/next", Upstream::Page(PAGE.to_string())),
+        ],
+        None,
+    );
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+    let browser = TestBrowser::with_settings("frame-address", settings);
+    browser.send(r#"{"action": "load_url", "url": "https://example.com/"}"#);
+
+    // The frame went on to another page by itself.
+    let next = browser.context().serve_proxy_request(PageRequest::get("cypher-proxy://example.com/next"));
+    let next = String::from_utf8(next.body).unwrap();
+    browser.send(&json!({ "action": "frame_loaded", "document": document_id(&next) }).to_string());
+    let shown = browser.last("showFrameAddress").unwrap();
+    assert_eq!(shown.args[1], "https://example.com/next");
+
+    // A made-up id, or none at all, changes nothing.
+    browser.calls();
+    browser.send(r#"{"action": "frame_loaded", "document": "0123456789abcdef0123456789abcdef"}"#);
+    browser.send(r#"{"action": "frame_loaded", "url": "https://bank.example/"}"#);
+    assert!(browser.last("showFrameAddress").is_none());
+}
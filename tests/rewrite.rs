@@ -0,0 +1,109 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use cypher::fetch::PageRequest;
+use cypher::protocol::to_proxy_url;
+use cypher::rewrite::{rewrite_css, rewrite_html};
+use common::server::{mock_proxy, Upstream};
+use common::{offline_settings, TestBrowser};
+
+const PAGE_URL: &str = "https://example.com/dir/page.html";
+
+// Every `name.html` in tests/fixtures/rewrite is rewritten as if it had been
+// fetched from PAGE_URL and compared with `name.expected.html`.
+#[test]
+fn fixtures_are_rewritten_as_expected() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/rewrite");
+    let mut checked = 0;
+    let mut failures = Vec::new();
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        if name.ends_with(".expected.html") {
+            continue;
+        }
+        let input = fs::read_to_string(&path).unwrap();
+        let expected = fs::read_to_string(dir.join(name.replace(".html", ".expected.html")))
+            .unwrap_or_else(|_| panic!("{} has no expected output", name));
+        let output = rewrite_html(&input, PAGE_URL, &to_proxy_url);
+        if output != expected {
+            failures.push(format!("--- {}\n{}", name, output));
+        }
+        checked += 1;
+    }
+    assert!(checked >= 15, "only {} fixtures found", checked);
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn rewriting_twice_changes_nothing_more() {
+    let input = fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/rewrite/links.html")).unwrap();
+    let once = rewrite_html(&input, PAGE_URL, &to_proxy_url);
+    assert_eq!(rewrite_html(&once, PAGE_URL, &to_proxy_url), once);
+}
+
+#[test]
+fn urls_the_mapper_declines_are_left_alone() {
+    let html = r#"<a href="keep.html">Kept</a><img src="https://images.example.net/x.png">"#;
+    let output = rewrite_html(html, PAGE_URL, &|url: &str| {
+        url.contains("images.example.net").then(|| "https://proxy.test/x.png".to_string())
+    });
+    assert_eq!(output, r#"<a href="keep.html">Kept</a><img src="https://proxy.test/x.png">"#);
+}
+
+#[test]
+fn stylesheets_resolve_against_their_own_url() {
+    let css = "@import 'theme.css';\n.logo { background: url(../img/logo.svg) }\n";
+    let output = rewrite_css(css, "https://cdn.example.net/css/site.css", &to_proxy_url);
+    assert_eq!(
+        output,
+        "@import 'cypher-proxy://cdn.example.net/css/theme.css';\n\
+         .logo { background: url(cypher-proxy://cdn.example.net/img/logo.svg) }\n"
+    );
+}
+
+#[test]
+fn unterminated_css_is_passed_through() {
+    let css = ".a { background: url(broken.png; } .b { content: \"open";
+    assert_eq!(rewrite_css(css, PAGE_URL, &to_proxy_url), css);
+}
+
+#[test]
+fn proxied_pages_and_stylesheets_are_rewritten() {
+    let proxy = mock_proxy(
+        vec![
+            (
+                "https://example.com/",
+                Upstream::Page(r#"<html><head><link rel="stylesheet" href="https://cdn.example.net/site.css"></head><body><a href="/about">About</a></body></html>"#.to_string()),
+            ),
+            (
+                "https://cdn.example.net/site.css",
+                Upstream::Response {
+                    status: 200,
+                    final_url: "https://cdn.example.net/site.css".to_string(),
+                    content_type: "text/css".to_string(),
+                    headers: Vec::new(),
+                    body: b"body { background: url(bg.png) }".to_vec(),
+                },
+            ),
+        ],
+        None,
+    );
+    let mut settings = offline_settings();
+    settings.network.proxy_url = proxy.url("");
+    let browser = TestBrowser::with_settings("rewrite", settings);
+
+    browser.send(r#"{"action": "load_url", "url": "https://example.com/"}"#);
+    let page = browser.shown_page().unwrap();
+    assert!(page.html.contains(r#"<link rel="stylesheet" href="cypher-proxy://cdn.example.net/site.css">"#));
+    assert!(page.html.contains(r#"<a href="cypher-proxy://example.com/about">About</a>"#));
+
+    let stylesheet = browser.context().serve_proxy_request(PageRequest::get("cypher-proxy://cdn.example.net/site.css"));
+    assert_eq!(stylesheet.content_type, "text/css; charset=utf-8");
+    assert_eq!(stylesheet.body, b"body { background: url(cypher-proxy://cdn.example.net/bg.png) }");
+
+    // The index keeps the page as the site sent it.
+    let results = browser.context().index().search("about");
+    assert_eq!(results.len(), 1);
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use std::fs;
+use cypher::session::{Session, SessionWindow};
+use cypher::tabs::TabManager;
+use cypher::windows::WindowManager;
+use common::temp_dir;
+
+#[test]
+fn a_crashed_session_is_kept_until_the_user_answers() {
+    let dir = temp_dir("crashed-session");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("session.json");
+    let crashed = Session {
+        clean_exit: false,
+        windows: (0..2).map(|_| SessionWindow { tabs: TabManager::default(), bounds: None }).collect(),
+    };
+    crashed.save(&path).unwrap();
+    let saved = fs::read(&path).unwrap();
+
+    let (mut windows, recovered) = WindowManager::load(path.clone());
+    assert_eq!(recovered.unwrap().windows.len(), 2);
+    windows.open(TabManager::default(), None, false);
+    windows.save(false);
+    windows.save(true);
+    assert_eq!(fs::read(&path).unwrap(), saved);
+
+    // Once it has been restored or discarded, the running session is saved.
+    windows.release_recovered();
+    windows.save(true);
+    let (_, recovered) = WindowManager::load(path);
+    assert!(recovered.is_none());
+    fs::remove_dir_all(&dir).unwrap();
+}
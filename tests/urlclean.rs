@@ -0,0 +1,87 @@
+mod common;
+
+use cypher::settings::{CleaningRule, Fetcher, UrlCleaningSettings};
+use cypher::urlclean::{clean_url, UrlCleaner};
+use serde_json::json;
+use common::server::{search_server, Response, TestServer};
+use common::{offline_settings, TestBrowser};
+
+fn clean(url: &str) -> (String, usize) {
+    clean_url(&UrlCleaningSettings::default(), url)
+}
+
+#[test]
+fn tracking_parameters_are_removed_and_the_rest_kept_as_written() {
+    assert_eq!(
+        clean("https://news.example/story?id=7&utm_source=feed&utm_Medium=rss&q=a+b%20c&fbclid=IwAR0#comments"),
+        ("https://news.example/story?id=7&q=a+b%20c#comments".to_string(), 3)
+    );
+    assert_eq!(clean("https://news.example/?gclid=1&msclkid=2"), ("https://news.example/".to_string(), 2));
+    assert_eq!(clean("https://news.example/?utm=1&flag"), ("https://news.example/?utm=1&flag".to_string(), 0));
+    // Site rules only apply on their own sites.
+    assert_eq!(
+        clean("https://m.youtube.com/watch?v=abc&si=xyz"),
+        ("https://m.youtube.com/watch?v=abc".to_string(), 1)
+    );
+    assert_eq!(clean("https://video.example/watch?v=abc&si=xyz").1, 0);
+    assert_eq!(clean("cypher://history?utm_source=x").1, 0);
+    // Redirects through DuckDuckGo are replaced by where they lead.
+    assert_eq!(
+        clean("https://duckduckgo.com/l/?uddg=https%3A%2F%2Fshop.example%2F%3Fid%3D1%26utm_source%3Dddg&rut=abc"),
+        ("https://shop.example/?id=1".to_string(), 2)
+    );
+    assert_eq!(clean("https://duckduckgo.com/l/?uddg=javascript%3Aalert(1)").1, 0);
+}
+
+#[test]
+fn rules_patterns_and_exceptions_come_from_the_settings() {
+    let mut settings = UrlCleaningSettings {
+        enabled: true,
+        rules: vec![CleaningRule { sites: vec!["shop.example".to_string()], params: vec!["ref*id".to_string()] }],
+        exceptions: Vec::new(),
+    };
+    assert_eq!(
+        clean_url(&settings, "https://www.shop.example/item?refid=1&ref_campaign_id=2&ref=3&utm_source=x"),
+        ("https://www.shop.example/item?ref=3&utm_source=x".to_string(), 2)
+    );
+    assert_eq!(clean_url(&settings, "https://notshop.example/?refid=1").1, 0);
+
+    settings.exceptions = vec!["shop.example".to_string()];
+    assert_eq!(clean_url(&settings, "https://www.shop.example/item?refid=1").1, 0);
+    settings.exceptions.clear();
+    settings.enabled = false;
+    assert_eq!(clean_url(&settings, "https://www.shop.example/item?refid=1").1, 0);
+
+    let mut cleaner = UrlCleaner::default();
+    cleaner.clean(&UrlCleaningSettings::default(), "https://a.example/?utm_source=x&utm_campaign=y");
+    cleaner.clean(&UrlCleaningSettings::default(), "https://a.example/?gclid=1");
+    assert_eq!(cleaner.stripped(), 3);
+}
+
+#[test]
+fn navigation_and_search_results_use_clean_addresses() {
+    let site = TestServer::start(|_| Response::html("<html><head><title>Story</title></head></html>"));
+    let search = search_server(&[]);
+    let mut settings = offline_settings();
+    settings.network.fetcher = Fetcher::Direct;
+    settings.search.results_url = search.url("/html/");
+    let browser = TestBrowser::with_settings("urlclean", settings);
+
+    let tracked = format!("{}?id=1&utm_source=newsletter&fbclid=abc", site.url("/story"));
+    browser.send(&json!({ "action": "load_url", "url": tracked }).to_string());
+    assert_eq!(site.requests()[0].path, "/story?id=1");
+    let visits = serde_json::to_string(&browser.context().history().lock().unwrap().search("", 10)).unwrap();
+    assert!(visits.contains(&format!("{}?id=1\"", site.url("/story"))));
+    assert!(!visits.contains("utm_source"));
+    // Links opened in new tabs go the same way.
+    let tracked = format!("{}?gclid=1", site.url("/other"));
+    browser.send(&json!({ "action": "open_in_background", "url": tracked }).to_string());
+    assert_eq!(site.requests()[1].path, "/other");
+
+    browser.send(r#"{"action": "search", "url": "rust"}"#);
+    let results = browser.last("displayResults").unwrap();
+    assert_eq!(results.args[1][1]["url"], "https://doc.rust-lang.org/book/");
+
+    browser.send(r#"{"action": "load_url", "url": "cypher://settings"}"#);
+    assert_eq!(browser.last("showSettingsPage").unwrap().args[2], 4);
+}